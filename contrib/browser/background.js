@@ -0,0 +1,33 @@
+// Answers the requests wmfocus sends through its native host.
+const browser = globalThis.browser ?? globalThis.chrome;
+
+const port = browser.runtime.connectNative("wmfocus");
+
+async function handle(request) {
+  switch (request.command) {
+    case "list-tabs": {
+      const windows = await browser.windows.getAll({ populate: true, windowTypes: ["normal"] });
+      return {
+        windows: windows.map((window) => ({
+          focused: window.focused,
+          tabs: window.tabs.map((tab) => ({ id: tab.id, title: tab.title ?? "", active: tab.active })),
+        })),
+      };
+    }
+    case "activate-tab": {
+      const tab = await browser.tabs.update(request.tabId, { active: true });
+      await browser.windows.update(tab.windowId, { focused: true });
+      return { ok: true };
+    }
+    default:
+      return { error: `Unknown command ${request.command}` };
+  }
+}
+
+port.onMessage.addListener(async (request) => {
+  try {
+    port.postMessage(await handle(request));
+  } catch (error) {
+    port.postMessage({ error: String(error) });
+  }
+});
@@ -0,0 +1,26 @@
+/* C interface to the wmfocus hint engine, link with -lwmfocus. libwmfocus.so comes from
+ * cargo rustc --lib --release --features ffi --crate-type cdylib */
+
+#ifndef WMFOCUS_H
+#define WMFOCUS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+typedef struct {
+    int32_t x;
+    int32_t y;
+    int32_t w;
+    int32_t h;
+} WmfocusRect;
+
+/* Assign hints made of the characters in hint_chars to the count rectangles at rects.
+ *
+ * Returns count NUL terminated hints in the order of rects, to be freed with wmfocus_free_hints,
+ * or NULL if there are too few hint_chars or they aren't valid UTF-8. */
+char **wmfocus_assign_hints(const WmfocusRect *rects, size_t count, const char *hint_chars);
+
+/* Free hints returned by wmfocus_assign_hints called with the same count. */
+void wmfocus_free_hints(char **hints, size_t count);
+
+#endif
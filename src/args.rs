@@ -1,10 +1,22 @@
-use anyhow::{Context, Result};
-use clap::{Parser, ValueEnum};
-use css_color_parser::Color as CssColor;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::hints::HintStrategy;
+use crate::{color, DesktopWindow};
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use font_loader::system_fonts;
+use itertools::Itertools;
 use log::{info, warn};
+use regex::Regex;
 
+use crate::keys::Sequence;
 use crate::utils;
+use crate::ExitStatus;
 
 #[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum HorizontalAlign {
@@ -20,6 +32,95 @@ pub enum VerticalAlign {
     Bottom,
 }
 
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+    /// Hint windows and focus the selected one
+    Windows,
+    /// Hint workspaces and switch to the selected one
+    Workspaces,
+    /// Hint outputs and focus the selected one
+    Outputs,
+    /// Hint marks and focus the window carrying the selected one
+    Marks,
+    /// Hint the tabs of the focused tabbed or stacked container and focus the selected one
+    Tabs,
+    /// Hint windows and show the properties of the selected one
+    Inspect,
+    /// Show thumbnails of all windows side by side on the focused output and focus the selected
+    /// one
+    Expose,
+    /// Hint the cells of a grid on the focused output and click into the selected one
+    Grid,
+}
+
+impl Mode {
+    /// The mode after this one, wrapping around at the end.
+    pub fn next(self) -> Mode {
+        let modes = Mode::value_variants();
+        let index = modes.iter().position(|&mode| mode == self).unwrap_or(0);
+        modes[(index + 1) % modes.len()]
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Placement {
+    /// Inside the window, see --halign and --valign
+    Inside,
+    /// Just above the window
+    OutsideTop,
+    /// Just outside the corner of the window picked with --halign and --valign
+    OutsideCorner,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Focus the selected window
+    Focus,
+    /// Focus the selected floating window and let it follow the pointer until a click drops it
+    Place,
+    /// Keep picking windows until canceled and put them all into a new tabbed or stacked container
+    /// next to the first one, see --group-layout
+    Group,
+    /// Hint outputs and move the focused workspace to the selected one
+    MoveWorkspace,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GroupLayout {
+    Tabbed,
+    Stacked,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    /// Print the selection only with --print-only, in a format depending on the mode
+    Text,
+    /// Always print the selection as JSON with all known details
+    Json,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PrintId {
+    /// The X window id (eg 0x1a2b)
+    XId,
+    /// The i3/sway container id, usable in criteria like [con_id=...]
+    ConId,
+    /// The container id followed by the X window id
+    Both,
+    /// The position and size as X,Y WxH, like slurp prints it (works in every mode)
+    Geometry,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CopyValue {
+    /// The X window id (eg 0x1a2b)
+    XId,
+    /// The window title
+    Title,
+    /// The window class
+    Class,
+}
+
 /// Load a system font.
 fn load_font(font_family: &str) -> Result<Vec<u8>> {
     let mut font_family_property = system_fonts::FontPropertyBuilder::new()
@@ -52,15 +153,55 @@ fn parse_truetype_font(f: &str) -> Result<FontConfig> {
         v.next().context("Wrong font format")?,
     );
 
+    let now = Instant::now();
     let loaded_font = load_font(family).context("Couldn't load font")?;
     let font_config = FontConfig {
         font_family: family.to_string(),
         font_size: size.parse::<f64>().context("Couldn't parse font size")?,
         loaded_font,
+        load_time: now.elapsed(),
     };
     Ok(font_config)
 }
 
+/// Parse a positive number of (possibly fractional) seconds.
+fn parse_timeout(s: &str) -> Result<Duration, String> {
+    let seconds: f64 = s
+        .parse()
+        .map_err(|_| format!("'{s}' isn't a number of seconds"))?;
+    if !(seconds > 0.0 && seconds.is_finite()) {
+        return Err("The timeout has to be larger than 0".to_string());
+    }
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+/// Parse a fraction larger than 0 and at most 1.
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    let fraction: f64 = s.parse().map_err(|_| format!("'{s}' isn't a number"))?;
+    if !(fraction > 0.0 && fraction <= 1.0) {
+        return Err("The fraction has to be larger than 0 and at most 1".to_string());
+    }
+    Ok(fraction)
+}
+
+/// Parse a scaling factor of at least 1.
+fn parse_factor(s: &str) -> Result<f64, String> {
+    let factor: f64 = s.parse().map_err(|_| format!("'{s}' isn't a number"))?;
+    if !(factor >= 1.0 && factor.is_finite()) {
+        return Err("The factor has to be at least 1".to_string());
+    }
+    Ok(factor)
+}
+
+/// Parse a positive number of milliseconds.
+fn parse_millis(s: &str) -> Result<Duration, String> {
+    match s.parse() {
+        Ok(0) => Err("The interval has to be at least 1ms".to_string()),
+        Ok(millis) => Ok(Duration::from_millis(millis)),
+        Err(_) => Err(format!("'{s}' isn't a number of milliseconds")),
+    }
+}
+
 /// Validate coordinates and parse offset.
 fn parse_offset(c: &str) -> Result<Offset, String> {
     let mut v = c.split(',');
@@ -71,31 +212,94 @@ fn parse_offset(c: &str) -> Result<Offset, String> {
             .ok_or("Wrong coordinate format, expected x,y coordinates")?,
     );
     let offset = Offset {
-        x: x.parse::<i32>()
-            .map_err(|_| "Couldn't parse x coordinate")?,
-        y: y.parse::<i32>()
-            .map_err(|_| "Couldn't parse y coordinate")?,
+        x: parse_offset_value(x).ok_or("Couldn't parse x coordinate")?,
+        y: parse_offset_value(y).ok_or("Couldn't parse y coordinate")?,
     };
     Ok(offset)
 }
 
+/// Parse a single offset coordinate which is either in pixels (`10`) or in percent (`10%`).
+fn parse_offset_value(v: &str) -> Option<OffsetValue> {
+    match v.strip_suffix('%') {
+        Some(percent) => percent.parse().ok().map(OffsetValue::Percent),
+        None => v.parse().ok().map(OffsetValue::Pixels),
+    }
+}
+
+/// Parse an X window id in either hexadecimal (`0x1a2b`) or decimal notation.
+fn parse_window_id(s: &str) -> Result<u32, String> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse::<u32>(),
+    };
+    parsed.map_err(|_| format!("Couldn't parse window id '{s}'"))
+}
+
+/// A background color for hints of windows whose class matches `regex`.
+#[derive(Debug, Clone)]
+pub struct ClassColor {
+    pub regex: Regex,
+    pub color: (f64, f64, f64, f64),
+}
+
+/// Parse a rule like `Alacritty|kitty=#2e7d32` into a `ClassColor`.
+fn parse_class_color(s: &str) -> Result<ClassColor, String> {
+    let (regex, color) = s
+        .rsplit_once('=')
+        .ok_or_else(|| format!("Couldn't parse class color '{s}', expected REGEX=COLOR"))?;
+    Ok(ClassColor {
+        regex: Regex::new(regex).map_err(|e| format!("Invalid class regex: {e}"))?,
+        color: parse_color(color)?,
+    })
+}
+
+/// Check that a `--format` template only uses known placeholders.
+fn parse_format(s: &str) -> Result<String, String> {
+    utils::format_selection(s, &DesktopWindow::default())?;
+    Ok(s.to_string())
+}
+
+/// Parse a grid size like `4x3` into columns and rows.
+fn parse_grid_size(s: &str) -> Result<(u32, u32), String> {
+    s.split_once(['x', 'X'])
+        .and_then(|(cols, rows)| Some((cols.parse().ok()?, rows.parse().ok()?)))
+        .filter(|&(cols, rows)| cols > 0 && rows > 0)
+        .ok_or_else(|| format!("Couldn't parse grid size '{s}', expected something like 4x3"))
+}
+
 /// Parse a color into a tuple of floats.
 fn parse_color(color_str: &str) -> Result<(f64, f64, f64, f64), String> {
-    let color = color_str
-        .parse::<CssColor>()
-        .map_err(|_| "Invalid color format")?;
-    Ok((
-        f64::from(color.r) / 255.0,
-        f64::from(color.g) / 255.0,
-        f64::from(color.b) / 255.0,
-        f64::from(color.a),
-    ))
+    color::parse_css_color(color_str)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OffsetValue {
+    Pixels(i32),
+    Percent(f64),
+}
+
+impl OffsetValue {
+    /// Turn this into pixels, using `reference` as the size that percentages are relative to.
+    pub fn resolve(&self, reference: i32) -> i32 {
+        match *self {
+            OffsetValue::Pixels(pixels) => pixels,
+            OffsetValue::Percent(percent) => {
+                (f64::from(reference) * percent / 100.0).round() as i32
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Offset {
-    pub x: i32,
-    pub y: i32,
+    pub x: OffsetValue,
+    pub y: OffsetValue,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OffsetRelativeTo {
+    Window,
+    Monitor,
 }
 
 #[derive(Debug, Clone)]
@@ -103,15 +307,30 @@ pub struct FontConfig {
     pub font_family: String,
     pub font_size: f64,
     pub loaded_font: Vec<u8>,
+    pub load_time: Duration,
 }
 
-fn parse_exit_keys(s: &str) -> Result<utils::Sequence> {
-    Ok(utils::Sequence::new(Some(s)))
+fn parse_key_sequence(s: &str) -> Result<Sequence, String> {
+    s.parse()
 }
 
-#[derive(Parser, Debug)]
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Repeatedly enumerate windows and create, map and unmap their hints without grabbing any
+    /// input, then print how long that took
+    Bench {
+        /// How often to go through the whole cycle
+        #[arg(long, default_value = "20", value_parser = clap::value_parser!(u32).range(1..))]
+        iterations: u32,
+    },
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(name = "wmfocus", author, about, version)]
 pub struct AppConfig {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Use a specific TrueType font with this format: family:size
     #[arg(
         short,
@@ -121,10 +340,41 @@ pub struct AppConfig {
     )]
     pub font: FontConfig,
 
+    /// Shrink the font down to this size for hints that wouldn't fit their window otherwise
+    #[arg(long, default_value = "12", value_name = "SIZE")]
+    pub min_font_size: f64,
+
+    /// Scale every hint (and its font) up to a fraction of its window, see --huge-fraction
+    #[arg(long)]
+    pub huge: bool,
+
+    /// How much of the width or height of its window a hint takes up with --huge
+    #[arg(
+        long,
+        default_value = "0.25",
+        value_name = "FRACTION",
+        value_parser(parse_fraction)
+    )]
+    pub huge_fraction: f64,
+
+    /// Enlarge the hint under the pointer and, once typing narrowed them down to a few, the hints
+    /// still matching by this factor
+    #[arg(long, value_name = "FACTOR", value_parser(parse_factor))]
+    pub zoom: Option<f64>,
+
+    /// Cancel when clicking anywhere, which keeps the mouse from other windows while hints are
+    /// shown
+    #[arg(long)]
+    pub click_cancel: bool,
+
     /// Define a set of possbile values to use as hint characters
     #[arg(short = 'c', long = "chars", default_value = "sadfjklewcmpgh")]
     pub hint_chars: String,
 
+    /// How to make up hints from the hint characters
+    #[arg(long, default_value = "fixed", ignore_case = true)]
+    pub hint_strategy: HintStrategy,
+
     /// Add an additional margin around the text box (value is a factor of the box size)
     #[arg(short, long, default_value = "0.2")]
     pub margin: f32,
@@ -183,6 +433,15 @@ pub struct AppConfig {
     )]
     pub bg_color_current: (f64, f64, f64, f64),
 
+    /// Background color for windows whose class (or app_id) matches a regex, eg 'Alacritty|kitty=#2e7d32' (can be given multiple times, first match wins)
+    #[arg(
+        long,
+        display_order = 55,
+        value_name = "REGEX=COLOR",
+        value_parser(parse_class_color)
+    )]
+    pub class_color: Vec<ClassColor>,
+
     /// Horizontal alignment of the box inside the window
     #[arg(
         long = "halign",
@@ -201,6 +460,22 @@ pub struct AppConfig {
     )]
     pub vertical_align: VerticalAlign,
 
+    /// Where hints go relative to their window, outside placements keep the window content visible
+    /// (as far as the monitor allows)
+    #[arg(
+        long,
+        display_order = 102,
+        default_value = "inside",
+        ignore_case = true,
+        conflicts_with = "fill"
+    )]
+    pub placement: Placement,
+
+    /// Dim all other windows to this opacity while hints are shown (needs a compositor going by
+    /// _NET_WM_WINDOW_OPACITY, like picom)
+    #[arg(long, value_name = "OPACITY", value_parser(parse_fraction))]
+    pub dim: Option<f64>,
+
     /// Completely fill out windows
     #[arg(long, display_order = 102, conflicts_with_all(&["horizontal_align", "vertical_align", "margin", "offset"]))]
     pub fill: bool,
@@ -209,7 +484,104 @@ pub struct AppConfig {
     #[arg(short, long)]
     pub print_only: bool,
 
-    /// Offset box from edge of window relative to alignment (x,y)
+    /// What to print about the selected window, implies --print-only
+    #[arg(long, value_name = "WHAT", ignore_case = true)]
+    pub print: Option<PrintId>,
+
+    /// Print the selection using this template instead, implies --print-only.
+    /// Placeholders: {x_id} {con_id} {id} {class} {title} {workspace} {output} {label} {marks} {x} {y} {width} {height}
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all(&["print", "output_format"]), value_parser(parse_format))]
+    pub format: Option<String>,
+
+    /// Copy this about the selected window to the clipboard instead of focusing it (uses wl-copy,
+    /// xclip or xsel)
+    #[arg(long, value_name = "WHAT", ignore_case = true)]
+    pub copy: Option<CopyValue>,
+
+    /// Terminate the printed selection with a NUL byte instead of a newline (for xargs -0), implies --print-only
+    #[arg(long)]
+    pub print0: bool,
+
+    /// Print the hints that would be shown (hint, id, class, title and geometry) and exit, see
+    /// also --output
+    #[arg(long, conflicts_with_all(&["mode_key", "by_output"]))]
+    pub list: bool,
+
+    /// Draw the hints over a screenshot into this PNG file instead of showing them, to try out
+    /// themes and placement
+    #[arg(long, value_name = "PATH", conflicts_with_all(&["list", "mode_key", "by_output"]))]
+    pub render_to_png: Option<PathBuf>,
+
+    /// After focusing a browser window, hint its tabs and switch to the selected one (needs the
+    /// browser extension from contrib/browser)
+    #[arg(long)]
+    pub browser_tabs: bool,
+
+    /// After focusing a kitty window, hint the kitty windows in its tabs and focus the selected one
+    /// (needs remote control, see --kitty-socket)
+    #[arg(long)]
+    pub kitty_windows: bool,
+
+    /// The address kitty listens on for remote control, as set with its listen_on option
+    #[arg(long, default_value = "unix:/tmp/kitty", value_name = "ADDRESS")]
+    pub kitty_socket: String,
+
+    /// After focusing a window, hint its edges and split it so that the next window opens in the
+    /// direction picked with h, j, k or l (i3 opens it to the right or below either way)
+    #[arg(long)]
+    pub preselect: bool,
+
+    /// Relay between the browser extension and wmfocus, this is started by the browser
+    #[arg(long, hide = true)]
+    pub browser_native_host: bool,
+
+    /// Print how long the different phases of showing the hints took to stderr
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Stay around after a selection and show the hints again whenever SIGUSR1 arrives (eg
+    /// `pkill -USR1 -x wmfocus`), SIGHUP reloads the config file
+    #[arg(long, conflicts_with_all(&["list", "render_to_png", "stdin"]))]
+    pub persist: bool,
+
+    /// With --persist, also offer the org.wmfocus.Selector service on the D-Bus session bus,
+    /// whose Select method shows the hints and returns the (i3/sway container) id of the selected
+    /// window
+    #[arg(long, requires = "persist")]
+    pub dbus: bool,
+
+    /// Show the hints again right after acting on a selection until nothing is selected (eg with
+    /// Escape), to hop through several windows in a row
+    #[arg(long, conflicts_with_all(&["list", "render_to_png", "stdin"]))]
+    pub repeat: bool,
+
+    /// Write JSON events (hints-shown, key-typed, selection, canceled, no-match) to this already
+    /// open file descriptor, one per line
+    #[arg(long, value_name = "FD")]
+    pub event_fd: Option<i32>,
+
+    /// Write the state (idle or selecting, the action and what was typed) as a line of JSON for
+    /// waybar or i3status-rs to this file or FIFO whenever it changes
+    #[arg(long, value_name = "PATH")]
+    pub status_file: Option<PathBuf>,
+
+    /// Ring the bell for keys that aren't hint characters or don't match any hint
+    #[arg(long)]
+    pub bell: bool,
+
+    /// Flash the hints for keys that aren't hint characters or don't match any hint
+    #[arg(long)]
+    pub flash: bool,
+
+    /// Read out the hints and the selection with speech-dispatcher (spd-say)
+    #[arg(long)]
+    pub announce: bool,
+
+    /// How to print the selection
+    #[arg(long = "output", default_value = "text", ignore_case = true)]
+    pub output_format: OutputFormat,
+
+    /// Offset box from edge of window relative to alignment (x,y), either in pixels or in percent (eg 50%,10%)
     #[arg(
         short,
         long,
@@ -219,20 +591,1113 @@ pub struct AppConfig {
     )]
     pub offset: Offset,
 
+    /// What percentages in --offset are relative to
+    #[arg(long, default_value = "window", ignore_case = true)]
+    pub offset_relative_to: OffsetRelativeTo,
+
     /// List of keys to exit application, sequences separator is space, key separator is '+', eg Control_L+g Shift_L+f
-    #[arg(short, long, value_parser(parse_exit_keys))]
-    pub exit_keys: Vec<utils::Sequence>,
+    #[arg(short, long, value_parser(parse_key_sequence))]
+    pub exit_keys: Vec<Sequence>,
+
+    /// Print the window manager command that would be run for the selection instead of running it
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Switch i3/sway into this binding mode while hints are shown, so that key bindings don't
+    /// fire and the bar shows what's going on (the mode has to be defined in the config)
+    #[arg(long, value_name = "MODE")]
+    pub binding_mode: Option<String>,
 
     /// If this flag is set, the currently active window will swap with the selected window.
     #[arg(short, long)]
     pub swap: bool,
+
+    /// What to do with the selected window
+    #[arg(
+        long,
+        default_value = "focus",
+        ignore_case = true,
+        conflicts_with = "swap"
+    )]
+    pub action: Action,
+
+    /// The layout of the container windows are put into with --action group
+    #[arg(long, default_value = "tabbed", ignore_case = true)]
+    pub group_layout: GroupLayout,
+
+    /// In workspaces mode, also hint the workspaces named in the window manager's config (in key
+    /// bindings and output assignments) or --workspace-names that don't exist yet, selecting one
+    /// creates it
+    #[arg(long)]
+    pub empty_workspaces: bool,
+
+    /// More workspaces to hint with --empty-workspaces
+    #[arg(long, num_args(1..), value_name = "NAME")]
+    pub workspace_names: Vec<String>,
+
+    /// Don't show hints for windows carrying any of these marks
+    #[arg(long, num_args(1..), value_name = "MARK")]
+    pub exclude_marked: Vec<String>,
+
+    /// Only show hints for windows that demand attention (have the urgency flag set)
+    #[arg(long)]
+    pub urgent_only: bool,
+
+    /// Don't show hints for sticky windows
+    #[arg(long, conflicts_with = "sticky_only")]
+    pub exclude_sticky: bool,
+
+    /// Only show hints for sticky windows
+    #[arg(long)]
+    pub sticky_only: bool,
+
+    /// Only show hints for windows of the same application (class) as the focused window
+    #[arg(long)]
+    pub same_app: bool,
+
+    /// Also show hints for split, tabbed and stacked containers so that they can be focused
+    #[arg(long)]
+    pub containers: bool,
+
+    /// What to show hints for
+    #[arg(long, default_value = "windows", ignore_case = true)]
+    pub mode: Mode,
+
+    /// Read the rectangles to hint from stdin and print the id of the selected one.
+    /// Expects a JSON array of objects like {"id": 1, "x": 0, "y": 0, "w": 100, "h": 100, "label": "optional"}
+    #[arg(long, conflicts_with_all(&["mode", "swap", "containers"]))]
+    pub stdin: bool,
+
+    /// Only show hints for these X windows (comma separated, eg 0x1a2b,0x3c4d)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        value_name = "ID",
+        value_parser(parse_window_id)
+    )]
+    pub windows: Vec<u32>,
+
+    /// Only show hints for this many windows at once (largest first), the rest can be reached by paging
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_hints: Option<u32>,
+
+    /// Key to show the next page of hints when using --max-hints
+    #[arg(
+        long,
+        default_value = "space",
+        requires = "max_hints",
+        value_parser(parse_key_sequence)
+    )]
+    pub page_key: Sequence,
+
+    /// Skip windows that are completely covered by other windows and move hints onto the visible part of partially covered ones
+    #[arg(long)]
+    pub occlusion: bool,
+
+    /// Show hints for windows behind fullscreen windows or covered by floating ones as well
+    #[arg(long)]
+    pub include_covered: bool,
+
+    /// Number of columns and rows of the grid in grid mode
+    #[arg(
+        long,
+        default_value = "4x3",
+        value_name = "COLSxROWS",
+        value_parser(parse_grid_size)
+    )]
+    pub grid_size: (u32, u32),
+
+    /// How many times the selected grid cell is subdivided again before clicking
+    #[arg(long, default_value = "1", value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub grid_depth: u32,
+
+    /// Only move the mouse into the selected grid cell, don't click
+    #[arg(long)]
+    pub grid_no_click: bool,
+
+    /// Fail instead of falling back to a dmenu style list on stdout when hints can't be shown
+    #[arg(long)]
+    pub no_fallback: bool,
+
+    /// On multiple outputs, pick an output first and then only hint what's on it
+    #[arg(long, conflicts_with = "stdin")]
+    pub by_output: bool,
+
+    /// Cancel if nothing was selected after this many seconds
+    #[arg(long, value_name = "SECONDS", value_parser(parse_timeout))]
+    pub timeout: Option<Duration>,
+
+    /// Give up grabbing keyboard and mouse from other clients after this many seconds
+    #[arg(
+        long,
+        default_value = "1",
+        value_name = "SECONDS",
+        value_parser(parse_timeout)
+    )]
+    pub grab_timeout: Duration,
+
+    /// Only show the hints after this long, keys typed in the meantime already count (so hints
+    /// that are known by heart can be typed without the hints ever showing up)
+    #[arg(long, value_name = "MS", value_parser(parse_millis))]
+    pub draw_delay: Option<Duration>,
+
+    /// Wait this long before trying to grab keyboard and mouse again
+    #[arg(
+        long,
+        default_value = "1",
+        value_name = "MS",
+        value_parser(parse_millis)
+    )]
+    pub grab_retry_interval: Duration,
+
+    /// Focus whatever had the focus before again when nothing was selected
+    #[arg(long)]
+    pub restore_focus: bool,
+
+    /// Focus the window by its id, app_id and title or pid if focusing its container didn't work
+    #[arg(long)]
+    pub criteria_fallback: bool,
+
+    /// Ask for the focus with _NET_ACTIVE_WINDOW if focusing through the window manager didn't work
+    #[arg(long)]
+    pub ewmh_fallback: bool,
+
+    /// Click into the middle of the selected window if nothing else managed to focus it
+    #[arg(long)]
+    pub click_fallback: bool,
+
+    /// Key to exit without selecting anything (key separator is '+', eg Control_L+g)
+    #[arg(long, default_value = "Escape", value_parser(parse_key_sequence))]
+    pub cancel_key: Sequence,
+
+    /// Key to take back the last typed hint character
+    #[arg(long, default_value = "BackSpace", value_parser(parse_key_sequence))]
+    pub undo_key: Sequence,
+
+    /// Key to select the first hint matching what was typed so far
+    #[arg(long, default_value = "Return", value_parser(parse_key_sequence))]
+    pub confirm_key: Sequence,
+
+    /// Key to switch to showing hints for the next --mode
+    #[arg(long, conflicts_with = "stdin", value_parser(parse_key_sequence))]
+    pub mode_key: Option<Sequence>,
+
+    /// Read defaults for all other options from this TOML file [default: $XDG_CONFIG_HOME/wmfocus/config.toml]
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Check the config file for problems and exit
+    #[arg(long)]
+    pub check_config: bool,
+
+    /// Use the options of this profile from the config file (eg [profile.reading])
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Print a commented config file with all default values and exit
+    #[arg(long)]
+    pub dump_config: bool,
+}
+
+/// Get the value of `--{name}` from `args` or else from the matching `WMFOCUS_*` variable.
+///
+/// We need some options before the config file is loaded, so we can't let clap parse them.
+fn early_arg(args: &[OsString], name: &str) -> Option<OsString> {
+    let flag = format!("--{name}");
+    let prefix = format!("--{name}=");
+    args.iter()
+        .enumerate()
+        .find_map(|(i, arg)| {
+            if arg == flag.as_str() {
+                args.get(i + 1).cloned()
+            } else {
+                let value = arg.as_bytes().strip_prefix(prefix.as_bytes())?;
+                Some(OsStr::from_bytes(value).to_owned())
+            }
+        })
+        .or_else(|| env::var_os(format!("WMFOCUS_{}", name.to_uppercase())))
+}
+
+/// Find the config file to use, either from `--config`, `WMFOCUS_CONFIG` or from the XDG config
+/// directory.
+fn find_config_path(args: &[OsString]) -> Option<(PathBuf, bool)> {
+    if let Some(path) = early_arg(args, "config") {
+        return Some((PathBuf::from(path), true));
+    }
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some((config_dir.join("wmfocus").join("config.toml"), false))
+}
+
+/// Turn a TOML value into the string values clap would get from the command line.
+fn config_values(key: &str, value: &toml::Value) -> Result<Vec<String>> {
+    Ok(match value {
+        toml::Value::String(s) => vec![s.clone()],
+        toml::Value::Integer(i) => vec![i.to_string()],
+        toml::Value::Float(f) => vec![f.to_string()],
+        toml::Value::Boolean(b) => vec![b.to_string()],
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|value| match value {
+                toml::Value::Array(_) | toml::Value::Table(_) => {
+                    bail!("Option '{key}' can't contain nested arrays or tables")
+                }
+                value => Ok(config_values(key, value)?.remove(0)),
+            })
+            .collect::<Result<_>>()?,
+        toml::Value::Datetime(_) | toml::Value::Table(_) => {
+            bail!("Option '{key}' has an unsupported type")
+        }
+    })
+}
+
+/// Section of the config file holding the key bindings, eg `cancel = "Escape"` for `--cancel-key`.
+const KEYS_SECTION: &str = "keys";
+
+/// Section of the config file holding the profiles, eg `[profile.reading]` for `--profile reading`.
+const PROFILE_SECTION: &str = "profile";
+
+/// Backends that can have their own section in the config file.
+const BACKEND_SECTIONS: &[&str] = &["i3", "sway", "ewmh"];
+
+/// An option set in the config file.
+#[derive(Debug)]
+struct ConfigEntry<'a> {
+    /// Section the option was found in, if any.
+    section: Option<String>,
+    /// Key as written in the config file.
+    key: &'a str,
+    /// Long name of the command line option this sets.
+    option: String,
+    value: &'a toml::Value,
+}
+
+/// Which of the optional sections of the config file to use.
+#[derive(Debug, Clone, Copy)]
+enum ConfigSections<'a> {
+    /// All of them, for checking the config file.
+    All,
+    /// Only the ones for this backend and profile.
+    Only {
+        backend: &'a str,
+        profile: Option<&'a str>,
+    },
+}
+
+/// Read and parse the config file at `path`.
+fn read_config_file(path: &Path) -> Result<(String, toml::Table)> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Couldn't read config file {}", path.display()))?;
+    let table = content
+        .parse()
+        .with_context(|| format!("Couldn't parse config file {}", path.display()))?;
+    Ok((content, table))
+}
+
+/// Get the sub-table `name` of `table`, if there is one.
+fn config_section<'a>(table: &'a toml::Table, name: &str) -> Option<&'a toml::Table> {
+    match table.get(name) {
+        Some(toml::Value::Table(section)) => Some(section),
+        _ => None,
+    }
+}
+
+/// List the options set in `section` (called `name`) of the config file.
+fn section_entries<'a>(
+    section: Option<&'a toml::Table>,
+    name: &str,
+) -> impl Iterator<Item = ConfigEntry<'a>> {
+    let name = name.to_owned();
+    section
+        .into_iter()
+        .flatten()
+        .map(move |(key, value)| ConfigEntry {
+            section: Some(name.clone()),
+            key,
+            option: key.clone(),
+            value,
+        })
+}
+
+/// List the options set in `table`.
+///
+/// Keys are the long names of the command line options (eg `textcolor = "#ffffff"`). Key bindings
+/// go into the `[keys]` section without the `-key` suffix. Options in the section named after the
+/// backend (eg `[sway]`) come after top-level ones so that they take precedence, and options of
+/// the selected profile (eg `[profile.reading]`) come last.
+fn config_entries<'a>(
+    table: &'a toml::Table,
+    sections: ConfigSections,
+) -> Result<Vec<ConfigEntry<'a>>> {
+    let top_level = table
+        .iter()
+        .filter(|(key, value)| {
+            let is_section = BACKEND_SECTIONS.contains(&key.as_str())
+                || *key == KEYS_SECTION
+                || *key == PROFILE_SECTION;
+            !(is_section && value.is_table())
+        })
+        .map(|(key, value)| ConfigEntry {
+            section: None,
+            key,
+            option: key.clone(),
+            value,
+        });
+    let keys = section_entries(config_section(table, KEYS_SECTION), KEYS_SECTION).map(|entry| {
+        ConfigEntry {
+            option: format!("{}-key", entry.key),
+            ..entry
+        }
+    });
+    let mut entries: Vec<_> = top_level.chain(keys).collect();
+
+    for &name in BACKEND_SECTIONS {
+        if let ConfigSections::Only { backend, .. } = sections {
+            if backend != name {
+                continue;
+            }
+        }
+        entries.extend(section_entries(config_section(table, name), name));
+    }
+
+    let profiles = config_section(table, PROFILE_SECTION);
+    match sections {
+        ConfigSections::All => {
+            for (name, profile) in profiles.into_iter().flatten() {
+                let section = format!("{PROFILE_SECTION}.{name}");
+                entries.extend(section_entries(profile.as_table(), &section));
+            }
+        }
+        ConfigSections::Only {
+            profile: Some(name),
+            ..
+        } => {
+            let profile = profiles
+                .and_then(|profiles| config_section(profiles, name))
+                .with_context(|| format!("Unknown profile '{name}'"))?;
+            let section = format!("{PROFILE_SECTION}.{name}");
+            entries.extend(section_entries(Some(profile), &section));
+        }
+        ConfigSections::Only { profile: None, .. } => {}
+    }
+
+    Ok(entries)
+}
+
+/// Find the argument of `command` that `option` (a long name) refers to.
+fn find_config_arg<'a>(command: &'a clap::Command, option: &str) -> Option<&'a clap::Arg> {
+    command
+        .get_arguments()
+        .find(|arg| arg.get_long() == Some(option) || arg.get_id() == option)
+        .filter(|arg| !["config", "profile", "check_config"].contains(&arg.get_id().as_str()))
+}
+
+/// Load the config file at `path` and make its values the defaults of `command`.
+fn apply_config_file(
+    mut command: clap::Command,
+    path: &Path,
+    sections: ConfigSections,
+) -> Result<clap::Command> {
+    let (_, table) = read_config_file(path)?;
+    let entries = config_entries(&table, sections)
+        .with_context(|| format!("Couldn't load config file {}", path.display()))?;
+    for entry in entries {
+        let id = find_config_arg(&command, &entry.option)
+            .map(|arg| arg.get_id().clone())
+            .with_context(|| format!("Unknown option '{}' in {}", entry.key, path.display()))?;
+        let values = config_values(entry.key, entry.value)?;
+        command = command.mut_arg(id, |arg| arg.default_values(values));
+    }
+    Ok(command)
+}
+
+/// Render a commented config file with the defaults of all options of `command`.
+fn default_config(command: &clap::Command) -> String {
+    let mut top_level = String::new();
+    let mut keys = String::new();
+    for arg in command.get_arguments() {
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        if arg.is_hide_set() {
+            continue;
+        }
+        let skipped = [
+            "help",
+            "version",
+            "config",
+            "profile",
+            "check-config",
+            "dump-config",
+        ];
+        if skipped.contains(&long) {
+            continue;
+        }
+        let (out, key) = match long.strip_suffix("-key") {
+            Some(key) => (&mut keys, key),
+            None => (&mut top_level, long),
+        };
+        if let Some(help) = arg.get_help() {
+            for line in help.to_string().lines() {
+                out.push_str(&format!("# {line}\n"));
+            }
+        }
+        let defaults: Vec<_> = arg
+            .get_default_values()
+            .iter()
+            .map(|value| value.to_string_lossy().into_owned())
+            .collect();
+        let value = match (arg.get_action(), defaults.as_slice()) {
+            (clap::ArgAction::SetTrue, _) => "false".to_owned(),
+            (_, [value]) if value.parse::<f64>().is_ok() => value.clone(),
+            (_, [value]) => toml::Value::String(value.clone()).to_string(),
+            (clap::ArgAction::Append, _) => "[]".to_owned(),
+            _ => {
+                let value_name = arg.get_value_names().and_then(|names| names.first());
+                format!(
+                    "<{}>",
+                    value_name.map(|name| name.as_str()).unwrap_or("VALUE")
+                )
+            }
+        };
+        out.push_str(&format!("#{key} = {value}\n\n"));
+    }
+    format!(
+        "# wmfocus configuration\n\
+         #\n\
+         # Options given on the command line or via WMFOCUS_* environment variables take precedence.\n\n\
+         {top_level}\
+         [keys]\n\n\
+         {keys}\
+         # Options in these sections are only used with the respective window manager.\n\
+         # [i3]\n\
+         # [sway]\n\n\
+         # Options in these sections are only used with --profile NAME.\n\
+         # [profile.NAME]\n"
+    )
+}
+
+/// Find the line number of `key` within `section` of the config file `content`.
+fn config_line(content: &str, section: Option<&str>, key: &str) -> Option<usize> {
+    let mut current_section = None;
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current_section = Some(name.trim());
+        } else if current_section == section {
+            let line_key = line.split('=').next().unwrap_or_default().trim();
+            if line_key.trim_matches('"') == key {
+                return Some(number + 1);
+            }
+        }
+    }
+    None
+}
+
+/// Check every value in the config file at `path`.
+///
+/// Returns a list of problems, each prefixed with its location in the file.
+fn check_config_file(command: &clap::Command, path: &Path) -> Result<Vec<String>> {
+    let (content, table) = read_config_file(path)?;
+    let mut problems = vec![];
+    for entry in config_entries(&table, ConfigSections::All)? {
+        let location = match config_line(&content, entry.section.as_deref(), entry.key) {
+            Some(line) => format!("{}:{line}", path.display()),
+            None => path.display().to_string(),
+        };
+        let Some(arg) = find_config_arg(command, &entry.option) else {
+            problems.push(format!("{location}: Unknown option '{}'", entry.key));
+            continue;
+        };
+        let values = match config_values(entry.key, entry.value) {
+            Ok(values) => values,
+            Err(e) => {
+                problems.push(format!("{location}: {e}"));
+                continue;
+            }
+        };
+        // Clap validates default values when parsing, so that's how we run the value parser.
+        for value in &values {
+            let long = arg.get_long().unwrap_or(entry.key).to_owned();
+            let probe = clap::Arg::new(long.clone())
+                .long(long)
+                .action(arg.get_action().clone())
+                .value_parser(arg.get_value_parser().clone())
+                .default_value(value.clone());
+            if let Err(e) = clap::Command::new("wmfocus")
+                .arg(probe)
+                .try_get_matches_from(["wmfocus"])
+            {
+                let message = e.to_string();
+                let message = message.trim_start_matches("error: ").lines().next();
+                problems.push(format!("{location}: {}", message.unwrap_or_default()));
+            }
+        }
+        match (arg.get_id().as_str(), values.first()) {
+            ("font", Some(font)) => {
+                let family = font.split(':').next().unwrap_or_default();
+                let mut property = system_fonts::FontPropertyBuilder::new()
+                    .family(family)
+                    .build();
+                if system_fonts::query_specific(&mut property).is_empty() {
+                    problems.push(format!("{location}: Font family '{family}' not found"));
+                }
+            }
+            ("hint_chars", Some(chars)) => {
+                if let Some(c) = chars.chars().duplicates().next() {
+                    problems.push(format!("{location}: Hint character '{c}' is used twice"));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(problems)
+}
+
+/// Add a hidden `--no-*` counterpart to every flag, which turns it back off if the config file or
+/// an environment variable turned it on. Whichever of the two is given last wins.
+fn with_negations(command: clap::Command) -> clap::Command {
+    let flags: Vec<_> = command
+        .get_arguments()
+        .filter(|arg| matches!(arg.get_action(), clap::ArgAction::SetTrue))
+        .filter_map(|arg| Some((arg.get_id().clone(), arg.get_long()?.to_owned())))
+        .filter(|(_, long)| long != "check-config" && long != "dump-config")
+        .collect();
+    flags.into_iter().fold(command, |command, (id, long)| {
+        // Named after its long name, so it can't clash with the ids clap derives from fields.
+        let negation = format!("no-{long}");
+        command
+            .mut_arg(&id, |arg| arg.overrides_with(negation.clone()))
+            .arg(
+                clap::Arg::new(negation.clone())
+                    .long(negation)
+                    .action(clap::ArgAction::SetTrue)
+                    .overrides_with(id)
+                    .hide(true),
+            )
+    })
+}
+
+/// Parse `args` with `command`, making flags turned off by their `--no-*` counterpart (see
+/// `with_negations`) false regardless of the config file and environment variables.
+fn get_matches(command: clap::Command, args: &[OsString]) -> clap::error::Result<clap::ArgMatches> {
+    let matches = command.clone().try_get_matches_from(args)?;
+    let negated: Vec<_> = command
+        .get_arguments()
+        .filter_map(|arg| {
+            let long = arg.get_long()?;
+            let flag = long.strip_prefix("no-")?;
+            // Only our own negations are named after their long name.
+            (arg.get_id() == long && matches.get_flag(long)).then(|| flag.to_owned())
+        })
+        .collect();
+    if negated.is_empty() {
+        return Ok(matches);
+    }
+    let command = negated.iter().fold(command, |command, flag| {
+        let id = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(flag))
+            .map(|arg| arg.get_id().clone())
+            .expect("negations are only added for existing flags");
+        command.mut_arg(id, |arg| arg.default_value("false").env(None::<&str>))
+    });
+    command.try_get_matches_from(args)
+}
+
+/// Allow setting every option via an environment variable named after its long name, eg
+/// `WMFOCUS_TEXTCOLOR` for `--textcolor`.
+///
+/// Flags can be set with anything that reads like a boolean, eg `WMFOCUS_HUGE=1`. `--check-config`
+/// and `--dump-config` are left out, they're looked at before clap gets to see anything.
+fn with_env_vars(command: clap::Command) -> clap::Command {
+    let skipped = ["help", "version", "check-config", "dump-config"];
+    let options: Vec<_> = command
+        .get_arguments()
+        .filter_map(|arg| Some((arg.get_id().clone(), arg.get_long()?.to_owned())))
+        .filter(|(_, long)| !skipped.contains(&long.as_str()))
+        .collect();
+    options.into_iter().fold(command, |command, (id, long)| {
+        let name = format!("WMFOCUS_{}", long.to_uppercase().replace('-', "_"));
+        command.mut_arg(id, |arg| {
+            let arg = arg.env(name);
+            if matches!(arg.get_action(), clap::ArgAction::SetTrue) {
+                arg.value_parser(clap::builder::BoolishValueParser::new())
+            } else {
+                arg
+            }
+        })
+    })
+}
+
+/// What the arguments given to wmfocus ask for.
+#[derive(Debug)]
+pub enum Parsed {
+    /// Show hints the way the config says.
+    Config(Box<AppConfig>),
+    /// Everything asked for (eg `--help` or `--dump-config`) was printed already, so there's
+    /// nothing left to do but exit with this status. Success is `ExitStatus::Selected`.
+    Exit(ExitStatus),
+}
+
+/// Parse the arguments wmfocus was started with, along with the config file and environment
+/// variables.
+///
+/// `backend` picks the section of the config file for the window manager (see `Backend::name`).
+/// Nothing exits here, that's up to the caller when it gets `Parsed::Exit`.
+pub fn parse_args(backend: &str) -> Result<Parsed> {
+    // Arguments clap expects to be text complain about not being text themselves.
+    let args: Vec<OsString> = env::args_os().collect();
+    let command = with_negations(with_env_vars(AppConfig::command()));
+    if args.iter().any(|arg| arg == "--check-config") {
+        let Some((path, _)) = find_config_path(&args) else {
+            bail!("Couldn't figure out where the config file is");
+        };
+        let problems = check_config_file(&command, &path)?;
+        for problem in &problems {
+            eprintln!("{problem}");
+        }
+        if !problems.is_empty() {
+            return Ok(Parsed::Exit(ExitStatus::Error));
+        }
+        println!("{} is valid", path.display());
+        return Ok(Parsed::Exit(ExitStatus::Selected));
+    }
+    if args.iter().any(|arg| arg == "--dump-config") {
+        print!("{}", default_config(&command));
+        return Ok(Parsed::Exit(ExitStatus::Selected));
+    }
+    let command = load_config_file(command, backend, &args)?;
+    let parsed =
+        get_matches(command, &args).and_then(|matches| AppConfig::from_arg_matches(&matches));
+    match parsed {
+        Ok(config) => Ok(Parsed::Config(Box::new(config))),
+        Err(e) => {
+            // Usage errors get our own exit code, --help and --version still exit successfully.
+            let _ = e.print();
+            Ok(Parsed::Exit(if e.use_stderr() {
+                ExitStatus::Error
+            } else {
+                ExitStatus::Selected
+            }))
+        }
+    }
+}
+
+/// Parse the arguments again along with the config file, which might have changed since.
+///
+/// Unlike `parse_args`, this doesn't print anything for `--help` and the like, they're errors. A
+/// persistent wmfocus should rather keep going with what it had.
+pub fn reparse_args(backend: &str) -> Result<AppConfig> {
+    let args: Vec<OsString> = env::args_os().collect();
+    let command = with_negations(with_env_vars(AppConfig::command()));
+    let command = load_config_file(command, backend, &args)?;
+    let matches = get_matches(command, &args)?;
+    Ok(AppConfig::from_arg_matches(&matches)?)
+}
+
+/// Make the values in the config file that `args` point to (if there is one) the defaults of
+/// `command`.
+fn load_config_file(
+    command: clap::Command,
+    backend: &str,
+    args: &[OsString],
+) -> Result<clap::Command> {
+    let profile = early_arg(args, "profile")
+        .map(|profile| {
+            profile
+                .into_string()
+                .map_err(|profile| anyhow::anyhow!("Invalid profile name {profile:?}"))
+        })
+        .transpose()?;
+    match find_config_path(args) {
+        Some((path, explicit)) if explicit || path.exists() => {
+            info!("Loading config file {}", path.display());
+            let sections = ConfigSections::Only {
+                backend,
+                profile: profile.as_deref(),
+            };
+            apply_config_file(command, &path, sections)
+        }
+        _ if profile.is_some() => bail!("Can't use a profile without a config file"),
+        _ => Ok(command),
+    }
 }
 
-pub fn parse_args() -> AppConfig {
-    let mut config = AppConfig::parse();
-    if config.fill {
-        config.horizontal_align = HorizontalAlign::Center;
-        config.vertical_align = VerticalAlign::Center;
+impl AppConfig {
+    /// Whether the selection should only be printed instead of acted upon.
+    pub fn prints_selection(&self) -> bool {
+        self.stdin
+            || self.print_only
+            || self.print0
+            || self.print.is_some()
+            || self.format.is_some()
+    }
+
+    /// What the selection is for, as reported to status bars.
+    pub fn action_name(&self) -> String {
+        if self.swap {
+            return "swap".to_string();
+        }
+        self.action
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    /// A copy of this config for showing hints in `mode`.
+    pub fn with_mode(&self, mode: Mode) -> AppConfig {
+        let mut config = self.clone();
+        config.mode = mode;
+        // There's nothing but outputs to move a workspace to.
+        if config.action == Action::MoveWorkspace {
+            config.mode = Mode::Outputs;
+        }
+        // Outputs are always completely filled out to make them easy to see.
+        if config.mode == Mode::Outputs {
+            config.fill = true;
+        }
+        // Grid cells and thumbnails are labeled right in their center.
+        if matches!(config.mode, Mode::Grid | Mode::Expose) {
+            config.horizontal_align = HorizontalAlign::Center;
+            config.vertical_align = VerticalAlign::Center;
+        }
+        if config.fill {
+            config.horizontal_align = HorizontalAlign::Center;
+            config.vertical_align = VerticalAlign::Center;
+        }
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_offset() {
+        let offset = parse_offset("10,-20").unwrap();
+        assert_eq!(offset.x, OffsetValue::Pixels(10));
+        assert_eq!(offset.y, OffsetValue::Pixels(-20));
+
+        let offset = parse_offset("50%,12.5%").unwrap();
+        assert_eq!(offset.x, OffsetValue::Percent(50.0));
+        assert_eq!(offset.y, OffsetValue::Percent(12.5));
+        assert_eq!(offset.x.resolve(300), 150);
+        assert_eq!(offset.y.resolve(800), 100);
+
+        assert!(parse_offset("10").is_err());
+        assert!(parse_offset("a,b").is_err());
+        assert!(parse_offset("1.5,2").is_err());
+    }
+
+    #[test]
+    fn test_parse_window_id() {
+        assert_eq!(parse_window_id("0x1a2b"), Ok(0x1a2b));
+        assert_eq!(parse_window_id("0X1A2B"), Ok(0x1a2b));
+        assert_eq!(parse_window_id("6699"), Ok(6699));
+        assert!(parse_window_id("0xzz").is_err());
+        assert!(parse_window_id("").is_err());
+    }
+
+    #[test]
+    fn test_apply_config_file() {
+        let path = env::temp_dir().join(format!("wmfocus-test-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            r##"
+chars = "asdf"
+textcolor = "#ffffff"
+max-hints = 5
+exclude_marked = ["a", "b"]
+fill = true
+
+[sway]
+chars = "qwer"
+
+[i3]
+margin = 0.5
+
+[keys]
+cancel = "Control_L+g"
+
+[profile.reading]
+textcolor = "#000000"
+
+[profile.present]
+font = "Mono:200"
+"##,
+        )
+        .unwrap();
+        let sections = ConfigSections::Only {
+            backend: "sway",
+            profile: Some("reading"),
+        };
+        let command = apply_config_file(AppConfig::command(), &path, sections).unwrap();
+
+        let defaults = |id: &str| -> Vec<String> {
+            command
+                .get_arguments()
+                .find(|arg| arg.get_id() == id)
+                .unwrap()
+                .get_default_values()
+                .iter()
+                .map(|value| value.to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(defaults("hint_chars"), vec!["qwer"]);
+        assert_eq!(defaults("text_color"), vec!["#000000"]);
+        assert_eq!(defaults("max_hints"), vec!["5"]);
+        assert_eq!(defaults("exclude_marked"), vec!["a", "b"]);
+        assert_eq!(defaults("fill"), vec!["true"]);
+        assert_eq!(defaults("margin"), vec!["0.2"]);
+        assert_eq!(defaults("cancel_key"), vec!["Control_L+g"]);
+        assert_eq!(defaults("font"), vec!["Mono:72"]);
+
+        let sections = ConfigSections::Only {
+            backend: "i3",
+            profile: Some("nonexistent"),
+        };
+        assert!(apply_config_file(AppConfig::command(), &path, sections).is_err());
+
+        fs::write(&path, "nonsense = 1\n").unwrap();
+        let sections = ConfigSections::Only {
+            backend: "i3",
+            profile: None,
+        };
+        assert!(apply_config_file(AppConfig::command(), &path, sections).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_with_env_vars() {
+        let command = with_env_vars(AppConfig::command());
+        let env = |id: &str| {
+            command
+                .get_arguments()
+                .find(|arg| arg.get_id() == id)
+                .unwrap()
+                .get_env()
+                .map(|env| env.to_string_lossy().into_owned())
+        };
+        assert_eq!(env("text_color").as_deref(), Some("WMFOCUS_TEXTCOLOR"));
+        assert_eq!(env("hint_chars").as_deref(), Some("WMFOCUS_CHARS"));
+        assert_eq!(env("max_hints").as_deref(), Some("WMFOCUS_MAX_HINTS"));
+        assert_eq!(env("check_config"), None);
+        assert_eq!(env("dump_config"), None);
+
+        // Flags take more than just true and false. Default values go through the same value
+        // parser as environment variables, without having to change the environment.
+        let huge = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == "huge")
+            .unwrap()
+            .clone();
+        let parse = |value: &str| {
+            let command =
+                clap::Command::new("wmfocus").arg(huge.clone().default_value(value.to_owned()));
+            command
+                .try_get_matches_from(["wmfocus"])
+                .map(|matches| matches.get_flag("huge"))
+        };
+        for value in ["1", "yes", "on", "true"] {
+            assert!(parse(value).unwrap());
+        }
+        for value in ["0", "no", "off", "false"] {
+            assert!(!parse(value).unwrap());
+        }
+        assert!(parse("maybe").is_err());
+    }
+
+    #[test]
+    fn test_early_arg() {
+        let args: Vec<OsString> = ["wmfocus", "--profile", "reading", "--config=/tmp/c.toml"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        assert_eq!(early_arg(&args, "profile"), Some("reading".into()));
+        assert_eq!(early_arg(&args, "config"), Some("/tmp/c.toml".into()));
+
+        // Paths don't have to be text.
+        let path = OsStr::from_bytes(b"/tmp/\xff.toml");
+        let mut arg = OsString::from("--config=");
+        arg.push(path);
+        let args = [OsString::from("wmfocus"), arg];
+        assert_eq!(early_arg(&args, "config").as_deref(), Some(path));
+        let (found, explicit) = find_config_path(&args).unwrap();
+        assert_eq!(found.as_os_str(), path);
+        assert!(explicit);
+    }
+
+    #[test]
+    fn test_negations() {
+        // AppConfig would load fonts.
+        let flag = |name: &'static str| {
+            clap::Arg::new(name)
+                .long(name)
+                .action(clap::ArgAction::SetTrue)
+                .default_value("true")
+        };
+        let command = with_negations(
+            clap::Command::new("wmfocus")
+                .arg(flag("huge"))
+                .arg(flag("fill"))
+                .arg(flag("check-config"))
+                .arg(clap::Arg::new("chars").long("chars")),
+        );
+        let parse = |args: &[&str]| {
+            let args: Vec<OsString> = ["wmfocus"].iter().chain(args).map(OsString::from).collect();
+            get_matches(command.clone(), &args).unwrap()
+        };
+        let matches = parse(&[]);
+        assert!(matches.get_flag("huge"));
+        assert!(matches.get_flag("fill"));
+        let matches = parse(&["--no-huge"]);
+        assert!(!matches.get_flag("huge"));
+        assert!(matches.get_flag("fill"));
+        // The last one wins.
+        assert!(parse(&["--no-huge", "--huge"]).get_flag("huge"));
+        assert!(!parse(&["--huge", "--no-huge"]).get_flag("huge"));
+        // Options that only make sense on the command line have no negation.
+        assert!(command
+            .get_arguments()
+            .all(|arg| arg.get_long() != Some("no-check-config")));
+
+        // Arguments that aren't text are errors rather than panics.
+        let args = [
+            OsString::from("wmfocus"),
+            OsString::from("--chars"),
+            OsStr::from_bytes(b"\xff").to_owned(),
+        ];
+        assert!(get_matches(command, &args).is_err());
+    }
+
+    #[test]
+    fn test_check_config_file() {
+        let path = env::temp_dir().join(format!("wmfocus-check-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            r#"chars = "asdfa"
+bgcolor = "notacolor"
+
+[sway]
+nonsense = 1
+halign = "middle"
+
+[profile.reading]
+margin = "wide"
+"#,
+        )
+        .unwrap();
+        let problems = check_config_file(&AppConfig::command(), &path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let location = path.display().to_string();
+        assert_eq!(problems.len(), 5, "{problems:?}");
+        assert!(problems[0].starts_with(&format!("{location}:2: ")));
+        assert!(problems[1].starts_with(&format!("{location}:1: Hint character 'a'")));
+        assert!(problems[2].starts_with(&format!("{location}:6: ")));
+        assert!(problems[3].starts_with(&format!("{location}:5: Unknown option 'nonsense'")));
+        assert!(problems[4].starts_with(&format!("{location}:9: ")));
+    }
+
+    #[test]
+    fn test_default_config() {
+        let config = default_config(&AppConfig::command());
+        assert!(config.contains("\n#chars = \"sadfjklewcmpgh\"\n"));
+        assert!(config.contains("\n#margin = 0.2\n"));
+        assert!(config.contains("\n#fill = false\n"));
+        assert!(config.contains("\n#exclude-marked = []\n"));
+        assert!(config.contains("\n#max-hints = <N>\n"));
+        assert!(!config.contains("dump-config"));
+
+        // Uncommenting all options with defaults has to result in a valid config.
+        let uncommented: String = config
+            .lines()
+            .map(|line| match line.strip_prefix('#') {
+                Some(option) if option.contains(" = ") && !option.contains('<') => option,
+                _ => line,
+            })
+            .map(|line| format!("{line}\n"))
+            .collect();
+        let table: toml::Table = uncommented.parse().unwrap();
+        let command = AppConfig::command();
+        for entry in config_entries(&table, ConfigSections::All).unwrap() {
+            assert!(
+                find_config_arg(&command, &entry.option).is_some(),
+                "{entry:?}"
+            );
+        }
+        assert_eq!(
+            table[KEYS_SECTION]["cancel"],
+            toml::Value::String("Escape".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_parse_class_color() {
+        let rule = parse_class_color("^(Alacritty|kitty)$=#00ff00").unwrap();
+        assert!(rule.regex.is_match("kitty"));
+        assert!(!rule.regex.is_match("firefox"));
+        assert_eq!(rule.color, (0.0, 1.0, 0.0, 1.0));
+
+        let rule = parse_class_color("a=b=rgba(0, 0, 255, 0.5)").unwrap();
+        assert!(rule.regex.is_match("a=b"));
+        assert_eq!(rule.color, (0.0, 0.0, 1.0, 0.5));
+
+        assert!(parse_class_color("kitty").is_err());
+        assert!(parse_class_color("(=#00ff00").is_err());
+        assert!(parse_class_color("kitty=nocolor").is_err());
+    }
+
+    #[test]
+    fn test_mode_next() {
+        assert_eq!(Mode::Windows.next(), Mode::Workspaces);
+        assert_eq!(Mode::Grid.next(), Mode::Windows);
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        assert_eq!(parse_timeout("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_timeout("0.5"), Ok(Duration::from_millis(500)));
+        assert!(parse_timeout("0").is_err());
+        assert!(parse_timeout("-1").is_err());
+        assert!(parse_timeout("soon").is_err());
+    }
+
+    #[test]
+    fn test_parse_factor() {
+        assert_eq!(parse_factor("1.5"), Ok(1.5));
+        assert_eq!(parse_factor("1"), Ok(1.0));
+        assert!(parse_factor("0.5").is_err());
+        assert!(parse_factor("inf").is_err());
+        assert!(parse_factor("x").is_err());
+    }
+
+    #[test]
+    fn test_parse_fraction() {
+        assert_eq!(parse_fraction("0.25"), Ok(0.25));
+        assert_eq!(parse_fraction("1"), Ok(1.0));
+        assert!(parse_fraction("0").is_err());
+        assert!(parse_fraction("1.5").is_err());
+        assert!(parse_fraction("NaN").is_err());
+    }
+
+    #[test]
+    fn test_parse_millis() {
+        assert_eq!(parse_millis("20"), Ok(Duration::from_millis(20)));
+        assert!(parse_millis("0").is_err());
+        assert!(parse_millis("0.5").is_err());
+    }
+
+    #[test]
+    fn test_parse_grid_size() {
+        assert_eq!(parse_grid_size("4x3"), Ok((4, 3)));
+        assert_eq!(parse_grid_size("10X2"), Ok((10, 2)));
+        assert!(parse_grid_size("4").is_err());
+        assert!(parse_grid_size("0x3").is_err());
+        assert!(parse_grid_size("ax3").is_err());
     }
-    config
 }
@@ -0,0 +1,70 @@
+//! The window manager side of showing hints: what there is to hint and how to focus it.
+
+use anyhow::Result;
+
+use crate::{DesktopOutput, DesktopWindow};
+
+/// A window manager that windows can be hinted and focused with.
+///
+/// The `wmfocus` binary implements this for i3 and sway. Other tools can implement it for whatever
+/// they run on and hand it to `overlay::Renderer::focus_window`.
+pub trait Backend {
+    /// Name of the window manager, used to pick its section in the config file.
+    fn name(&self) -> &str;
+
+    /// The windows to show hints for, along with their containers (eg tabbed ones) if
+    /// `include_containers` is set.
+    fn windows(&self, include_containers: bool) -> Result<Vec<DesktopWindow>>;
+
+    /// The outputs as the window manager sees them, for correcting the geometry of `windows` on
+    /// transformed or scaled outputs (see `geometry::apply_output_geometry`).
+    ///
+    /// Window managers that don't transform or scale outputs themselves can leave this empty.
+    fn outputs(&self) -> Result<Vec<DesktopOutput>> {
+        Ok(vec![])
+    }
+
+    /// Give the focus to `window`, one of those `windows` returned.
+    fn focus(&self, window: &DesktopWindow) -> Result<()>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+
+    /// A window manager with a single window that remembers what was focused.
+    #[derive(Default)]
+    struct Fake {
+        focused: RefCell<Option<i64>>,
+    }
+
+    impl Backend for Fake {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        fn windows(&self, _include_containers: bool) -> Result<Vec<DesktopWindow>> {
+            Ok(vec![DesktopWindow {
+                id: 42,
+                ..Default::default()
+            }])
+        }
+
+        fn focus(&self, window: &DesktopWindow) -> Result<()> {
+            *self.focused.borrow_mut() = Some(window.id);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_backend() {
+        let fake = Fake::default();
+        let backend: &dyn Backend = &fake;
+        assert!(backend.outputs().unwrap().is_empty());
+        let windows = backend.windows(false).unwrap();
+        backend.focus(&windows[0]).unwrap();
+        assert_eq!(*fake.focused.borrow(), Some(42));
+    }
+}
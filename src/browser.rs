@@ -0,0 +1,281 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::json;
+
+use wmfocus::DesktopWindow;
+
+/// Titles are cut off after this many characters so that tab hints don't get too wide.
+const MAX_TITLE_CHARS: usize = 60;
+
+/// Window classes of browsers the extension is available for.
+const BROWSER_CLASSES: &[&str] = &[
+    "firefox",
+    "librewolf",
+    "chromium",
+    "google-chrome",
+    "brave-browser",
+    "vivaldi-stable",
+];
+
+#[derive(Debug, Deserialize)]
+struct BrowserWindow {
+    focused: bool,
+    tabs: Vec<Tab>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tab {
+    id: i64,
+    title: String,
+    active: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct TabList {
+    windows: Vec<BrowserWindow>,
+}
+
+/// Whether `dw` is a browser window we might be able to hint the tabs of.
+pub fn is_browser(dw: &DesktopWindow) -> bool {
+    dw.class.as_deref().is_some_and(|class| {
+        BROWSER_CLASSES
+            .iter()
+            .any(|browser| class.eq_ignore_ascii_case(browser))
+    })
+}
+
+/// Where the native host listens for requests.
+fn socket_path() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join("wmfocus-browser.sock")
+}
+
+/// Frame `message` the way native messaging wants it: its length as a native endian `u32`
+/// followed by the JSON.
+fn encode_message(message: &serde_json::Value) -> Vec<u8> {
+    let json = message.to_string();
+    let mut encoded = (json.len() as u32).to_ne_bytes().to_vec();
+    encoded.extend(json.as_bytes());
+    encoded
+}
+
+/// Read one native messaging message from `reader`, `None` once the browser went away.
+fn read_message(reader: &mut impl Read) -> Result<Option<serde_json::Value>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e).context("Couldn't read message length"),
+    }
+    let mut json = vec![0; u32::from_ne_bytes(length) as usize];
+    reader
+        .read_exact(&mut json)
+        .context("Couldn't read message")?;
+    Ok(Some(
+        serde_json::from_slice(&json).context("Invalid JSON in message")?,
+    ))
+}
+
+/// Relay requests from the socket to the extension until the browser closes our stdin.
+///
+/// The browser starts `wmfocus --browser-native-host` for the extension in `contrib/browser` and
+/// talks to it over native messaging. `wmfocus` itself connects to the socket and sends one JSON
+/// request per line, which gets answered with one JSON line.
+pub fn run_native_host() -> Result<()> {
+    let path = socket_path();
+    // A previous native host that didn't get to clean up leaves its socket behind.
+    let _ = fs::remove_file(&path);
+    let listener =
+        UnixListener::bind(&path).with_context(|| format!("Couldn't bind {}", path.display()))?;
+    info!("Listening for requests on {}", path.display());
+
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let result = (|| -> Result<()> {
+        for stream in listener.incoming() {
+            // A bad connection only costs its own request, the browser is still there for the
+            // next one.
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Couldn't accept connection: {e}");
+                    continue;
+                }
+            };
+            let request = match read_request(&stream) {
+                Ok(request) => request,
+                Err(e) => {
+                    warn!("Ignoring request: {e:#}");
+                    continue;
+                }
+            };
+
+            stdout.write_all(&encode_message(&request))?;
+            stdout.flush()?;
+            let Some(response) = read_message(&mut stdin)? else {
+                break;
+            };
+            if let Err(e) = writeln!(&stream, "{response}") {
+                warn!("Couldn't answer request: {e}");
+            }
+        }
+        Ok(())
+    })();
+    let _ = fs::remove_file(&path);
+    result
+}
+
+/// Read the one JSON line a connection to the native host's socket sends.
+fn read_request(stream: &UnixStream) -> Result<serde_json::Value> {
+    let mut request = String::new();
+    BufReader::new(stream)
+        .read_line(&mut request)
+        .context("Couldn't read request")?;
+    serde_json::from_str(&request).context("Invalid request")
+}
+
+/// Send `request` to the native host and wait for the answer.
+fn send_request(request: serde_json::Value) -> Result<serde_json::Value> {
+    let path = socket_path();
+    let stream = UnixStream::connect(&path).with_context(|| {
+        format!(
+            "Couldn't connect to {}, is the browser extension installed?",
+            path.display()
+        )
+    })?;
+    writeln!(&stream, "{request}")?;
+    let mut response = String::new();
+    BufReader::new(&stream).read_line(&mut response)?;
+    let response: serde_json::Value =
+        serde_json::from_str(&response).context("Invalid response from the browser")?;
+    if let Some(error) = response.get("error") {
+        bail!("The browser couldn't handle the request: {error}");
+    }
+    Ok(response)
+}
+
+/// Turn the tabs of the browser window showing `window` into things to hint, listed in the middle
+/// of `window`.
+fn tabs_to_hint(tab_list: TabList, window: &DesktopWindow) -> Vec<DesktopWindow> {
+    // The title of a browser window is the title of its active tab (plus the browser's name).
+    let title = window.title.as_deref().unwrap_or_default();
+    let showing_window = |browser_window: &&BrowserWindow| {
+        browser_window
+            .tabs
+            .iter()
+            .any(|tab| tab.active && !tab.title.is_empty() && title.starts_with(&tab.title))
+    };
+    let Some(browser_window) = tab_list
+        .windows
+        .iter()
+        .find(showing_window)
+        .or_else(|| tab_list.windows.iter().find(|w| w.focused))
+    else {
+        return vec![];
+    };
+
+    browser_window
+        .tabs
+        .iter()
+        .map(|tab| {
+            let mut label: String = tab.title.chars().take(MAX_TITLE_CHARS).collect();
+            if tab.title.chars().count() > MAX_TITLE_CHARS {
+                label.push('…');
+            }
+            DesktopWindow {
+                id: tab.id,
+                pos: window.pos,
+                size: window.size,
+                is_focused: tab.active,
+                class: window.class.clone(),
+                title: Some(tab.title.clone()),
+                workspace: window.workspace.clone(),
+                output: window.output.clone(),
+                label: Some(label),
+                is_listed: true,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Ask the browser for the tabs of `window`.
+pub fn get_tabs(window: &DesktopWindow) -> Result<Vec<DesktopWindow>> {
+    let response = send_request(json!({"command": "list-tabs"}))?;
+    let tab_list: TabList =
+        serde_json::from_value(response).context("Unexpected list of tabs from the browser")?;
+    Ok(tabs_to_hint(tab_list, window))
+}
+
+/// Make the browser switch to `tab`.
+pub fn activate_tab(tab: &DesktopWindow) -> Result<()> {
+    send_request(json!({"command": "activate-tab", "tabId": tab.id}))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let message = json!({"command": "list-tabs"});
+        let encoded = encode_message(&message);
+        assert_eq!(&encoded[..4], &(23u32).to_ne_bytes());
+        let mut reader = encoded.as_slice();
+        assert_eq!(read_message(&mut reader).unwrap(), Some(message));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_read_request() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        writeln!(&theirs, r#"{{"command": "list-tabs"}}"#).unwrap();
+        assert_eq!(
+            read_request(&ours).unwrap(),
+            json!({"command": "list-tabs"})
+        );
+
+        // Broken requests are an error for their connection only.
+        for request in ["\n", "not json\n", ""] {
+            let (ours, mut theirs) = UnixStream::pair().unwrap();
+            theirs.write_all(request.as_bytes()).unwrap();
+            drop(theirs);
+            assert!(read_request(&ours).is_err());
+        }
+    }
+
+    #[test]
+    fn test_tabs_to_hint() {
+        let tab_list: TabList = serde_json::from_value(json!({"windows": [
+            {"focused": true, "tabs": [{"id": 1, "title": "Other", "active": true}]},
+            {"focused": false, "tabs": [
+                {"id": 2, "title": "Rust", "active": false},
+                {"id": 3, "title": "wmfocus - GitHub", "active": true},
+            ]},
+        ]}))
+        .unwrap();
+        let window = DesktopWindow {
+            title: Some("wmfocus - GitHub — Mozilla Firefox".to_string()),
+            class: Some("firefox".to_string()),
+            pos: (0, 24),
+            size: (960, 1056),
+            ..Default::default()
+        };
+        let tabs = tabs_to_hint(tab_list, &window);
+        assert_eq!(tabs.iter().map(|t| t.id).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(tabs[1].is_focused && tabs[1].is_listed);
+        assert_eq!(tabs[0].label.as_deref(), Some("Rust"));
+        assert_eq!(tabs[0].pos, window.pos);
+        assert!(is_browser(&window));
+    }
+}
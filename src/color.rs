@@ -0,0 +1,258 @@
+use css_color_parser::Color as CssColor;
+
+/// A color as (red, green, blue, alpha) with every component in the range 0 to 1.
+pub type Rgba = (f64, f64, f64, f64);
+
+/// Parse a CSS color.
+///
+/// Supports hex notation with 3, 4, 6 or 8 digits, `rgb()`/`rgba()`, `hsl()`/`hsla()` and `hwb()`
+/// in both the legacy comma separated and the modern space separated syntax (eg
+/// `rgb(30 30 30 / 90%)`), `transparent` and all named colors.
+pub fn parse_css_color(color_str: &str) -> Result<Rgba, String> {
+    let color_str = color_str.trim().to_lowercase();
+    let invalid = || format!("Invalid color '{color_str}'");
+
+    if let Some(hex) = color_str.strip_prefix('#') {
+        return parse_hex(hex).ok_or_else(invalid);
+    }
+    if color_str == "transparent" {
+        return Ok((0.0, 0.0, 0.0, 0.0));
+    }
+    if let Some((function, args)) = color_str.strip_suffix(')').and_then(|s| s.split_once('(')) {
+        let (components, alpha) = split_args(args).ok_or_else(invalid)?;
+        let alpha = match alpha {
+            Some(alpha) => parse_alpha(alpha).ok_or_else(invalid)?,
+            None => 1.0,
+        };
+        let (r, g, b) = match (function.trim(), components.as_slice()) {
+            ("rgb" | "rgba", [r, g, b]) => (
+                parse_rgb_component(r).ok_or_else(invalid)?,
+                parse_rgb_component(g).ok_or_else(invalid)?,
+                parse_rgb_component(b).ok_or_else(invalid)?,
+            ),
+            ("hsl" | "hsla", [h, s, l]) => hsl_to_rgb(
+                parse_hue(h).ok_or_else(invalid)?,
+                parse_percentage(s).ok_or_else(invalid)?,
+                parse_percentage(l).ok_or_else(invalid)?,
+            ),
+            ("hwb", [h, w, b]) => hwb_to_rgb(
+                parse_hue(h).ok_or_else(invalid)?,
+                parse_percentage(w).ok_or_else(invalid)?,
+                parse_percentage(b).ok_or_else(invalid)?,
+            ),
+            _ => return Err(invalid()),
+        };
+        return Ok((r, g, b, alpha));
+    }
+
+    let color = color_str.parse::<CssColor>().map_err(|_| invalid())?;
+    Ok((
+        f64::from(color.r) / 255.0,
+        f64::from(color.g) / 255.0,
+        f64::from(color.b) / 255.0,
+        f64::from(color.a),
+    ))
+}
+
+/// Parse the digits of a hex color (without the leading `#`).
+fn parse_hex(hex: &str) -> Option<Rgba> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits: Vec<u8> = match hex.len() {
+        // Short notation, every digit is doubled.
+        3 | 4 => hex
+            .chars()
+            .map(|c| u8::from_str_radix(&c.to_string().repeat(2), 16).ok())
+            .collect::<Option<_>>()?,
+        6 | 8 => (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<_>>()?,
+        _ => return None,
+    };
+    let component = |i: usize| f64::from(digits[i]) / 255.0;
+    let alpha = if digits.len() == 4 { component(3) } else { 1.0 };
+    Some((component(0), component(1), component(2), alpha))
+}
+
+/// Split the arguments of a color function into its three components and an optional alpha.
+fn split_args(args: &str) -> Option<(Vec<&str>, Option<&str>)> {
+    let mut components: Vec<&str> = if args.contains(',') {
+        args.split(',').map(str::trim).collect()
+    } else {
+        let (components, alpha) = match args.split_once('/') {
+            Some((components, alpha)) => (components, Some(alpha.trim())),
+            None => (args, None),
+        };
+        let mut components: Vec<&str> = components.split_whitespace().collect();
+        components.extend(alpha);
+        components
+    };
+    match components.len() {
+        3 => Some((components, None)),
+        4 => {
+            let alpha = components.pop();
+            Some((components, alpha))
+        }
+        _ => None,
+    }
+}
+
+/// Parse a number or a percentage (which is returned as a fraction).
+fn parse_number_or_percentage(value: &str) -> Option<(f64, bool)> {
+    match value.strip_suffix('%') {
+        Some(percentage) => Some((percentage.trim().parse::<f64>().ok()? / 100.0, true)),
+        None => Some((value.parse().ok()?, false)),
+    }
+}
+
+/// Parse a color channel which is either in the range 0 to 255 or a percentage.
+fn parse_rgb_component(value: &str) -> Option<f64> {
+    let (value, is_percentage) = parse_number_or_percentage(value)?;
+    let value = if is_percentage { value } else { value / 255.0 };
+    Some(value.clamp(0.0, 1.0))
+}
+
+/// Parse an alpha value which is either in the range 0 to 1 or a percentage.
+fn parse_alpha(value: &str) -> Option<f64> {
+    let (value, _) = parse_number_or_percentage(value)?;
+    Some(value.clamp(0.0, 1.0))
+}
+
+/// Parse a saturation, lightness, whiteness or blackness value.
+///
+/// These are percentages but plain numbers (in the range 0 to 100) are accepted as well.
+fn parse_percentage(value: &str) -> Option<f64> {
+    let (value, is_percentage) = parse_number_or_percentage(value)?;
+    let value = if is_percentage { value } else { value / 100.0 };
+    Some(value.clamp(0.0, 1.0))
+}
+
+/// Parse a hue in degrees, optionally with one of the CSS angle units.
+fn parse_hue(value: &str) -> Option<f64> {
+    let units = [
+        ("deg", 1.0),
+        ("grad", 0.9),
+        ("rad", 180.0 / std::f64::consts::PI),
+        ("turn", 360.0),
+    ];
+    let (number, factor) = units
+        .iter()
+        .find_map(|(unit, factor)| Some((value.strip_suffix(unit)?, *factor)))
+        .unwrap_or((value, 1.0));
+    Some((number.trim().parse::<f64>().ok()? * factor).rem_euclid(360.0))
+}
+
+/// Convert a hue in degrees and saturation and lightness in the range 0 to 1 into RGB.
+fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> (f64, f64, f64) {
+    let channel = |n: f64| {
+        let k = (n + hue / 30.0) % 12.0;
+        let a = saturation * lightness.min(1.0 - lightness);
+        lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+    (channel(0.0), channel(8.0), channel(4.0))
+}
+
+/// Convert a hue in degrees and whiteness and blackness in the range 0 to 1 into RGB.
+fn hwb_to_rgb(hue: f64, whiteness: f64, blackness: f64) -> (f64, f64, f64) {
+    if whiteness + blackness >= 1.0 {
+        let gray = whiteness / (whiteness + blackness);
+        return (gray, gray, gray);
+    }
+    let (r, g, b) = hsl_to_rgb(hue, 1.0, 0.5);
+    let scale = |c: f64| c * (1.0 - whiteness - blackness) + whiteness;
+    (scale(r), scale(g), scale(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_color_eq(actual: Rgba, expected: Rgba) {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        assert!(
+            close(actual.0, expected.0)
+                && close(actual.1, expected.1)
+                && close(actual.2, expected.2)
+                && close(actual.3, expected.3),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn test_parse_hex() {
+        assert_color_eq(parse_css_color("#fff").unwrap(), (1.0, 1.0, 1.0, 1.0));
+        assert_color_eq(
+            parse_css_color("#f008").unwrap(),
+            (1.0, 0.0, 0.0, 136.0 / 255.0),
+        );
+        assert_color_eq(parse_css_color("#00FF00").unwrap(), (0.0, 1.0, 0.0, 1.0));
+        assert_color_eq(
+            parse_css_color("#0000ff80").unwrap(),
+            (0.0, 0.0, 1.0, 128.0 / 255.0),
+        );
+        assert!(parse_css_color("#12345").is_err());
+        assert!(parse_css_color("#ggg").is_err());
+    }
+
+    #[test]
+    fn test_parse_rgb() {
+        let expected = (30.0 / 255.0, 30.0 / 255.0, 30.0 / 255.0, 0.9);
+        assert_color_eq(parse_css_color("rgba(30, 30, 30, 0.9)").unwrap(), expected);
+        assert_color_eq(parse_css_color("rgb(30 30 30 / 0.9)").unwrap(), expected);
+        assert_color_eq(parse_css_color("rgb(30 30 30 / 90%)").unwrap(), expected);
+        assert_color_eq(
+            parse_css_color("rgb(100%, 0%, 50%)").unwrap(),
+            (1.0, 0.0, 0.5, 1.0),
+        );
+        assert!(parse_css_color("rgb(1, 2)").is_err());
+        assert!(parse_css_color("rgb(a b c)").is_err());
+    }
+
+    #[test]
+    fn test_parse_hsl() {
+        assert_color_eq(
+            parse_css_color("hsl(0, 100%, 50%)").unwrap(),
+            (1.0, 0.0, 0.0, 1.0),
+        );
+        assert_color_eq(
+            parse_css_color("hsl(120deg 100% 25%)").unwrap(),
+            (0.0, 0.5, 0.0, 1.0),
+        );
+        assert_color_eq(
+            parse_css_color("hsla(0.5turn, 100%, 50%, 0.25)").unwrap(),
+            (0.0, 1.0, 1.0, 0.25),
+        );
+        assert_color_eq(
+            parse_css_color("hsl(240 100 50 / 50%)").unwrap(),
+            (0.0, 0.0, 1.0, 0.5),
+        );
+    }
+
+    #[test]
+    fn test_parse_hwb() {
+        assert_color_eq(
+            parse_css_color("hwb(0 0% 0%)").unwrap(),
+            (1.0, 0.0, 0.0, 1.0),
+        );
+        assert_color_eq(
+            parse_css_color("hwb(120 50% 0%)").unwrap(),
+            (0.5, 1.0, 0.5, 1.0),
+        );
+        assert_color_eq(
+            parse_css_color("hwb(0 60% 60% / 0.5)").unwrap(),
+            (0.5, 0.5, 0.5, 0.5),
+        );
+    }
+
+    #[test]
+    fn test_parse_named() {
+        assert_color_eq(parse_css_color("Red").unwrap(), (1.0, 0.0, 0.0, 1.0));
+        assert_color_eq(
+            parse_css_color("transparent").unwrap(),
+            (0.0, 0.0, 0.0, 0.0),
+        );
+        assert!(parse_css_color("notacolor").is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Selections offered as `org.wmfocus.Selector` on the session bus.
+//!
+//! The service has a `Select(a{sv} options) -> x window_id` method and a
+//! `SelectionMade(x window_id, s window)` signal, where `window` is the JSON that `--format json`
+//! prints. `window_id` is the id wmfocus knows the window by, the container id with i3/sway. The
+//! only option so far is `mode` with the same values as `--mode`.
+//!
+//! zbus answers calls on a thread of its own. `Select` calls are handed over to whoever polls the
+//! `Selector` and wait there until `Selector::answer` says how the selection went.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc;
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use log::{info, warn};
+use zbus::blocking::connection;
+use zbus::object_server::SignalContext;
+use zbus::zvariant::{OwnedValue, Value};
+
+use crate::args::Mode;
+use crate::{utils, DesktopWindow, ExitStatus};
+
+/// The name wmfocus takes on the session bus.
+pub const SERVICE_NAME: &str = "org.wmfocus.Selector";
+
+/// The path of the object offering `INTERFACE`.
+pub const OBJECT_PATH: &str = "/org/wmfocus/Selector";
+
+/// The interface with `Select` and `SelectionMade`.
+pub const INTERFACE: &str = "org.wmfocus.Selector";
+
+/// How a `Select` call can fail, as `org.wmfocus.Selector.Error.*`.
+#[derive(Debug, zbus::DBusError)]
+#[zbus(prefix = "org.wmfocus.Selector.Error")]
+enum Error {
+    #[zbus(error)]
+    ZBus(zbus::Error),
+    Canceled(String),
+    NoWindows(String),
+    GrabFailed(String),
+    NoMatch(String),
+    Failed(String),
+    /// The options of `Select` don't make sense.
+    InvalidArgs(String),
+}
+
+impl Error {
+    /// The error for a selection that ended with `status`.
+    fn from_status(status: ExitStatus) -> Error {
+        match status {
+            ExitStatus::Canceled => Error::Canceled("Canceled by the user".to_string()),
+            ExitStatus::NoWindows => {
+                Error::NoWindows("There was nothing to show hints for".to_string())
+            }
+            ExitStatus::GrabFailed => {
+                Error::GrabFailed("The keyboard couldn't be grabbed".to_string())
+            }
+            ExitStatus::NoMatch => {
+                Error::NoMatch("The typed keys didn't match any hint".to_string())
+            }
+            ExitStatus::Selected | ExitStatus::Error => {
+                Error::Failed("Something went wrong, see the log of wmfocus".to_string())
+            }
+        }
+    }
+}
+
+/// Someone asked for hints through `Select`, see `Selector::next_request`.
+#[derive(Debug)]
+pub struct Request {
+    /// What to show hints for, if not the default.
+    pub mode: Option<Mode>,
+    reply: async_channel::Sender<Result<i64, Error>>,
+}
+
+/// The object at `OBJECT_PATH`, living on the zbus thread.
+struct Service {
+    requests: Mutex<mpsc::Sender<Request>>,
+    /// Written to for every request so that polling the `Selector` wakes up.
+    wake_up: UnixStream,
+}
+
+#[zbus::interface(name = "org.wmfocus.Selector")]
+impl Service {
+    /// Show the hints and return the id of the selected window.
+    async fn select(&self, options: HashMap<String, OwnedValue>) -> Result<i64, Error> {
+        let mode = parse_options(&options).map_err(|e| Error::InvalidArgs(format!("{e:#}")))?;
+        let (reply, answer) = async_channel::bounded(1);
+        let sent = self
+            .requests
+            .lock()
+            .expect("no one panics while holding the lock")
+            .send(Request { mode, reply });
+        if sent.is_err() || (&self.wake_up).write_all(&[0]).is_err() {
+            return Err(Error::Failed("wmfocus is going away".to_string()));
+        }
+        answer
+            .recv()
+            .await
+            .unwrap_or_else(|_| Err(Error::Failed("wmfocus didn't answer".to_string())))
+    }
+
+    /// Announces every selection, along with the JSON that `--format json` prints for it.
+    #[zbus(signal)]
+    async fn selection_made(
+        ctxt: &SignalContext<'_>,
+        window_id: i64,
+        window: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// The `org.wmfocus.Selector` service.
+#[derive(Debug)]
+pub struct Selector {
+    connection: zbus::blocking::Connection,
+    requests: mpsc::Receiver<Request>,
+    wake_up: UnixStream,
+}
+
+impl Selector {
+    /// Take `SERVICE_NAME` on the session bus.
+    pub fn new() -> Result<Selector> {
+        let (sender, requests) = mpsc::channel();
+        let (wake_up, waker) = UnixStream::pair().context("Couldn't create a socket pair")?;
+        let service = Service {
+            requests: Mutex::new(sender),
+            wake_up: waker,
+        };
+        let connection = connection::Builder::session()
+            .and_then(|builder| builder.name(SERVICE_NAME))
+            .and_then(|builder| builder.serve_at(OBJECT_PATH, service))
+            .and_then(|builder| builder.build())
+            .with_context(|| format!("Couldn't take {SERVICE_NAME} on the session bus"))?;
+        info!("Offering {SERVICE_NAME} on the session bus");
+        Ok(Selector {
+            connection,
+            requests,
+            wake_up,
+        })
+    }
+
+    /// Take the next `Select` call after polling said there's one. Everything else on the bus is
+    /// taken care of by zbus.
+    pub fn next_request(&mut self) -> Result<Option<Request>> {
+        let mut byte = [0];
+        self.wake_up
+            .read_exact(&mut byte)
+            .context("Lost the connection to the zbus thread")?;
+        Ok(self.requests.try_recv().ok())
+    }
+
+    /// Answer `request` with how the selection ended, `selected` being what was selected if
+    /// anything.
+    pub fn answer(
+        &mut self,
+        request: Request,
+        status: ExitStatus,
+        selected: Option<&DesktopWindow>,
+    ) {
+        let result = match (status, selected) {
+            (ExitStatus::Selected, Some(selected)) => Ok(selected.id),
+            (status, _) => Err(Error::from_status(status)),
+        };
+        // The caller might have given up waiting already, which is fine.
+        let _ = request.reply.send_blocking(result);
+    }
+
+    /// Let everyone know that `selected` was selected.
+    pub fn selection_made(&mut self, selected: &DesktopWindow) -> Result<()> {
+        let service = self
+            .connection
+            .object_server()
+            .interface::<_, Service>(OBJECT_PATH)?;
+        zbus::block_on(Service::selection_made(
+            service.signal_context(),
+            selected.id,
+            &utils::selection_json(selected).to_string(),
+        ))?;
+        Ok(())
+    }
+}
+
+impl AsFd for Selector {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.wake_up.as_fd()
+    }
+}
+
+/// The mode asked for in the `a{sv}` options of `Select`.
+fn parse_options(options: &HashMap<String, OwnedValue>) -> Result<Option<Mode>> {
+    let mut mode = None;
+    for (key, value) in options {
+        match (key.as_str(), &**value) {
+            ("mode", Value::Str(name)) => {
+                mode = Some(
+                    Mode::from_str(name, true)
+                        .map_err(|_| anyhow::anyhow!("Unknown mode '{name}'"))?,
+                );
+            }
+            ("mode", _) => bail!("The mode has to be a string"),
+            (key, _) => warn!("Ignoring unknown Select option '{key}'"),
+        }
+    }
+    Ok(mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_options() {
+        let options = |entries: Vec<(&str, Value)>| {
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.try_into().unwrap()))
+                .collect::<HashMap<String, OwnedValue>>()
+        };
+        assert_eq!(parse_options(&options(vec![])).unwrap(), None);
+        assert_eq!(
+            parse_options(&options(vec![("mode", Value::from("outputs"))])).unwrap(),
+            Some(Mode::Outputs)
+        );
+        assert_eq!(
+            parse_options(&options(vec![("depth", Value::from(3))])).unwrap(),
+            None
+        );
+        assert!(parse_options(&options(vec![("mode", Value::from("nope"))])).is_err());
+        assert!(parse_options(&options(vec![("mode", Value::from(1))])).is_err());
+    }
+
+    #[test]
+    fn test_error_names() {
+        use zbus::DBusError;
+
+        let name = |status| Error::from_status(status).name().to_string();
+        assert_eq!(
+            name(ExitStatus::Canceled),
+            "org.wmfocus.Selector.Error.Canceled"
+        );
+        assert_eq!(
+            name(ExitStatus::NoMatch),
+            "org.wmfocus.Selector.Error.NoMatch"
+        );
+        assert_eq!(name(ExitStatus::Error), "org.wmfocus.Selector.Error.Failed");
+    }
+}
@@ -0,0 +1,118 @@
+use std::os::fd::{AsFd, BorrowedFd};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use rustix::event::{poll, PollFd, PollFlags};
+use x11rb::connection::Connection;
+use x11rb::protocol::Event;
+
+/// Something that happened while waiting in `EventLoop::next_event`.
+#[derive(Debug)]
+pub enum LoopEvent {
+    /// An event from the X server.
+    X(Event),
+    /// The source with this index (in the sources given to `EventLoop::new`) has something to read.
+    Readable(usize),
+    /// The deadline passed.
+    Timeout,
+}
+
+/// Waits for X events, other file descriptors (like window manager IPC or sockets) and a deadline
+/// all at once, so that none of them blocks the others.
+pub struct EventLoop<'a, C> {
+    conn: &'a C,
+    sources: Vec<BorrowedFd<'a>>,
+    deadline: Option<Instant>,
+}
+
+impl<'a, C: Connection + AsFd> EventLoop<'a, C> {
+    /// Wait for events from `conn` and for any of `sources` to become readable.
+    /// `LoopEvent::Readable` reports the index of the source in `sources`.
+    pub fn new(conn: &'a C, sources: Vec<BorrowedFd<'a>>) -> Self {
+        EventLoop {
+            conn,
+            sources,
+            deadline: None,
+        }
+    }
+
+    /// Give up waiting at `deadline` (or never with `None`).
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Wait for whatever happens first.
+    pub fn next_event(&mut self) -> Result<LoopEvent> {
+        loop {
+            // XCB might have read events already while waiting for a reply.
+            if let Some(event) = self.conn.poll_for_event()? {
+                return Ok(LoopEvent::X(event));
+            }
+            self.conn.flush()?;
+
+            let mut fds = vec![self.conn.as_fd()];
+            fds.extend(&self.sources);
+            match wait_readable(&fds, self.deadline)? {
+                None => return Ok(LoopEvent::Timeout),
+                // There's something from X, which the next round picks up.
+                Some(0) => continue,
+                Some(i) => return Ok(LoopEvent::Readable(i - 1)),
+            }
+        }
+    }
+}
+
+/// Wait until one of `fds` can be read from (or was closed) and return its index, or `None` once
+/// `deadline` passed.
+pub fn wait_readable(fds: &[BorrowedFd], deadline: Option<Instant>) -> Result<Option<usize>> {
+    loop {
+        let timeout = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                // Round up so that we don't wake up right before the deadline.
+                remaining
+                    .as_micros()
+                    .div_ceil(1000)
+                    .try_into()
+                    .unwrap_or(i32::MAX)
+            }
+            None => -1,
+        };
+        let mut poll_fds: Vec<_> = fds
+            .iter()
+            .map(|fd| PollFd::from_borrowed_fd(*fd, PollFlags::IN))
+            .collect();
+        match poll(&mut poll_fds, timeout) {
+            Ok(0) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                return Ok(None)
+            }
+            Ok(0) => continue,
+            Ok(_) => return Ok(poll_fds.iter().position(|fd| !fd.revents().is_empty())),
+            Err(rustix::io::Errno::INTR) => continue,
+            Err(e) => return Err(e).context("Couldn't wait for events"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    #[test]
+    fn test_wait_readable() {
+        let (_a, b) = UnixStream::pair().unwrap();
+        let (mut c, d) = UnixStream::pair().unwrap();
+        let fds = [b.as_fd(), d.as_fd()];
+
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert_eq!(wait_readable(&fds, Some(deadline)).unwrap(), None);
+        assert!(Instant::now() >= deadline);
+
+        c.write_all(b"x").unwrap();
+        assert_eq!(wait_readable(&fds, None).unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,283 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::{BorrowedFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde_json::json;
+
+use crate::utils;
+use crate::DesktopWindow;
+
+/// Where to report what's going on during a selection so that bars and scripts can follow along.
+///
+/// Every event is a JSON object on its own line with an `event` field saying what happened.
+///
+/// With `Events::announcing`, hints and the selection are also read out by speech-dispatcher. With
+/// `Events::reporting_status`, the state is also written to a file or FIFO for status bars.
+#[derive(Debug, Default)]
+pub struct Events {
+    file: Option<File>,
+    announce: bool,
+    status: Option<Status>,
+}
+
+/// Where to write the state for status bars and what the selection is for, see
+/// `Events::reporting_status`.
+#[derive(Debug)]
+struct Status {
+    path: PathBuf,
+    action: String,
+}
+
+impl Events {
+    /// Report events to the already open file descriptor `fd`, or nowhere if there is none.
+    pub fn new(fd: Option<RawFd>) -> Result<Events> {
+        let Some(fd) = fd else {
+            return Ok(Events::default());
+        };
+        if fd < 0 {
+            anyhow::bail!("Invalid event file descriptor {fd}");
+        }
+        // Duplicating checks that the descriptor is actually open and lets us close our copy
+        // without closing the original (which might well be stdout).
+        let owned = unsafe { BorrowedFd::borrow_raw(fd) }
+            .try_clone_to_owned()
+            .with_context(|| format!("Couldn't use file descriptor {fd} for events"))?;
+        Ok(Events {
+            file: Some(File::from(owned)),
+            ..Default::default()
+        })
+    }
+
+    /// Also read out the hints and the selection if `announce` is set.
+    pub fn announcing(self, announce: bool) -> Events {
+        Events { announce, ..self }
+    }
+
+    /// Also write whether hints are shown for `action` and what was typed to the file or FIFO at
+    /// `path`, if there is one, as a line of JSON that waybar and i3status-rs understand.
+    pub fn reporting_status(self, path: Option<PathBuf>, action: &str) -> Events {
+        let status = path.map(|path| Status {
+            path,
+            action: action.to_string(),
+        });
+        Events { status, ..self }
+    }
+
+    /// Hints were shown, `hints` maps each of them to what it's for.
+    pub fn hints_shown<'a>(
+        &self,
+        hints: impl IntoIterator<Item = (&'a String, &'a DesktopWindow)>,
+    ) {
+        let hints: Vec<_> = hints.into_iter().collect();
+        if self.announce {
+            // The announcement is interrupted by that of the selection.
+            speak(&hints_announcement(&hints), "text");
+        }
+        let hints: serde_json::Map<_, _> = hints
+            .into_iter()
+            .map(|(hint, dw)| (hint.clone(), utils::selection_json(dw)))
+            .collect();
+        self.emit(json!({"event": "hints-shown", "hints": hints}));
+        self.report_status(Some(""));
+    }
+
+    /// The hint characters typed so far changed to `keys`.
+    pub fn key_typed(&self, keys: &str) {
+        self.emit(json!({"event": "key-typed", "keys": keys}));
+        self.report_status(Some(keys));
+    }
+
+    /// `dw` was selected.
+    pub fn selection(&self, dw: &DesktopWindow) {
+        if self.announce {
+            speak(spoken_name(dw), "message");
+        }
+        self.emit(json!({"event": "selection", "window": utils::selection_json(dw)}));
+        self.report_status(None);
+    }
+
+    /// The user canceled.
+    pub fn canceled(&self) {
+        self.emit(json!({"event": "canceled"}));
+        self.report_status(None);
+    }
+
+    /// The keys typed didn't match any hint.
+    pub fn no_match(&self) {
+        self.emit(json!({"event": "no-match"}));
+        self.report_status(None);
+    }
+
+    /// No hints are shown (anymore).
+    pub fn idle(&self) {
+        self.report_status(None);
+    }
+
+    /// Write the state to the status file, selecting with `keys` typed or idle with `None`.
+    fn report_status(&self, keys: Option<&str>) {
+        let Some(status) = &self.status else {
+            return;
+        };
+        // A FIFO without a reader can't be opened without blocking, then nobody's interested.
+        let written = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .custom_flags(rustix::fs::OFlags::NONBLOCK.bits() as i32)
+            .open(&status.path)
+            .and_then(|mut file| writeln!(file, "{}", status_json(&status.action, keys)));
+        if let Err(e) = written {
+            debug!("Couldn't report status to {}: {e}", status.path.display());
+        }
+    }
+
+    fn emit(&self, event: serde_json::Value) {
+        let Some(mut file) = self.file.as_ref() else {
+            return;
+        };
+        // Whoever listens going away shouldn't keep anyone from selecting a window.
+        if let Err(e) = writeln!(file, "{event}").and_then(|_| file.flush()) {
+            warn!("Couldn't report event: {e}");
+        }
+    }
+}
+
+/// The state for status bars, selecting for `action` with `keys` typed or idle with `None`.
+///
+/// waybar goes by `text`, `alt`, `class` and `tooltip`, i3status-rs by `text` and `state`.
+fn status_json(action: &str, keys: Option<&str>) -> serde_json::Value {
+    match keys {
+        Some(keys) => {
+            let text = if keys.is_empty() {
+                action.to_string()
+            } else {
+                format!("{action}: {keys}")
+            };
+            json!({
+                "text": text,
+                "alt": "selecting",
+                "class": "selecting",
+                "tooltip": format!("wmfocus is waiting for a selection to {action}"),
+                "state": "Info",
+                "action": action,
+                "keys": keys,
+            })
+        }
+        None => json!({
+            "text": "",
+            "alt": "idle",
+            "class": "idle",
+            "tooltip": "wmfocus is idle",
+            "state": "Idle",
+        }),
+    }
+}
+
+/// What to call `dw` when reading it out.
+fn spoken_name(dw: &DesktopWindow) -> &str {
+    dw.label
+        .as_deref()
+        .or(dw.class.as_deref())
+        .or(dw.title.as_deref())
+        .unwrap_or("unnamed window")
+}
+
+/// Read out all `hints` along with what they're for, in the order they're on the screen.
+fn hints_announcement(hints: &[(&String, &DesktopWindow)]) -> String {
+    let mut hints = hints.to_vec();
+    hints.sort_by_key(|(_, dw)| (dw.pos.1, dw.pos.0));
+    let count = match hints.len() {
+        1 => "1 window".to_string(),
+        n => format!("{n} windows"),
+    };
+    let hints = hints
+        .iter()
+        .map(|(hint, dw)| format!("{hint} {}", spoken_name(dw)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{count}: {hints}")
+}
+
+/// Have speech-dispatcher read out `text` with `priority`, which decides what interrupts what.
+fn speak(text: &str, priority: &str) {
+    let status = Command::new("spd-say")
+        .args(["--priority", priority, "--", text])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => debug!("Announced '{text}'"),
+        Ok(status) => warn!("Couldn't announce '{text}', spd-say failed with {status}"),
+        Err(e) => warn!("Couldn't announce '{text}', can't run spd-say: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        assert!(Events::new(None).unwrap().file.is_none());
+        assert!(Events::new(Some(-1)).is_err());
+        assert!(Events::new(Some(9999)).is_err());
+        // Events::new only borrows the descriptor, so stderr stays usable afterwards.
+        drop(Events::new(Some(2)).unwrap());
+        assert!(Events::new(Some(2)).is_ok());
+    }
+
+    #[test]
+    fn test_status_json() {
+        let selecting = status_json("swap", Some("as"));
+        assert_eq!(selecting["text"], "swap: as");
+        assert_eq!(selecting["class"], "selecting");
+        assert_eq!(selecting["keys"], "as");
+        assert_eq!(status_json("focus", Some(""))["text"], "focus");
+        let idle = status_json("focus", None);
+        assert_eq!(idle["text"], "");
+        assert_eq!(idle["alt"], "idle");
+        assert_eq!(idle["state"], "Idle");
+    }
+
+    #[test]
+    fn test_report_status() {
+        let path = std::env::temp_dir().join(format!("wmfocus-status-{}", std::process::id()));
+        let events = Events::default().reporting_status(Some(path.clone()), "focus");
+        events.key_typed("a");
+        events.canceled();
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = lines
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![status_json("focus", Some("a")), status_json("focus", None)]
+        );
+    }
+
+    #[test]
+    fn test_hints_announcement() {
+        let firefox = DesktopWindow {
+            pos: (960, 0),
+            class: Some("firefox".to_string()),
+            title: Some("Mozilla Firefox".to_string()),
+            ..Default::default()
+        };
+        let terminal = DesktopWindow {
+            title: Some("~".to_string()),
+            ..Default::default()
+        };
+        let (a, s) = ("a".to_string(), "s".to_string());
+        assert_eq!(
+            hints_announcement(&[(&s, &firefox), (&a, &terminal)]),
+            "2 windows: a ~, s firefox"
+        );
+        assert_eq!(hints_announcement(&[(&s, &firefox)]), "1 window: s firefox");
+    }
+}
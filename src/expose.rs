@@ -0,0 +1,134 @@
+//! Exposé: thumbnails of all windows side by side on one output, with the hints on top of them.
+
+use anyhow::{Context, Result};
+use log::debug;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{self, ConnectionExt as _};
+use x11rb::protocol::Event;
+use x11rb::xcb_ffi::XCBConnection;
+
+use crate::args::AppConfig;
+use crate::geometry::{self, Rect};
+use crate::overlay::{self, Transparency};
+use crate::DesktopWindow;
+
+/// Space between the thumbnails and around them, in pixels.
+const GAP: i32 = 24;
+
+/// Width of the frame around the thumbnail of the focused window, in pixels.
+const FOCUS_FRAME: f64 = 4.0;
+
+/// Capture `desktop_windows` and show their thumbnails side by side on `area`.
+///
+/// Returns the (mapped and drawn) window showing the thumbnails, which the caller has to destroy,
+/// and a copy of every `DesktopWindow` moved onto its thumbnail for showing hints there.
+///
+/// X only knows what's on the screen, so parts of windows covered by others show up as whatever
+/// covers them, and windows that can't be captured at all get an empty thumbnail.
+pub fn show(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &AppConfig,
+    desktop_windows: &[DesktopWindow],
+    area: Rect,
+) -> Result<(xproto::Window, Vec<DesktopWindow>)> {
+    // Everything has to be captured before the thumbnails cover it.
+    let screen_rect = (
+        0,
+        0,
+        i32::from(screen.width_in_pixels),
+        i32::from(screen.height_in_pixels),
+    );
+    let mut captures = vec![];
+    for dw in desktop_windows {
+        let visible =
+            geometry::intersection((dw.pos.0, dw.pos.1, dw.size.0, dw.size.1), screen_rect);
+        let capture = match visible {
+            Some((x, y, w, h)) => {
+                overlay::capture_backdrop(conn, screen, (x as i16, y as i16, w as u16, h as u16))
+                    .unwrap_or_else(|e| {
+                        debug!("Couldn't capture window {}: {e:#}", dw.id);
+                        None
+                    })
+            }
+            None => None,
+        };
+        captures.push(capture);
+    }
+
+    let cells = geometry::expose_cells(desktop_windows.len(), area, GAP);
+    let thumbnails: Vec<Rect> = desktop_windows
+        .iter()
+        .zip(&cells)
+        .map(|(dw, cell)| geometry::fit_rect(dw.size, *cell))
+        .collect();
+
+    // Thumbnails are opaque no matter the background color.
+    let opaque_config = AppConfig {
+        bg_color: (
+            app_config.bg_color.0,
+            app_config.bg_color.1,
+            app_config.bg_color.2,
+            1.0,
+        ),
+        ..app_config.clone()
+    };
+    let (window, cairo_context, _) = overlay::create_overlay_window(
+        conn,
+        screen,
+        &opaque_config,
+        Transparency::Pseudo,
+        (area.0 as i16, area.1 as i16, area.2 as u16, area.3 as u16),
+    )?;
+    conn.map_window(window)?;
+    conn.flush()?;
+
+    // Drawing only sticks once the window is actually shown.
+    loop {
+        if let Event::Expose(event) = conn.wait_for_event()? {
+            if event.window == window && event.count == 0 {
+                break;
+            }
+        }
+    }
+
+    let bg = app_config.bg_color;
+    cairo_context.set_source_rgb(bg.0, bg.1, bg.2);
+    cairo_context.paint().context("Error trying to draw")?;
+    for ((dw, capture), thumbnail) in desktop_windows.iter().zip(&captures).zip(&thumbnails) {
+        let (x, y, w, h) = thumbnail;
+        let (x, y) = (f64::from(x - area.0), f64::from(y - area.1));
+        if dw.is_focused {
+            let current = app_config.bg_color_current;
+            cairo_context.set_source_rgb(current.0, current.1, current.2);
+            cairo_context.set_line_width(FOCUS_FRAME * 2.0);
+            cairo_context.rectangle(x, y, f64::from(*w), f64::from(*h));
+            cairo_context.stroke().context("Error trying to draw")?;
+        }
+        let Some(capture) = capture else {
+            continue;
+        };
+        cairo_context.save()?;
+        cairo_context.translate(x, y);
+        cairo_context.scale(
+            f64::from(*w) / f64::from(dw.size.0),
+            f64::from(*h) / f64::from(dw.size.1),
+        );
+        cairo_context.set_source_surface(capture, 0.0, 0.0)?;
+        cairo_context.paint().context("Error trying to draw")?;
+        cairo_context.restore()?;
+    }
+    cairo_context.target().flush();
+    conn.flush()?;
+
+    let moved = desktop_windows
+        .iter()
+        .zip(thumbnails)
+        .map(|(dw, (x, y, w, h))| DesktopWindow {
+            pos: (x, y),
+            size: (w, h),
+            ..dw.clone()
+        })
+        .collect();
+    Ok((window, moved))
+}
@@ -0,0 +1,118 @@
+//! A C ABI for the hint engine so that tools not written in Rust (eg awesome or qtile configs)
+//! hand out the same hints as wmfocus does.
+//!
+//! Only built with the `ffi` feature. See `contrib/wmfocus.h` for the declarations and how to get a
+//! shared library.
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+use std::slice;
+
+use crate::hints::{self, HintStrategy};
+
+/// A rectangle to assign a hint to.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct WmfocusRect {
+    pub x: i32,
+    pub y: i32,
+    pub w: i32,
+    pub h: i32,
+}
+
+/// Assign hints made of the characters in `hint_chars` to the `count` rectangles at `rects`.
+///
+/// Returns an array of `count` NUL terminated hints in the order of `rects`, which has to be freed
+/// with `wmfocus_free_hints`, or NULL if there are too few `hint_chars` or they aren't valid UTF-8.
+///
+/// # Safety
+///
+/// `rects` has to point to `count` rectangles (or can be NULL if `count` is 0) and `hint_chars`
+/// has to be a NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn wmfocus_assign_hints(
+    rects: *const WmfocusRect,
+    count: usize,
+    hint_chars: *const c_char,
+) -> *mut *mut c_char {
+    if hint_chars.is_null() || (rects.is_null() && count > 0) {
+        return ptr::null_mut();
+    }
+    let Ok(hint_chars) = CStr::from_ptr(hint_chars).to_str() else {
+        return ptr::null_mut();
+    };
+    let rects: Vec<_> = if count == 0 {
+        vec![]
+    } else {
+        slice::from_raw_parts(rects, count)
+            .iter()
+            .map(|r| (r.x, r.y, r.w, r.h))
+            .collect()
+    };
+    let Ok(hints) = hints::assign_hints(&rects, hint_chars, HintStrategy::default()) else {
+        return ptr::null_mut();
+    };
+    let hints: Box<[*mut c_char]> = hints
+        .into_iter()
+        .map(|hint| {
+            CString::new(hint)
+                .expect("Hints are made of hint_chars, which can't contain NUL")
+                .into_raw()
+        })
+        .collect();
+    Box::into_raw(hints).cast()
+}
+
+/// Free hints returned by `wmfocus_assign_hints`.
+///
+/// # Safety
+///
+/// `hints` has to be NULL or come from `wmfocus_assign_hints` called with the same `count`, and
+/// must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn wmfocus_free_hints(hints: *mut *mut c_char, count: usize) {
+    if hints.is_null() {
+        return;
+    }
+    let hints = Box::from_raw(ptr::slice_from_raw_parts_mut(hints, count));
+    for hint in hints.iter() {
+        drop(CString::from_raw(*hint));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assign_hints() {
+        let rects = [
+            WmfocusRect {
+                x: 960,
+                y: 0,
+                w: 960,
+                h: 1080,
+            },
+            WmfocusRect {
+                x: 0,
+                y: 0,
+                w: 960,
+                h: 1080,
+            },
+        ];
+        let hint_chars = CString::new("asdf").unwrap();
+        unsafe {
+            let hints = wmfocus_assign_hints(rects.as_ptr(), rects.len(), hint_chars.as_ptr());
+            assert!(!hints.is_null());
+            assert_eq!(CStr::from_ptr(*hints).to_str(), Ok("s"));
+            assert_eq!(CStr::from_ptr(*hints.add(1)).to_str(), Ok("a"));
+            wmfocus_free_hints(hints, rects.len());
+
+            let empty = CString::new("").unwrap();
+            assert!(wmfocus_assign_hints(rects.as_ptr(), rects.len(), empty.as_ptr()).is_null());
+            let none = wmfocus_assign_hints(ptr::null(), 0, hint_chars.as_ptr());
+            assert!(!none.is_null());
+            wmfocus_free_hints(none, 0);
+        }
+    }
+}
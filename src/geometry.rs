@@ -0,0 +1,979 @@
+//! Rectangle math for placing hints, independent of any window manager or display server.
+
+use std::cmp::Ordering;
+use std::iter;
+
+use itertools::Itertools;
+use log::debug;
+
+use crate::{DesktopOutput, DesktopWindow, Transform};
+
+/// A rectangle in the format (x, y, w, h).
+pub type Rect = (i32, i32, i32, i32);
+
+/// Split the area of `dw` into a grid of `cols` by `rows` cells.
+///
+/// Leftover pixels go to the last column and row so that the cells cover the whole area.
+pub fn grid_cells(dw: &DesktopWindow, (cols, rows): (u32, u32)) -> Vec<DesktopWindow> {
+    let (cols, rows) = (cols as i32, rows as i32);
+    let (cell_w, cell_h) = (dw.size.0 / cols, dw.size.1 / rows);
+    (0..rows)
+        .cartesian_product(0..cols)
+        .map(|(row, col)| {
+            let x = dw.pos.0 + col * cell_w;
+            let y = dw.pos.1 + row * cell_h;
+            let w = if col == cols - 1 {
+                dw.pos.0 + dw.size.0 - x
+            } else {
+                cell_w
+            };
+            let h = if row == rows - 1 {
+                dw.pos.1 + dw.size.1 - y
+            } else {
+                cell_h
+            };
+            DesktopWindow {
+                id: (row * cols + col).into(),
+                pos: (x, y),
+                size: (w, h),
+                output: dw.output.clone(),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Lay out `count` equally sized cells side by side on `area`, `gap` apart from each other and the
+/// edges, in rows from the top.
+///
+/// The grid is about as wide as it is high in cells and the last row is centered if it isn't full.
+pub fn expose_cells(count: usize, area: Rect, gap: i32) -> Vec<Rect> {
+    if count == 0 {
+        return vec![];
+    }
+    let cols = (count as f64).sqrt().ceil() as usize;
+    let rows = count.div_ceil(cols);
+    let cell_w = (area.2 - gap) / cols as i32 - gap;
+    let cell_h = (area.3 - gap) / rows as i32 - gap;
+    (0..count)
+        .map(|i| {
+            let (row, col) = (i / cols, i % cols);
+            let in_row = (count - row * cols).min(cols);
+            let indent = (cols - in_row) as i32 * (cell_w + gap) / 2;
+            (
+                area.0 + gap + indent + col as i32 * (cell_w + gap),
+                area.1 + gap + row as i32 * (cell_h + gap),
+                cell_w,
+                cell_h,
+            )
+        })
+        .collect()
+}
+
+/// The largest rect with the aspect ratio of `size` that fits into `cell` (but isn't larger than
+/// `size`), centered in it.
+pub fn fit_rect(size: (i32, i32), cell: Rect) -> Rect {
+    let scale = fit_scale((f64::from(size.0), f64::from(size.1)), (cell.2, cell.3));
+    let (w, h) = (
+        (f64::from(size.0) * scale) as i32,
+        (f64::from(size.1) * scale) as i32,
+    );
+    (cell.0 + (cell.2 - w) / 2, cell.1 + (cell.3 - h) / 2, w, h)
+}
+
+/// Strips along the left, bottom, top and right edge of `rect` (in the order of h, j, k and l),
+/// each a quarter of its shorter side thick.
+pub fn edge_strips((x, y, w, h): Rect) -> [Rect; 4] {
+    let thickness = (w.min(h) / 4).max(1);
+    [
+        (x, y, thickness, h),
+        (x, y + h - thickness, w, thickness),
+        (x, y, w, thickness),
+        (x + w - thickness, y, thickness, h),
+    ]
+}
+
+/// The bar along the bottom of a hint of `size` showing that a `fraction` of the time is left, see
+/// `--timeout`.
+pub fn countdown_bar((w, h): (i32, i32), fraction: f64) -> Rect {
+    let height = (h / 8).max(2).min(h);
+    let width = (f64::from(w) * fraction.clamp(0.0, 1.0)).round() as i32;
+    (0, h - height, width, height)
+}
+
+/// `rect` scaled by `factor` around its center.
+pub fn zoom_rect((x, y, w, h): Rect, factor: f64) -> Rect {
+    let (zoomed_w, zoomed_h) = (
+        (f64::from(w) * factor).round() as i32,
+        (f64::from(h) * factor).round() as i32,
+    );
+    (
+        x + (w - zoomed_w) / 2,
+        y + (h - zoomed_h) / 2,
+        zoomed_w,
+        zoomed_h,
+    )
+}
+
+/// Whether `point` lies within `rect`.
+pub fn contains((x, y, w, h): Rect, point: (i32, i32)) -> bool {
+    (x..x + w).contains(&point.0) && (y..y + h).contains(&point.1)
+}
+
+/// Shrink `dw` to its visible frame, leaving out the invisible borders (like shadows) given as
+/// `[left, right, top, bottom]` the way `_GTK_FRAME_EXTENTS` has them.
+///
+/// Extents that don't leave anything of `dw` are ignored.
+pub fn apply_frame_extents(dw: &mut DesktopWindow, [left, right, top, bottom]: [u32; 4]) {
+    let [left, right, top, bottom] =
+        [left, right, top, bottom].map(|e| e.min(i32::MAX as u32) as i32);
+    let size = (
+        dw.size.0.saturating_sub(left).saturating_sub(right),
+        dw.size.1.saturating_sub(top).saturating_sub(bottom),
+    );
+    if size.0 <= 0 || size.1 <= 0 {
+        return;
+    }
+    dw.pos = (dw.pos.0 + left, dw.pos.1 + top);
+    dw.size = size;
+}
+
+/// Sort list of `DesktopWindow`s by position.
+///
+/// This sorts by column first and row second.
+pub fn sort_by_pos(mut dws: Vec<DesktopWindow>) -> Vec<DesktopWindow> {
+    dws.sort_by_key(|w| w.pos.0);
+    dws.sort_by_key(|w| w.pos.1);
+    dws
+}
+
+/// Map `rect` (relative to an output of `size` as the window manager sees it) to where it ends up on
+/// the same output before `transform` is applied.
+pub fn transform_rect(
+    rect: (i32, i32, i32, i32),
+    size: (i32, i32),
+    transform: Transform,
+) -> (i32, i32, i32, i32) {
+    let (w, h) = size;
+    let map_point = |(x, y): (i32, i32)| {
+        // Undo flipping first, then rotation.
+        let x = match transform {
+            Transform::Flipped
+            | Transform::Flipped90
+            | Transform::Flipped180
+            | Transform::Flipped270 => w - x,
+            _ => x,
+        };
+        match transform {
+            Transform::Rotate90 | Transform::Flipped90 => (h - y, x),
+            Transform::Rotate180 | Transform::Flipped180 => (w - x, h - y),
+            Transform::Rotate270 | Transform::Flipped270 => (y, w - x),
+            Transform::Normal | Transform::Flipped => (x, y),
+        }
+    };
+    let (x1, y1) = map_point((rect.0, rect.1));
+    let (x2, y2) = map_point((rect.0 + rect.2, rect.1 + rect.3));
+    (x1.min(x2), y1.min(y2), (x2 - x1).abs(), (y2 - y1).abs())
+}
+
+/// Correct the geometry of all `dws` that live on transformed or scaled `outputs`.
+///
+/// The window manager reports logical coordinates with the output transform and scale already
+/// applied. In case the matching X `monitors` have the untransformed or unscaled size (which can
+/// happen with Xwayland), we'll have to map the coordinates into the space of the X monitor. For
+/// 180 degree rotations, the size is the same either way so we can't tell and leave them alone.
+pub fn apply_output_geometry(
+    dws: &mut [DesktopWindow],
+    outputs: &[DesktopOutput],
+    monitors: &[DesktopOutput],
+) {
+    for dw in dws {
+        let Some(output) = outputs.iter().find(|o| Some(&o.name) == dw.output.as_ref()) else {
+            continue;
+        };
+        let Some(monitor) = monitors.iter().find(|m| m.name == output.name) else {
+            continue;
+        };
+        let monitor = monitor.rect;
+        let rotated = matches!(
+            output.transform,
+            Transform::Rotate90
+                | Transform::Rotate270
+                | Transform::Flipped90
+                | Transform::Flipped270
+        );
+        let logical_size = (output.rect.2, output.rect.3);
+        let fits = |(w, h): (i32, i32), factor: f64| {
+            ((f64::from(w) * factor).round() as i32 - monitor.2).abs() <= 1
+                && ((f64::from(h) * factor).round() as i32 - monitor.3).abs() <= 1
+        };
+
+        // Figure out whether X sees this output untransformed and/or unscaled.
+        let candidates = [
+            (false, 1.0),
+            (true, 1.0),
+            (false, output.scale),
+            (true, output.scale),
+        ];
+        let Some(&(untransform, factor)) = candidates.iter().find(|(untransform, factor)| {
+            let size = if *untransform {
+                (logical_size.1, logical_size.0)
+            } else {
+                logical_size
+            };
+            (!untransform || rotated) && fits(size, *factor)
+        }) else {
+            continue;
+        };
+        if !untransform && factor == 1.0 {
+            continue;
+        }
+
+        let mut local = (
+            dw.pos.0 - output.rect.0,
+            dw.pos.1 - output.rect.1,
+            dw.size.0,
+            dw.size.1,
+        );
+        if untransform {
+            local = transform_rect(local, logical_size, output.transform);
+        }
+        let scale = |v: i32| (f64::from(v) * factor).round() as i32;
+        dw.pos = (monitor.0 + scale(local.0), monitor.1 + scale(local.1));
+        dw.size = (scale(local.2), scale(local.3));
+        debug!("Mapped {:?} onto X monitor of output {:?}", dw, output);
+    }
+}
+
+/// Find the monitor that `dw` lives on.
+///
+/// This goes by the output name the window manager gave us and falls back to the monitor that
+/// contains the center of `dw`.
+pub fn find_monitor<'a>(
+    dw: &DesktopWindow,
+    monitors: &'a [DesktopOutput],
+) -> Option<&'a DesktopOutput> {
+    monitors
+        .iter()
+        .find(|m| Some(&m.name) == dw.output.as_ref())
+        .or_else(|| {
+            let center = (dw.pos.0 + dw.size.0 / 2, dw.pos.1 + dw.size.1 / 2);
+            monitors
+                .iter()
+                .find(|m| intersects(m.rect, (center.0, center.1, 1, 1)))
+        })
+}
+
+/// Move `rect` so that it lies within `bounds` and return its new position.
+///
+/// If `rect` is larger than `bounds`, it will be aligned with the top left corner of `bounds`.
+pub fn clamp_to_rect(rect: (i32, i32, i32, i32), bounds: (i32, i32, i32, i32)) -> (i32, i32) {
+    (
+        rect.0.min(bounds.0 + bounds.2 - rect.2).max(bounds.0),
+        rect.1.min(bounds.1 + bounds.3 - rect.3).max(bounds.1),
+    )
+}
+
+/// How much something of `size` has to be scaled by to fit into `bounds`, at most 1.
+pub fn fit_scale(size: (f64, f64), bounds: (i32, i32)) -> f64 {
+    (f64::from(bounds.0) / size.0)
+        .min(f64::from(bounds.1) / size.1)
+        .min(1.0)
+}
+
+/// How much something of `size` has to be scaled by to be as large as fits into `fraction` of the
+/// width and height of `bounds`.
+pub fn grow_scale(size: (f64, f64), bounds: (i32, i32), fraction: f64) -> f64 {
+    let scale =
+        (f64::from(bounds.0) * fraction / size.0).min(f64::from(bounds.1) * fraction / size.1);
+    if scale.is_finite() {
+        scale
+    } else {
+        1.0
+    }
+}
+
+/// Where `rect` goes to lie just outside of `window`.
+///
+/// `side` tells for the x and y axis whether `rect` goes before `window` (-1), after it (1) or stays
+/// where it is (0).
+pub fn outside_rect(rect: Rect, window: Rect, side: (i32, i32)) -> (i32, i32) {
+    let place = |pos: i32, size: i32, window_pos: i32, window_size: i32, side: i32| match side {
+        ..=-1 => window_pos - size,
+        0 => pos,
+        1.. => window_pos + window_size,
+    };
+    (
+        place(rect.0, rect.2, window.0, window.2, side.0),
+        place(rect.1, rect.3, window.1, window.3, side.1),
+    )
+}
+
+/// Find a spot for `rect` close to where it is that doesn't overlap any of the `taken` ones.
+///
+/// Spots to the right of, below and above the rects in the way are tried in that order, keeping `rect` within
+/// `bounds` (usually the window it belongs to) if it fits in there at all. If there's no such spot,
+/// `rect` is stacked below whatever is in the way instead, which puts the hints of windows sharing
+/// an origin into a column.
+pub fn place_rect(rect: Rect, taken: &[Rect], bounds: Rect) -> (i32, i32) {
+    let (_, _, width, height) = rect;
+    let is_free = |(x, y): (i32, i32)| !taken.iter().any(|t| intersects(*t, (x, y, width, height)));
+    if is_free((rect.0, rect.1)) {
+        return (rect.0, rect.1);
+    }
+
+    let fits = width <= bounds.2 && height <= bounds.3;
+    let within = |(x, y): (i32, i32)| {
+        !fits
+            || (x >= bounds.0
+                && y >= bounds.1
+                && x + width <= bounds.0 + bounds.2
+                && y + height <= bounds.1 + bounds.3)
+    };
+    // Only what's near `rect` can make a spot worth trying.
+    let nearby: Vec<Rect> = taken
+        .iter()
+        .copied()
+        .filter(|t| !fits || intersects(*t, bounds))
+        .collect();
+    let xs = iter::once(rect.0)
+        .chain(nearby.iter().map(|t| t.0 + t.2))
+        .chain(nearby.iter().map(|t| t.0 - width));
+    let ys: Vec<i32> = iter::once(rect.1)
+        .chain(nearby.iter().map(|t| t.1 + t.3))
+        .chain(nearby.iter().map(|t| t.1 - height))
+        .collect();
+    let best = xs
+        .flat_map(|x| ys.iter().map(move |y| (x, *y)))
+        .filter(|spot| within(*spot) && is_free(*spot))
+        .min_by_key(|&(x, y)| {
+            let direction = match (x.cmp(&rect.0), y.cmp(&rect.1)) {
+                (Ordering::Greater, Ordering::Equal) => 0,
+                (Ordering::Equal, Ordering::Greater) => 1,
+                (Ordering::Equal, Ordering::Less) => 2,
+                _ => 3,
+            };
+            (direction, (x - rect.0).abs() + (y - rect.1).abs())
+        });
+    if let Some(spot) = best {
+        return spot;
+    }
+
+    let mut y = rect.1;
+    while let Some(bottom) = taken
+        .iter()
+        .filter(|t| intersects(**t, (rect.0, y, width, height)))
+        .map(|t| t.1 + t.3)
+        .max()
+    {
+        y = bottom;
+    }
+    (rect.0, y)
+}
+
+/// Where `rect` has to go to stay put relative to a window that moved from `old` to `new`.
+///
+/// `anchor` is the point of the window `rect` keeps its distance to, as fractions of the window's
+/// width and height (`(0.0, 0.0)` is the top left corner, `(1.0, 1.0)` the bottom right one).
+pub fn follow_rect(rect: Rect, old: Rect, new: Rect, anchor: (f64, f64)) -> (i32, i32) {
+    let shift = |pos: i32, size: i32, fraction: f64| pos + (f64::from(size) * fraction) as i32;
+    (
+        rect.0 + shift(new.0, new.2, anchor.0) - shift(old.0, old.2, anchor.0),
+        rect.1 + shift(new.1, new.3, anchor.1) - shift(old.1, old.3, anchor.1),
+    )
+}
+
+/// Drop windows that are hidden behind a fullscreen window or completely covered by floating
+/// windows on the same workspace.
+///
+/// Unlike `handle_occlusion`, this only needs what the window manager told us about its layers.
+/// Entries without a place on the screen are left alone.
+pub fn drop_covered(dws: Vec<DesktopWindow>) -> Vec<DesktopWindow> {
+    let rect = |dw: &DesktopWindow| (dw.pos.0, dw.pos.1, dw.size.0, dw.size.1);
+    let fullscreen_workspaces: Vec<_> = dws
+        .iter()
+        .filter(|dw| dw.is_fullscreen && dw.workspace.is_some())
+        .map(|dw| dw.workspace.clone())
+        .collect();
+    let floating: Vec<_> = dws
+        .iter()
+        .filter(|dw| dw.is_floating)
+        .map(|dw| (dw.workspace.clone(), rect(dw)))
+        .collect();
+
+    dws.into_iter()
+        .filter(|dw| {
+            if dw.is_listed || dw.is_fullscreen {
+                return true;
+            }
+            if fullscreen_workspaces.contains(&dw.workspace) {
+                debug!("{dw:?} is behind a fullscreen window");
+                return false;
+            }
+            if dw.is_floating {
+                return true;
+            }
+            let covers: Vec<Rect> = floating
+                .iter()
+                .filter(|(workspace, _)| *workspace == dw.workspace)
+                .map(|(_, rect)| *rect)
+                .collect();
+            let visible = !visible_parts(rect(dw), &covers).is_empty();
+            if !visible {
+                debug!("{dw:?} is covered by floating windows");
+            }
+            visible
+        })
+        .collect()
+}
+
+/// Take the X stacking order into account for all `dws`.
+///
+/// Windows that are completely covered by windows stacked above them are dropped. Windows that are
+/// partially covered are shrunk to the largest part of them that is still visible so that their
+/// hint ends up somewhere the user can actually see. `stacking` is the order of X window ids from
+/// bottom to top. `DesktopWindow`s that aren't part of the stacking order are left alone.
+pub fn handle_occlusion(dws: Vec<DesktopWindow>, stacking: &[u32]) -> Vec<DesktopWindow> {
+    let stack_index = |dw: &DesktopWindow| {
+        dw.x_window_id
+            .and_then(|id| stacking.iter().position(|&s| s == id as u32))
+    };
+    let rects: Vec<_> = dws
+        .iter()
+        .map(|dw| (stack_index(dw), (dw.pos.0, dw.pos.1, dw.size.0, dw.size.1)))
+        .collect();
+
+    dws.into_iter()
+        .zip(rects.iter())
+        .filter_map(|(mut dw, &(index, rect))| {
+            let Some(index) = index else {
+                return Some(dw);
+            };
+            let covers: Vec<_> = rects
+                .iter()
+                .filter(|(other_index, _)| other_index.is_some_and(|i| i > index))
+                .map(|(_, r)| *r)
+                .collect();
+            let visible = visible_parts(rect, &covers);
+            let largest = visible.into_iter().max_by_key(|r| r.2 * r.3)?;
+            if largest != rect {
+                debug!("{:?} is partially covered, using {:?}", dw, largest);
+                dw.pos = (largest.0, largest.1);
+                dw.size = (largest.2, largest.3);
+            }
+            Some(dw)
+        })
+        .collect()
+}
+
+/// Subtract `cut` from `rect` and return the remainder as up to four rects.
+fn subtract_rect(
+    rect: (i32, i32, i32, i32),
+    cut: (i32, i32, i32, i32),
+) -> Vec<(i32, i32, i32, i32)> {
+    if !intersects(rect, cut) {
+        return vec![rect];
+    }
+    let mut remainder = vec![];
+    let (rect_bottom, cut_bottom) = (rect.1 + rect.3, cut.1 + cut.3);
+    if cut.1 > rect.1 {
+        remainder.push((rect.0, rect.1, rect.2, cut.1 - rect.1));
+    }
+    if cut_bottom < rect_bottom {
+        remainder.push((rect.0, cut_bottom, rect.2, rect_bottom - cut_bottom));
+    }
+    let (band_top, band_bottom) = (rect.1.max(cut.1), rect_bottom.min(cut_bottom));
+    if cut.0 > rect.0 {
+        remainder.push((rect.0, band_top, cut.0 - rect.0, band_bottom - band_top));
+    }
+    if cut.0 + cut.2 < rect.0 + rect.2 {
+        remainder.push((
+            cut.0 + cut.2,
+            band_top,
+            rect.0 + rect.2 - (cut.0 + cut.2),
+            band_bottom - band_top,
+        ));
+    }
+    remainder
+}
+
+/// Return the parts of `rect` that aren't covered by any of `covers`.
+fn visible_parts(
+    rect: (i32, i32, i32, i32),
+    covers: &[(i32, i32, i32, i32)],
+) -> Vec<(i32, i32, i32, i32)> {
+    covers.iter().fold(vec![rect], |parts, cut| {
+        parts
+            .into_iter()
+            .flat_map(|part| subtract_rect(part, *cut))
+            .collect()
+    })
+}
+
+/// Returns the part of `r1` that also lies within `r2`, if any.
+pub fn intersection(r1: Rect, r2: Rect) -> Option<Rect> {
+    let left = r1.0.max(r2.0);
+    let top = r1.1.max(r2.1);
+    let right = (r1.0 + r1.2).min(r2.0 + r2.2);
+    let bottom = (r1.1 + r1.3).min(r2.1 + r2.3);
+    (left < right && top < bottom).then_some((left, top, right - left, bottom - top))
+}
+
+/// Find the monitor showing the largest part of a `DesktopWindow` spanning multiple monitors.
+///
+/// Returns that monitor along with the part of the window lying on it or `None` if the window is
+/// on at most one monitor.
+pub fn find_spanned_monitor<'a>(
+    dw: &DesktopWindow,
+    monitors: &'a [DesktopOutput],
+) -> Option<(&'a DesktopOutput, Rect)> {
+    let rect = (dw.pos.0, dw.pos.1, dw.size.0, dw.size.1);
+    let parts: Vec<_> = monitors
+        .iter()
+        .filter_map(|m| Some((m, intersection(rect, m.rect)?)))
+        .collect();
+    if parts.len() < 2 {
+        return None;
+    }
+    parts
+        .into_iter()
+        .max_by_key(|(_, part)| i64::from(part.2) * i64::from(part.3))
+}
+
+/// Returns true if `r1` and `r2` overlap.
+pub fn intersects(r1: Rect, r2: Rect) -> bool {
+    let left_corner_inside = r1.0 < r2.0 + r2.2;
+    let right_corner_inside = r1.0 + r1.2 > r2.0;
+    let top_corner_inside = r1.1 < r2.1 + r2.3;
+    let bottom_corner_inside = r1.1 + r1.3 > r2.1;
+    left_corner_inside && right_corner_inside && top_corner_inside && bottom_corner_inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intersects() {
+        assert!(intersects((1905, 705, 31, 82), (1905, 723, 38, 64)));
+    }
+
+    #[test]
+    fn test_no_intersect() {
+        assert!(!intersects((1905, 705, 31, 82), (2000, 723, 38, 64)));
+    }
+
+    #[test]
+    fn test_drop_covered() {
+        let window = |id, pos, size, workspace: &str| DesktopWindow {
+            id,
+            pos,
+            size,
+            workspace: Some(workspace.to_string()),
+            ..Default::default()
+        };
+        let ids = |dws: Vec<DesktopWindow>| dws.iter().map(|dw| dw.id).collect::<Vec<_>>();
+
+        let tiled = window(1, (0, 0), (960, 1080), "1");
+        let other_tiled = window(2, (960, 0), (960, 1080), "1");
+        let floating = DesktopWindow {
+            is_floating: true,
+            ..window(3, (-10, -10), (980, 1100), "1")
+        };
+        let other_workspace = window(4, (0, 0), (960, 1080), "2");
+        assert_eq!(
+            ids(drop_covered(vec![
+                tiled.clone(),
+                other_tiled.clone(),
+                floating.clone(),
+                other_workspace.clone(),
+            ])),
+            vec![2, 3, 4]
+        );
+
+        // Nothing but the fullscreen window is visible on its workspace.
+        let fullscreen = DesktopWindow {
+            is_fullscreen: true,
+            ..other_tiled.clone()
+        };
+        let listed = DesktopWindow {
+            is_listed: true,
+            ..window(5, (0, 0), (1920, 1080), "1")
+        };
+        assert_eq!(
+            ids(drop_covered(vec![
+                tiled,
+                fullscreen,
+                floating,
+                other_workspace,
+                listed
+            ])),
+            vec![2, 4, 5]
+        );
+    }
+
+    #[test]
+    fn test_expose_cells() {
+        assert_eq!(expose_cells(0, (0, 0, 1000, 600), 10), vec![]);
+        assert_eq!(
+            expose_cells(2, (100, 0, 1000, 600), 10),
+            vec![(110, 10, 485, 580), (605, 10, 485, 580)]
+        );
+        // The last row is centered.
+        assert_eq!(
+            expose_cells(3, (0, 0, 1000, 600), 10),
+            vec![
+                (10, 10, 485, 285),
+                (505, 10, 485, 285),
+                (257, 305, 485, 285)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fit_rect() {
+        assert_eq!(fit_rect((800, 600), (0, 0, 400, 400)), (0, 50, 400, 300));
+        assert_eq!(fit_rect((100, 50), (10, 10, 400, 400)), (160, 185, 100, 50));
+    }
+
+    #[test]
+    fn test_edge_strips() {
+        assert_eq!(
+            edge_strips((100, 50, 800, 400)),
+            [
+                (100, 50, 100, 400),
+                (100, 350, 800, 100),
+                (100, 50, 800, 100),
+                (800, 50, 100, 400),
+            ]
+        );
+        assert_eq!(edge_strips((0, 0, 2, 2))[3], (1, 0, 1, 2));
+    }
+
+    #[test]
+    fn test_countdown_bar() {
+        assert_eq!(countdown_bar((100, 40), 1.0), (0, 35, 100, 5));
+        assert_eq!(countdown_bar((100, 40), 0.25), (0, 35, 25, 5));
+        assert_eq!(countdown_bar((100, 10), -0.5), (0, 8, 0, 2));
+        assert_eq!(countdown_bar((100, 1), 2.0), (0, 0, 100, 1));
+    }
+
+    #[test]
+    fn test_outside_rect() {
+        let window = (100, 200, 800, 600);
+        assert_eq!(
+            outside_rect((100, 200, 40, 20), window, (0, -1)),
+            (100, 180)
+        );
+        assert_eq!(
+            outside_rect((860, 200, 40, 20), window, (1, -1)),
+            (900, 180)
+        );
+        assert_eq!(outside_rect((100, 780, 40, 20), window, (-1, 1)), (60, 800));
+        assert_eq!(outside_rect((480, 490, 40, 20), window, (0, 0)), (480, 490));
+    }
+
+    #[test]
+    fn test_zoom_rect() {
+        assert_eq!(zoom_rect((100, 100, 40, 20), 1.5), (90, 95, 60, 30));
+        assert_eq!(zoom_rect((100, 100, 40, 20), 1.0), (100, 100, 40, 20));
+        assert!(contains((100, 100, 40, 20), (100, 119)));
+        assert!(!contains((100, 100, 40, 20), (140, 110)));
+    }
+
+    #[test]
+    fn test_apply_frame_extents() {
+        let mut dw = DesktopWindow {
+            pos: (100, 50),
+            size: (852, 652),
+            ..Default::default()
+        };
+        apply_frame_extents(&mut dw, [26, 26, 23, 29]);
+        assert_eq!((dw.pos, dw.size), ((126, 73), (800, 600)));
+        apply_frame_extents(&mut dw, [400, 400, 0, 0]);
+        assert_eq!((dw.pos, dw.size), ((126, 73), (800, 600)));
+    }
+
+    #[test]
+    fn test_fit_scale() {
+        assert_eq!(fit_scale((50.0, 20.0), (100, 100)), 1.0);
+        assert_eq!(fit_scale((200.0, 20.0), (100, 100)), 0.5);
+        assert_eq!(fit_scale((50.0, 40.0), (100, 10)), 0.25);
+        assert_eq!(fit_scale((0.0, 0.0), (100, 10)), 1.0);
+    }
+
+    #[test]
+    fn test_grow_scale() {
+        assert_eq!(grow_scale((50.0, 20.0), (800, 400), 0.25), 4.0);
+        assert_eq!(grow_scale((50.0, 20.0), (800, 40), 0.25), 0.5);
+        assert_eq!(grow_scale((0.0, 0.0), (800, 400), 0.25), 1.0);
+    }
+
+    #[test]
+    fn test_place_rect() {
+        let window = (0, 0, 200, 100);
+        let hint = (0, 0, 40, 20);
+
+        assert_eq!(place_rect(hint, &[], window), (0, 0));
+        // Right next to what's in the way.
+        assert_eq!(place_rect(hint, &[(0, 0, 40, 20)], window), (40, 0));
+        // Going right would leave the window, so go down instead.
+        assert_eq!(place_rect(hint, &[(0, 0, 190, 20)], window), (0, 20));
+        // Up is fine as well.
+        assert_eq!(
+            place_rect((150, 80, 40, 20), &[(0, 80, 200, 20)], window),
+            (150, 60)
+        );
+        // No room in the window at all, so stack up in a column.
+        let full = [(0, 0, 200, 60), (0, 60, 200, 40)];
+        assert_eq!(place_rect(hint, &full, window), (0, 100));
+        assert_eq!(
+            place_rect(hint, &[(0, 0, 40, 20), (0, 20, 40, 20)], (0, 0, 40, 40)),
+            (0, 40)
+        );
+    }
+
+    #[test]
+    fn test_follow_rect() {
+        let hint = (10, 20, 30, 15);
+        let old = (0, 0, 100, 100);
+
+        // Moving takes the hint along no matter where it is anchored.
+        let moved = (50, -10, 100, 100);
+        assert_eq!(follow_rect(hint, old, moved, (0.0, 0.0)), (60, 10));
+        assert_eq!(follow_rect(hint, old, moved, (0.5, 1.0)), (60, 10));
+
+        // Resizing only moves hints that aren't anchored at the top left corner.
+        let resized = (0, 0, 200, 50);
+        assert_eq!(follow_rect(hint, old, resized, (0.0, 0.0)), (10, 20));
+        assert_eq!(follow_rect(hint, old, resized, (0.5, 0.5)), (60, -5));
+        assert_eq!(follow_rect(hint, old, resized, (1.0, 1.0)), (110, -30));
+    }
+
+    #[test]
+    fn test_visible_parts() {
+        let rect = (0, 0, 100, 100);
+
+        assert_eq!(visible_parts(rect, &[]), vec![rect]);
+        assert_eq!(visible_parts(rect, &[(200, 200, 10, 10)]), vec![rect]);
+        assert!(visible_parts(rect, &[(-10, -10, 200, 200)]).is_empty());
+
+        // Covering the left half leaves the right half.
+        assert_eq!(
+            visible_parts(rect, &[(0, 0, 50, 100)]),
+            vec![(50, 0, 50, 100)]
+        );
+
+        // A hole in the middle leaves four parts that add up to the rest.
+        let parts = visible_parts(rect, &[(25, 25, 50, 50)]);
+        assert_eq!(parts.len(), 4);
+        assert_eq!(
+            parts.iter().map(|r| r.2 * r.3).sum::<i32>(),
+            100 * 100 - 50 * 50
+        );
+    }
+
+    #[test]
+    fn test_handle_occlusion() {
+        let window = |x_window_id, pos, size| DesktopWindow {
+            x_window_id: Some(x_window_id),
+            pos,
+            size,
+            ..Default::default()
+        };
+        let dws = vec![
+            window(1, (0, 0), (100, 100)),
+            window(2, (0, 0), (50, 100)),
+            window(3, (200, 0), (10, 10)),
+            window(4, (190, 0), (30, 30)),
+        ];
+
+        let dws = handle_occlusion(dws, &[1, 3, 2, 4]);
+        assert_eq!(dws.len(), 3);
+        assert_eq!(dws[0].pos, (50, 0));
+        assert_eq!(dws[0].size, (50, 100));
+        assert_eq!(dws[1].x_window_id, Some(2));
+        assert_eq!(dws[2].x_window_id, Some(4));
+    }
+
+    #[test]
+    fn test_transform_rect() {
+        let size = (1080, 1920);
+        let rect = (0, 0, 100, 200);
+
+        assert_eq!(transform_rect(rect, size, Transform::Normal), rect);
+        assert_eq!(
+            transform_rect(rect, size, Transform::Rotate90),
+            (1720, 0, 200, 100)
+        );
+        assert_eq!(
+            transform_rect(rect, size, Transform::Rotate180),
+            (980, 1720, 100, 200)
+        );
+        assert_eq!(
+            transform_rect(rect, size, Transform::Rotate270),
+            (0, 980, 200, 100)
+        );
+        assert_eq!(
+            transform_rect(rect, size, Transform::Flipped),
+            (980, 0, 100, 200)
+        );
+
+        // Everything must still lie within the untransformed output.
+        for transform in [
+            Transform::Rotate90,
+            Transform::Rotate270,
+            Transform::Flipped90,
+            Transform::Flipped270,
+        ] {
+            let (x, y, w, h) = transform_rect((500, 1500, 580, 420), size, transform);
+            assert!(x >= 0 && y >= 0 && x + w <= size.1 && y + h <= size.0);
+        }
+    }
+
+    #[test]
+    fn test_apply_output_geometry() {
+        let outputs = vec![DesktopOutput {
+            name: "DP-1".to_string(),
+            rect: (1920, 0, 1080, 1920),
+            transform: Transform::Rotate90,
+            scale: 1.0,
+        }];
+        let mut dws = vec![DesktopWindow {
+            pos: (1920, 0),
+            size: (100, 200),
+            output: Some("DP-1".to_string()),
+            ..Default::default()
+        }];
+
+        // X already agrees with the window manager, nothing to do.
+        let mut monitors = vec![DesktopOutput {
+            name: "DP-1".to_string(),
+            rect: (1920, 0, 1080, 1920),
+            transform: Transform::Normal,
+            scale: 1.0,
+        }];
+        apply_output_geometry(&mut dws, &outputs, &monitors);
+        assert_eq!((dws[0].pos, dws[0].size), ((1920, 0), (100, 200)));
+
+        monitors[0].rect = (1920, 0, 1920, 1080);
+        apply_output_geometry(&mut dws, &outputs, &monitors);
+        assert_eq!((dws[0].pos, dws[0].size), ((1920 + 1720, 0), (200, 100)));
+    }
+
+    #[test]
+    fn test_apply_output_geometry_scaled() {
+        let outputs = vec![DesktopOutput {
+            name: "eDP-1".to_string(),
+            rect: (0, 0, 1280, 720),
+            transform: Transform::Normal,
+            scale: 1.5,
+        }];
+        let monitors = vec![DesktopOutput {
+            name: "eDP-1".to_string(),
+            rect: (0, 0, 1920, 1080),
+            transform: Transform::Normal,
+            scale: 1.0,
+        }];
+        let mut dws = vec![DesktopWindow {
+            pos: (640, 100),
+            size: (640, 620),
+            output: Some("eDP-1".to_string()),
+            ..Default::default()
+        }];
+
+        apply_output_geometry(&mut dws, &outputs, &monitors);
+        assert_eq!((dws[0].pos, dws[0].size), ((960, 150), (960, 930)));
+    }
+
+    #[test]
+    fn test_clamp_to_rect() {
+        let bounds = (1920, 0, 1920, 1080);
+
+        assert_eq!(clamp_to_rect((2000, 100, 50, 50), bounds), (2000, 100));
+        assert_eq!(clamp_to_rect((3900, 100, 50, 50), bounds), (3790, 100));
+        assert_eq!(clamp_to_rect((1900, -20, 50, 50), bounds), (1920, 0));
+        assert_eq!(clamp_to_rect((2000, 1070, 50, 50), bounds), (2000, 1030));
+        assert_eq!(clamp_to_rect((2000, 100, 3000, 50), bounds), (1920, 100));
+    }
+
+    #[test]
+    fn test_find_monitor() {
+        let monitor = |name: &str, rect| DesktopOutput {
+            name: name.to_string(),
+            rect,
+            transform: Transform::Normal,
+            scale: 1.0,
+        };
+        let monitors = vec![
+            monitor("left", (0, 0, 1920, 1080)),
+            monitor("right", (1920, 0, 1920, 1080)),
+        ];
+        let mut dw = DesktopWindow {
+            pos: (1800, 0),
+            size: (400, 400),
+            ..Default::default()
+        };
+
+        assert_eq!(find_monitor(&dw, &monitors).unwrap().name, "right");
+        dw.output = Some("left".to_string());
+        assert_eq!(find_monitor(&dw, &monitors).unwrap().name, "left");
+        assert!(find_monitor(&dw, &[]).is_none());
+    }
+
+    #[test]
+    fn test_find_spanned_monitor() {
+        let monitor = |name: &str, rect| DesktopOutput {
+            name: name.to_string(),
+            rect,
+            transform: Transform::Normal,
+            scale: 1.0,
+        };
+        let monitors = vec![
+            monitor("left", (0, 0, 1920, 1080)),
+            monitor("right", (1920, 0, 1920, 1080)),
+        ];
+
+        // Mostly on the right monitor even though the window manager puts it on the left one.
+        let spanning = DesktopWindow {
+            pos: (1800, 100),
+            size: (600, 400),
+            output: Some("left".to_string()),
+            ..Default::default()
+        };
+        let (monitor, part) = find_spanned_monitor(&spanning, &monitors).unwrap();
+        assert_eq!(monitor.name, "right");
+        assert_eq!(part, (1920, 100, 480, 400));
+
+        let contained = DesktopWindow {
+            pos: (100, 100),
+            size: (600, 400),
+            ..Default::default()
+        };
+        assert!(find_spanned_monitor(&contained, &monitors).is_none());
+    }
+
+    #[test]
+    fn test_grid_cells() {
+        let area = DesktopWindow {
+            pos: (100, 50),
+            size: (1000, 601),
+            output: Some("DP-1".to_owned()),
+            ..Default::default()
+        };
+        let cells = grid_cells(&area, (3, 2));
+        let rects: Vec<_> = cells.iter().map(|c| (c.pos, c.size)).collect();
+        assert_eq!(
+            rects,
+            vec![
+                ((100, 50), (333, 300)),
+                ((433, 50), (333, 300)),
+                ((766, 50), (334, 300)),
+                ((100, 350), (333, 301)),
+                ((433, 350), (333, 301)),
+                ((766, 350), (334, 301)),
+            ]
+        );
+        assert!(cells.iter().all(|c| c.output.as_deref() == Some("DP-1")));
+    }
+}
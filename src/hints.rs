@@ -0,0 +1,280 @@
+//! Assigning hints to the things they are shown for.
+
+use std::collections::VecDeque;
+use std::iter;
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use itertools::Itertools;
+use log::debug;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::geometry::Rect;
+use crate::DesktopWindow;
+
+/// How `HintGenerator` makes up hints.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HintStrategy {
+    /// All hints are equally long
+    #[default]
+    Fixed,
+    /// Some hints are shorter than others so that fewer keys have to be typed on average
+    Mixed,
+}
+
+/// Makes up hints from a set of characters.
+///
+/// No matter the strategy, all hints are different, no hint is the start of another one (so a
+/// hint is selected as soon as it's typed) and no hint is longer than necessary for the number of
+/// hints.
+#[derive(Debug, Clone)]
+pub struct HintGenerator {
+    hints: std::vec::IntoIter<String>,
+}
+
+impl HintGenerator {
+    /// Hints for `count` things made of the characters in `hint_chars` (duplicates are ignored).
+    pub fn new(hint_chars: &str, count: usize, strategy: HintStrategy) -> Result<HintGenerator> {
+        let chars: Vec<char> = hint_chars.chars().unique().collect();
+        if chars.is_empty() {
+            bail!("No hint_chars found");
+        }
+        if chars.len() < 2 && count > 1 {
+            bail!("More than one hint needs at least two hint_chars");
+        }
+        let hints = match strategy {
+            HintStrategy::Fixed => fixed_hints(&chars, count),
+            HintStrategy::Mixed => mixed_hints(&chars, count),
+        };
+        debug!("Hints: {:?}", hints);
+        Ok(HintGenerator {
+            hints: hints.into_iter(),
+        })
+    }
+}
+
+impl Iterator for HintGenerator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        self.hints.next()
+    }
+}
+
+/// The first `count` combinations of `chars` of the shortest length that has enough of them.
+fn fixed_hints(chars: &[char], count: usize) -> Vec<String> {
+    let mut length = 1;
+    while chars.len().pow(length) < count {
+        length += 1;
+    }
+    iter::repeat(chars.iter())
+        .take(length as usize)
+        .multi_cartesian_product()
+        .take(count)
+        .map(|combination| combination.into_iter().collect())
+        .collect()
+}
+
+/// Hints of different lengths, the shorter ones first.
+///
+/// This starts out with one hint per character and then keeps replacing the first hint with one
+/// hint per character appended to it until there are enough.
+fn mixed_hints(chars: &[char], count: usize) -> Vec<String> {
+    let mut hints = VecDeque::from([String::new()]);
+    while hints.len() < count || hints.front().is_some_and(String::is_empty) {
+        let prefix = hints.pop_front().unwrap_or_default();
+        hints.extend(chars.iter().map(|c| format!("{prefix}{c}")));
+    }
+    hints.into_iter().take(count).collect()
+}
+
+/// Hints for `rects` in the same order as `rects`.
+///
+/// Like wmfocus itself does, hints are handed out row by row from the top left.
+pub fn assign_hints(
+    rects: &[Rect],
+    hint_chars: &str,
+    strategy: HintStrategy,
+) -> Result<Vec<String>> {
+    let mut order: Vec<usize> = (0..rects.len()).collect();
+    order.sort_by_key(|&i| (rects[i].1, rects[i].0));
+    let mut hints = vec![String::new(); rects.len()];
+    for (i, hint) in order
+        .into_iter()
+        .zip(HintGenerator::new(hint_chars, rects.len(), strategy)?)
+    {
+        hints[i] = hint;
+    }
+    Ok(hints)
+}
+
+/// Split `hint` into the part that's shown as typed with `pressed_keys` typed and the rest.
+///
+/// Nothing counts as typed unless `hint` starts with all of `pressed_keys`. The split is always
+/// between graphemes, so a character with a combining mark only counts as typed as a whole.
+pub fn split_typed<'a>(hint: &'a str, pressed_keys: &str) -> (&'a str, &'a str) {
+    if !hint.starts_with(pressed_keys) {
+        return ("", hint);
+    }
+    let end = hint
+        .grapheme_indices(true)
+        .map(|(i, grapheme)| i + grapheme.len())
+        .take_while(|end| *end <= pressed_keys.len())
+        .last()
+        .unwrap_or_default();
+    hint.split_at(end)
+}
+
+/// How many bytes of `hint` are shown as typed with `pressed_keys` typed, which is all that
+/// decides how a hint looks.
+pub fn typed_len(hint: &str, pressed_keys: &str) -> usize {
+    split_typed(hint, pressed_keys).0.len()
+}
+
+/// Split `dws` into pages of at most `max_per_page` `DesktopWindow`s.
+///
+/// The largest windows end up on the first page. Within a page, the original order is kept. There
+/// is always at least one (possibly empty) page.
+pub fn paginate(dws: &[DesktopWindow], max_per_page: Option<u32>) -> Vec<Vec<&DesktopWindow>> {
+    let Some(max_per_page) = max_per_page.filter(|_| !dws.is_empty()) else {
+        return vec![dws.iter().collect()];
+    };
+    let mut indices: Vec<usize> = (0..dws.len()).collect();
+    indices.sort_by_key(|&i| std::cmp::Reverse(dws[i].size.0 * dws[i].size.1));
+    indices
+        .chunks(max_per_page as usize)
+        .map(|chunk| {
+            let mut chunk = chunk.to_vec();
+            chunk.sort_unstable();
+            chunk.into_iter().map(|i| &dws[i]).collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typed_len() {
+        assert_eq!(typed_len("sa", ""), 0);
+        assert_eq!(typed_len("sa", "s"), 1);
+        assert_eq!(typed_len("sa", "d"), 0);
+        // Hints that didn't match before and still don't look the same.
+        assert_eq!(typed_len("da", "s"), typed_len("da", "sd"));
+    }
+
+    #[test]
+    fn test_split_typed() {
+        assert_eq!(split_typed("sa", ""), ("", "sa"));
+        assert_eq!(split_typed("sa", "s"), ("s", "a"));
+        assert_eq!(split_typed("sa", "sa"), ("sa", ""));
+        assert_eq!(split_typed("sa", "d"), ("", "sa"));
+        assert_eq!(split_typed("äö", "ä"), ("ä", "ö"));
+        // Regex metacharacters are just characters.
+        assert_eq!(split_typed(".*", "."), (".", "*"));
+        assert_eq!(split_typed("ab", "."), ("", "ab"));
+        // A combining mark belongs to the character before it.
+        assert_eq!(split_typed("e\u{301}x", "e"), ("", "e\u{301}x"));
+        assert_eq!(split_typed("e\u{301}x", "e\u{301}"), ("e\u{301}", "x"));
+    }
+
+    fn hints(hint_chars: &str, count: usize, strategy: HintStrategy) -> Vec<String> {
+        HintGenerator::new(hint_chars, count, strategy)
+            .unwrap()
+            .collect()
+    }
+
+    #[test]
+    fn test_hint_generator() {
+        assert_eq!(hints("asdf", 3, HintStrategy::Fixed), vec!["a", "s", "d"]);
+        assert_eq!(
+            hints("asdf", 5, HintStrategy::Fixed),
+            vec!["aa", "as", "ad", "af", "sa"]
+        );
+        assert_eq!(
+            hints("asdf", 5, HintStrategy::Mixed),
+            vec!["s", "d", "f", "aa", "as"]
+        );
+        assert_eq!(hints("a", 1, HintStrategy::Fixed), vec!["a"]);
+        assert!(hints("asdf", 0, HintStrategy::Mixed).is_empty());
+        assert!(HintGenerator::new("", 1, HintStrategy::Fixed).is_err());
+        assert!(HintGenerator::new("aa", 2, HintStrategy::Mixed).is_err());
+    }
+
+    /// Check the guarantees of `HintGenerator` for lots of combinations of characters and counts.
+    #[test]
+    fn test_hint_generator_properties() {
+        for hint_chars in ["ab", "abc", "asdf", "sadfjklewcmpgh", "äöü", "aab"] {
+            let chars: Vec<char> = hint_chars.chars().unique().collect();
+            for count in (0..=300).chain([1000, 5000]) {
+                // The shortest length that has enough combinations for all hints.
+                let minimal_length = (1..)
+                    .find(|&length| chars.len().pow(length) >= count)
+                    .unwrap() as usize;
+                for strategy in [HintStrategy::Fixed, HintStrategy::Mixed] {
+                    let case = format!("{count} hints of {hint_chars:?} with {strategy:?}");
+                    let mut hints = hints(hint_chars, count, strategy);
+                    assert_eq!(hints.len(), count, "{case}");
+                    assert!(
+                        hints.iter().all(|h| h.chars().all(|c| chars.contains(&c))),
+                        "{case}"
+                    );
+                    let lengths = hints.iter().map(|h| h.chars().count());
+                    match strategy {
+                        HintStrategy::Fixed => {
+                            assert!(lengths.clone().all(|l| l == minimal_length), "{case}")
+                        }
+                        HintStrategy::Mixed => {
+                            assert!(lengths.max().unwrap_or(1) <= minimal_length, "{case}")
+                        }
+                    }
+                    // After sorting, a hint that is the start of others comes right before them.
+                    hints.sort();
+                    for pair in hints.windows(2) {
+                        assert_ne!(pair[0], pair[1], "{case}");
+                        assert!(!pair[1].starts_with(&pair[0]), "{case}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_assign_hints() {
+        let rects = [(960, 0, 960, 1080), (0, 540, 960, 540), (0, 0, 960, 540)];
+        assert_eq!(
+            assign_hints(&rects, "asdf", HintStrategy::Fixed).unwrap(),
+            vec!["s", "d", "a"]
+        );
+        assert!(assign_hints(&rects, "", HintStrategy::Fixed).is_err());
+        assert!(assign_hints(&rects, "a", HintStrategy::Fixed).is_err());
+    }
+
+    #[test]
+    fn test_paginate() {
+        let dws: Vec<DesktopWindow> = [(10, 10), (30, 30), (20, 20), (40, 40), (5, 5)]
+            .into_iter()
+            .enumerate()
+            .map(|(i, size)| DesktopWindow {
+                id: i as i64,
+                size,
+                ..Default::default()
+            })
+            .collect();
+
+        let ids = |page: &Vec<&DesktopWindow>| page.iter().map(|dw| dw.id).collect::<Vec<_>>();
+
+        let pages = paginate(&dws, None);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(ids(&pages[0]), vec![0, 1, 2, 3, 4]);
+
+        let pages = paginate(&dws, Some(2));
+        assert_eq!(pages.len(), 3);
+        assert_eq!(ids(&pages[0]), vec![1, 3]);
+        assert_eq!(ids(&pages[1]), vec![0, 2]);
+        assert_eq!(ids(&pages[2]), vec![4]);
+
+        assert_eq!(paginate(&[], Some(2)).len(), 1);
+    }
+}
@@ -0,0 +1,240 @@
+//! Key chords like `Control_L+g` as used for exit keys and all other key bindings.
+
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// Modifiers in the order they are written in, by the names of their left and right keys.
+const MODIFIERS: &[(&str, &[&str])] = &[
+    ("Control", &["Control_L", "Control_R", "Ctrl"]),
+    ("Shift", &["Shift_L", "Shift_R"]),
+    ("Alt", &["Alt_L", "Alt_R"]),
+    ("Meta", &["Meta_L", "Meta_R"]),
+    ("Super", &["Super_L", "Super_R"]),
+    ("Hyper", &["Hyper_L", "Hyper_R"]),
+];
+
+/// The canonical name of `key`, which is the same for the left and right versions of a modifier.
+pub fn normalize_key(key: &str) -> &str {
+    MODIFIERS
+        .iter()
+        .find(|(name, aliases)| key == *name || aliases.contains(&key))
+        .map_or(key, |(name, _)| name)
+}
+
+/// Bits of the modifier state of X key events and the modifiers they usually stand for.
+const MODIFIER_MASKS: &[(u16, &str)] = &[(1, "Shift"), (4, "Control"), (8, "Alt"), (64, "Super")];
+
+/// Whether `key` is one of the modifiers that are held down along with other keys.
+pub fn is_modifier(key: &str) -> bool {
+    MODIFIERS
+        .iter()
+        .any(|(name, _)| *name == normalize_key(key))
+}
+
+/// Where `key` (already normalized) goes in a `Sequence`: modifiers first, in a fixed order.
+fn sort_key(key: &str) -> (usize, String, &str) {
+    let rank = MODIFIERS
+        .iter()
+        .position(|(name, _)| *name == key)
+        .unwrap_or(MODIFIERS.len());
+    (rank, key.to_lowercase(), key)
+}
+
+/// Keys that are held down together, eg `Control_L+g`.
+///
+/// Keys are X keysym names. The order of the keys doesn't matter and modifiers match no matter
+/// which side of the keyboard they're on, so `g+Control_R` is the same as `Control_L+g`. Formatting
+/// gives the canonical form, `Control+g` for both of those.
+#[derive(Debug, Clone, Default)]
+pub struct Sequence {
+    /// The keys as they were pressed, so that releasing one side of a modifier keeps the other one
+    /// held.
+    pressed: Vec<String>,
+}
+
+impl Sequence {
+    /// Add `key` after it was pressed.
+    pub fn push(&mut self, key: &str) {
+        if !self.pressed.iter().any(|k| k == key) {
+            self.pressed.push(key.to_string());
+        }
+    }
+
+    /// Remove `key` after it was released.
+    pub fn remove(&mut self, key: &str) {
+        self.pressed.retain(|k| k != key);
+    }
+
+    /// Whether this is a chord rather than a single key, ie more than one key is held down.
+    pub fn is_started(&self) -> bool {
+        self.keys().count() > 1
+    }
+
+    /// This sequence along with the modifiers active in `state` (the modifier state of an X key
+    /// event).
+    ///
+    /// With sticky keys, modifiers are latched or locked instead of being held down, so they're
+    /// pressed and released before the key they go with.
+    pub fn with_modifiers(&self, state: u16) -> Sequence {
+        let mut sequence = self.clone();
+        for (mask, modifier) in MODIFIER_MASKS {
+            if state & mask != 0 {
+                sequence.push(modifier);
+            }
+        }
+        sequence
+    }
+
+    /// The keys in canonical order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        let mut keys: Vec<_> = self.pressed.iter().map(|key| normalize_key(key)).collect();
+        keys.sort_by(|a, b| sort_key(a).cmp(&sort_key(b)));
+        keys.dedup();
+        keys.into_iter()
+    }
+}
+
+impl PartialEq for Sequence {
+    fn eq(&self, other: &Sequence) -> bool {
+        self.keys().eq(other.keys())
+    }
+}
+
+impl Eq for Sequence {}
+
+impl Hash for Sequence {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for key in self.keys() {
+            key.hash(state);
+        }
+    }
+}
+
+impl FromStr for Sequence {
+    type Err = String;
+
+    /// Parse keys separated by `+`.
+    fn from_str(s: &str) -> Result<Sequence, String> {
+        let mut sequence = Sequence::default();
+        for key in s.split('+').map(str::trim) {
+            if key.is_empty() {
+                return Err(format!("Invalid key sequence '{s}'"));
+            }
+            sequence.push(key);
+        }
+        Ok(sequence)
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.keys().collect::<Vec<_>>().join("+"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(s: &str) -> Sequence {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_sequences_equal() {
+        let a = sequence("Control_L+Shift_L+a");
+        let b = sequence("Control_L+a+Shift_L");
+
+        assert_eq!(a, b);
+
+        let mut c = Sequence::default();
+
+        c.push("Shift_L");
+        c.push("Control_L");
+        c.push("a");
+
+        assert_eq!(a, c);
+    }
+
+    #[test]
+    fn test_sequences_not_equal() {
+        let a = sequence("Control_L+Shift_L+a");
+        let b = sequence("Control_L+a");
+
+        assert_ne!(a, b);
+
+        let mut c = Sequence::default();
+
+        c.push("Shift_L");
+        c.push("a");
+
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn test_sequences_is_started() {
+        let mut sequence = Sequence::default();
+        assert!(!sequence.is_started());
+
+        sequence.push("Control_L");
+        assert!(!sequence.is_started());
+
+        sequence.push("g");
+        assert!(sequence.is_started());
+
+        sequence.remove("g");
+
+        assert!(!sequence.is_started());
+    }
+
+    #[test]
+    fn test_sequences_normalized() {
+        assert_eq!(sequence("Control_L+g"), sequence("g+Control_R"));
+        assert_eq!(sequence("Ctrl+g"), sequence("Control+g"));
+        assert_eq!(
+            sequence("g+Shift_R+Control_L").to_string(),
+            "Control+Shift+g"
+        );
+        assert_eq!(sequence("b+A+c").to_string(), "A+b+c");
+        assert_ne!(sequence("a"), sequence("A"));
+
+        // Holding both Control keys still counts as one, and Control stays held until both are
+        // released.
+        let mut held = Sequence::default();
+        held.push("Control_L");
+        held.push("Control_R");
+        assert!(!held.is_started());
+        assert_eq!(held, sequence("Control"));
+        held.remove("Control_R");
+        assert_eq!(held, sequence("Control"));
+        held.push("g");
+        assert!(held.is_started());
+        held.remove("Control_L");
+        assert_eq!(held, sequence("g"));
+
+        assert!("Control_L++g".parse::<Sequence>().is_err());
+        assert!("".parse::<Sequence>().is_err());
+    }
+
+    #[test]
+    fn test_sequences_with_modifiers() {
+        let mut typed = Sequence::default();
+        typed.push("g");
+        // Control latched by sticky keys, along with Num Lock.
+        assert_eq!(typed.with_modifiers(4 | 16), sequence("Control_L+g"));
+        assert_eq!(typed.with_modifiers(0), sequence("g"));
+
+        // Modifiers that are held down anyway aren't added twice.
+        typed.push("Control_R");
+        assert_eq!(typed.with_modifiers(4 | 1), sequence("Control+Shift+g"));
+    }
+
+    #[test]
+    fn test_is_modifier() {
+        assert!(is_modifier("Control_R"));
+        assert!(is_modifier("Super"));
+        assert!(!is_modifier("g"));
+        assert!(!is_modifier("Escape"));
+    }
+}
@@ -0,0 +1,266 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::Deserialize;
+use serde_json::json;
+
+use wmfocus::DesktopWindow;
+
+/// Titles are cut off after this many characters so that window hints don't get too wide.
+const MAX_TITLE_CHARS: usize = 60;
+
+/// Remote control commands are wrapped in this and `COMMAND_END`.
+const COMMAND_START: &[u8] = b"\x1bP@kitty-cmd";
+
+/// End of a remote control command or response.
+const COMMAND_END: &[u8] = b"\x1b\\";
+
+/// The version of the remote control protocol we speak.
+const PROTOCOL_VERSION: [u32; 3] = [0, 14, 2];
+
+#[derive(Debug, Deserialize)]
+struct OsWindow {
+    platform_window_id: Option<u32>,
+    is_focused: bool,
+    tabs: Vec<Tab>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tab {
+    title: String,
+    windows: Vec<Window>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Window {
+    id: i64,
+    title: String,
+    is_focused: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    ok: bool,
+    data: Option<String>,
+    error: Option<String>,
+}
+
+/// Whether `dw` is a kitty window we might be able to hint the kitty windows of.
+pub fn is_kitty(dw: &DesktopWindow) -> bool {
+    dw.class
+        .as_deref()
+        .is_some_and(|class| class.eq_ignore_ascii_case("kitty"))
+}
+
+/// Connect to the kitty instance with `pid` listening on `address` (as given to kitty's
+/// `listen_on`, without the `unix:`).
+///
+/// kitty appends its pid to the address so that every instance gets its own socket, but we'll
+/// also try the address as it is for those configured with `{kitty_pid}` somewhere else.
+fn connect(address: &str, pid: Option<u32>) -> Result<UnixStream> {
+    let address = address.strip_prefix("unix:").unwrap_or(address);
+    let candidates = pid
+        .map(|pid| format!("{address}-{pid}"))
+        .into_iter()
+        .chain([address.to_string()]);
+    for candidate in candidates {
+        let connected = match candidate.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name)
+                .and_then(|addr| UnixStream::connect_addr(&addr)),
+            None => UnixStream::connect(&candidate),
+        };
+        match connected {
+            Ok(stream) => return Ok(stream),
+            Err(e) => debug!("Couldn't connect to kitty at {candidate}: {e}"),
+        }
+    }
+    bail!("Couldn't connect to kitty at {address}, is remote control enabled with listen_on?")
+}
+
+/// Frame the remote control command `cmd` with `payload`.
+fn encode_command(cmd: &str, payload: serde_json::Value) -> Vec<u8> {
+    let command = json!({"cmd": cmd, "version": PROTOCOL_VERSION, "payload": payload});
+    let mut encoded = COMMAND_START.to_vec();
+    encoded.extend(command.to_string().as_bytes());
+    encoded.extend(COMMAND_END);
+    encoded
+}
+
+/// Unwrap what kitty answered to a command, `None` if it answered without data.
+fn decode_response(response: &[u8]) -> Result<Option<String>> {
+    let json = response
+        .strip_prefix(COMMAND_START)
+        .and_then(|rest| rest.strip_suffix(COMMAND_END))
+        .context("Got invalid response from kitty")?;
+    let response: Response =
+        serde_json::from_slice(json).context("Couldn't parse response from kitty")?;
+    if !response.ok {
+        bail!(
+            "kitty couldn't handle the command: {}",
+            response.error.unwrap_or_default()
+        );
+    }
+    Ok(response.data)
+}
+
+/// Send the remote control command `cmd` to kitty and wait for the answer.
+fn send_command(
+    address: &str,
+    pid: Option<u32>,
+    cmd: &str,
+    payload: serde_json::Value,
+) -> Result<Option<String>> {
+    let stream = connect(address, pid)?;
+    (&stream)
+        .write_all(&encode_command(cmd, payload))
+        .context("Couldn't send command to kitty")?;
+    // The response starts and ends with an escape character, which the JSON in between has
+    // escaped.
+    let mut reader = BufReader::new(&stream);
+    let mut response = vec![];
+    for _ in 0..2 {
+        reader
+            .read_until(COMMAND_END[0], &mut response)
+            .context("Couldn't receive response from kitty")?;
+    }
+    let mut last = [0];
+    reader
+        .read_exact(&mut last)
+        .context("Couldn't receive response from kitty")?;
+    response.extend(last);
+    decode_response(&response)
+}
+
+/// Turn the kitty windows in all tabs of the OS window showing `window` into things to hint, listed
+/// in the middle of `window`.
+///
+/// kitty doesn't tell where its windows are, so their hints can't go onto them.
+fn windows_to_hint(os_windows: Vec<OsWindow>, window: &DesktopWindow) -> Vec<DesktopWindow> {
+    let Some(os_window) = os_windows
+        .iter()
+        .find(|os_window| {
+            os_window.platform_window_id.is_some()
+                && os_window.platform_window_id == window.x_window_id.map(|id| id as u32)
+        })
+        .or_else(|| os_windows.iter().find(|os_window| os_window.is_focused))
+    else {
+        return vec![];
+    };
+
+    os_window
+        .tabs
+        .iter()
+        .flat_map(|tab| {
+            tab.windows.iter().map(move |kitty_window| {
+                // Tabs with a single window are named after it anyway.
+                let title = if tab.windows.len() > 1 {
+                    format!("{}: {}", tab.title, kitty_window.title)
+                } else {
+                    kitty_window.title.clone()
+                };
+                let mut label: String = title.chars().take(MAX_TITLE_CHARS).collect();
+                if title.chars().count() > MAX_TITLE_CHARS {
+                    label.push('…');
+                }
+                DesktopWindow {
+                    id: kitty_window.id,
+                    pos: window.pos,
+                    size: window.size,
+                    is_focused: kitty_window.is_focused,
+                    class: window.class.clone(),
+                    title: Some(kitty_window.title.clone()),
+                    workspace: window.workspace.clone(),
+                    output: window.output.clone(),
+                    label: Some(label),
+                    is_listed: true,
+                    ..Default::default()
+                }
+            })
+        })
+        .collect()
+}
+
+/// Ask the kitty instance with `pid` listening on `address` for the kitty windows of `window`.
+pub fn get_windows(
+    window: &DesktopWindow,
+    address: &str,
+    pid: Option<u32>,
+) -> Result<Vec<DesktopWindow>> {
+    let data =
+        send_command(address, pid, "ls", json!({}))?.context("kitty didn't list its windows")?;
+    let os_windows: Vec<OsWindow> =
+        serde_json::from_str(&data).context("Unexpected list of windows from kitty")?;
+    Ok(windows_to_hint(os_windows, window))
+}
+
+/// Make kitty focus `kitty_window` (and switch to its tab).
+pub fn focus_window(kitty_window: &DesktopWindow, address: &str, pid: Option<u32>) -> Result<()> {
+    send_command(
+        address,
+        pid,
+        "focus-window",
+        json!({"match": format!("id:{}", kitty_window.id)}),
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let encoded = encode_command("ls", json!({}));
+        assert!(encoded.starts_with(b"\x1bP@kitty-cmd{"));
+        assert!(encoded.ends_with(b"}\x1b\\"));
+
+        let response = br#"{"ok": true, "data": "[]"}"#;
+        let framed = [COMMAND_START, response, COMMAND_END].concat();
+        assert_eq!(decode_response(&framed).unwrap().as_deref(), Some("[]"));
+        let failed = [
+            COMMAND_START,
+            br#"{"ok": false, "error": "nope"}"#,
+            COMMAND_END,
+        ]
+        .concat();
+        assert!(decode_response(&failed).is_err());
+        assert!(decode_response(response).is_err());
+    }
+
+    #[test]
+    fn test_windows_to_hint() {
+        let os_windows: Vec<OsWindow> = serde_json::from_value(json!([
+            {"platform_window_id": 1, "is_focused": true, "tabs": [
+                {"title": "other", "windows": [{"id": 1, "title": "other", "is_focused": true}]},
+            ]},
+            {"platform_window_id": 0x1a00003, "is_focused": false, "tabs": [
+                {"title": "vim", "windows": [{"id": 2, "title": "vim", "is_focused": false}]},
+                {"title": "build", "windows": [
+                    {"id": 3, "title": "cargo", "is_focused": true},
+                    {"id": 4, "title": "zsh", "is_focused": false},
+                ]},
+            ]},
+        ]))
+        .unwrap();
+        let window = DesktopWindow {
+            x_window_id: Some(0x1a00003),
+            class: Some("kitty".to_string()),
+            pos: (0, 24),
+            size: (960, 1056),
+            ..Default::default()
+        };
+        let windows = windows_to_hint(os_windows, &window);
+        assert_eq!(
+            windows.iter().map(|w| w.id).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert_eq!(windows[0].label.as_deref(), Some("vim"));
+        assert_eq!(windows[1].label.as_deref(), Some("build: cargo"));
+        assert!(windows[1].is_focused && windows[1].is_listed);
+        assert_eq!(windows[2].pos, window.pos);
+        assert!(is_kitty(&window));
+    }
+}
@@ -0,0 +1,93 @@
+//! Everything wmfocus does that doesn't depend on a particular window manager: which hints things
+//! get and where they go (`hints`, `geometry`), and showing them and waiting for a selection
+//! (`overlay`).
+//!
+//! The window manager itself is behind the `backend::Backend` trait. The `wmfocus` binary
+//! implements it for i3 and sway and builds on top of this to get windows and focus them.
+
+use std::process::ExitCode;
+
+pub mod args;
+pub mod backend;
+pub mod color;
+pub mod dbus;
+pub mod event_loop;
+pub mod events;
+pub mod expose;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod geometry;
+pub mod hints;
+pub mod keys;
+pub mod overlay;
+pub mod stats;
+pub mod utils;
+
+/// How wmfocus exits, so that scripts can tell why nothing was selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// Something was selected.
+    Selected = 0,
+    /// The user canceled.
+    Canceled = 1,
+    /// There was nothing to show hints for.
+    NoWindows = 2,
+    /// Keyboard or mouse couldn't be grabbed.
+    GrabFailed = 3,
+    /// The keys typed didn't match any hint.
+    NoMatch = 4,
+    /// Any other error, including invalid arguments.
+    Error = 5,
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> ExitCode {
+        ExitCode::from(status as u8)
+    }
+}
+
+/// A window (or workspace, output, tab or whatever else) to show a hint for.
+#[derive(Debug, Clone, Default)]
+pub struct DesktopWindow {
+    pub id: i64,
+    pub x_window_id: Option<i32>,
+    pub pos: (i32, i32),
+    pub size: (i32, i32),
+    pub is_focused: bool,
+    pub is_urgent: bool,
+    pub is_sticky: bool,
+    pub is_fullscreen: bool,
+    pub is_floating: bool,
+    pub pid: Option<u32>,
+    pub marks: Vec<String>,
+    pub class: Option<String>,
+    pub title: Option<String>,
+    pub workspace: Option<String>,
+    pub output: Option<String>,
+    pub label: Option<String>,
+    pub is_listed: bool,
+    pub external_id: Option<String>,
+}
+
+/// How the contents of an output are rotated and/or flipped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transform {
+    #[default]
+    Normal,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    Flipped,
+    Flipped90,
+    Flipped180,
+    Flipped270,
+}
+
+/// An output (or X monitor) with its geometry in the format (x, y, w, h).
+#[derive(Debug, Clone)]
+pub struct DesktopOutput {
+    pub name: String,
+    pub rect: (i32, i32, i32, i32),
+    pub transform: Transform,
+    pub scale: f64,
+}
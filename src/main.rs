@@ -1,19 +1,31 @@
-use std::collections::HashMap;
-use std::time::Duration;
+use std::io::{self, Read, Write};
+use std::iter;
+use std::os::fd::AsFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::process::ExitCode;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use log::{debug, info, warn};
+use log::{info, warn};
 use x11rb::xcb_ffi::XCBConnection;
 
 use x11rb::{
     connection::Connection,
     protocol::xproto::{self, ConnectionExt as _},
     protocol::Event,
-    wrapper::ConnectionExt,
 };
 
-mod args;
-mod utils;
+mod browser;
+mod kitty;
+
+use wmfocus::backend::Backend;
+use wmfocus::overlay::{self, Selection};
+use wmfocus::{
+    args, dbus, event_loop, events, expose, geometry, hints, stats, utils, DesktopOutput,
+    DesktopWindow, ExitStatus,
+};
 
 #[cfg(feature = "i3")]
 extern crate i3ipc;
@@ -24,312 +36,1431 @@ mod wm_i3;
 #[cfg(feature = "i3")]
 use crate::wm_i3 as wm;
 
-#[derive(Debug)]
-pub struct DesktopWindow {
-    id: i64,
-    x_window_id: Option<i32>,
-    pos: (i32, i32),
-    size: (i32, i32),
-    is_focused: bool,
-}
+/// How long a window gets to take the focus before trying harder.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+const FOCUS_TIMEOUT: Duration = Duration::from_millis(200);
 
-#[derive(Debug)]
-pub struct RenderWindow<'a> {
-    desktop_window: &'a DesktopWindow,
-    cairo_context: cairo::Context,
-    draw_pos: (f64, f64),
-    rect: (i32, i32, i32, i32),
+/// Get everything to show hints for in the current mode.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn fetch_desktop_windows(app_config: &args::AppConfig) -> Result<Vec<DesktopWindow>> {
+    // Get the windows (or whatever else we want to hint) from each specific window manager
+    // implementation.
+    let desktop_windows_raw = if app_config.stdin {
+        let mut input = String::new();
+        io::stdin()
+            .read_to_string(&mut input)
+            .context("Couldn't read from stdin")?;
+        utils::parse_stdin_rects(&input).context("Couldn't parse rectangles from stdin")?
+    } else {
+        match app_config.mode {
+            args::Mode::Windows | args::Mode::Inspect | args::Mode::Expose => wm::I3
+                .windows(app_config.containers)
+                .context("Couldn't get desktop windows")?,
+            args::Mode::Workspaces => {
+                let mut workspaces = wm::get_workspaces().context("Couldn't get workspaces")?;
+                if app_config.empty_workspaces {
+                    let mut names = wm::get_configured_workspace_names().unwrap_or_else(|e| {
+                        warn!("Couldn't get workspace names from the config: {e:#}");
+                        vec![]
+                    });
+                    names.extend(app_config.workspace_names.iter().cloned());
+                    let empty = wm::empty_workspaces(&workspaces, &names);
+                    workspaces.extend(empty);
+                }
+                workspaces
+            }
+            args::Mode::Outputs => wm::get_outputs().context("Couldn't get outputs")?,
+            args::Mode::Marks => wm::get_marks().context("Couldn't get marks")?,
+            args::Mode::Tabs => wm::get_tabs().context("Couldn't get tabs")?,
+            args::Mode::Grid => {
+                let outputs = wm::get_outputs().context("Couldn't get outputs")?;
+                let focused = outputs
+                    .iter()
+                    .find(|output| output.is_focused)
+                    .context("Couldn't find focused output")?;
+                geometry::grid_cells(focused, app_config.grid_size)
+            }
+        }
+    };
+    Ok(desktop_windows_raw)
 }
 
+/// Bring what `fetch_desktop_windows` got into X's coordinates and drop what shouldn't be hinted.
 #[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
-fn main() -> Result<()> {
-    pretty_env_logger::init();
-    let app_config = args::parse_args();
+fn prepare_desktop_windows(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    monitors: &[DesktopOutput],
+    desktop_windows_raw: Vec<DesktopWindow>,
+) -> Result<Vec<DesktopWindow>> {
+    // Drop all windows that the user doesn't want to see hints for.
+    let mut desktop_windows_raw = utils::filter_windows(desktop_windows_raw, app_config);
 
-    // Get the windows from each specific window manager implementation.
-    let desktop_windows_raw = wm::get_windows().context("Couldn't get desktop windows")?;
+    // If the user handed us specific X windows, we'll ask the X server about their geometry.
+    if !app_config.windows.is_empty() {
+        utils::update_x_geometry(conn, screen, &mut desktop_windows_raw)
+            .context("Couldn't get window geometry from X")?;
+    }
+
+    // Rotated or scaled outputs might be laid out differently in X than what the window manager
+    // tells us.
+    if !app_config.stdin && app_config.windows.is_empty() {
+        let outputs = wm::I3.outputs().context("Couldn't get outputs")?;
+        geometry::apply_output_geometry(&mut desktop_windows_raw, &outputs, monitors);
+    }
+
+    // Client side decorated windows include their shadows, which hints shouldn't go onto.
+    if !app_config.stdin {
+        utils::apply_frame_extents(conn, &mut desktop_windows_raw)
+            .context("Couldn't get frame extents from X")?;
+    }
+
+    // Don't show hints for windows the user can't see anyway.
+    if !app_config.include_covered {
+        desktop_windows_raw = geometry::drop_covered(desktop_windows_raw);
+    }
+    if app_config.occlusion {
+        let stacking = utils::get_stacking_order(conn, screen)
+            .context("Couldn't get stacking order from X")?;
+        desktop_windows_raw = geometry::handle_occlusion(desktop_windows_raw, &stacking);
+    }
 
     // Sort by position to make hint position more deterministic.
-    let desktop_windows = utils::sort_by_pos(desktop_windows_raw);
+    Ok(geometry::sort_by_pos(desktop_windows_raw))
+}
 
-    let (conn, screen_num) = XCBConnection::connect(None).context("No Xorg connection")?;
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn main() -> ExitCode {
+    pretty_env_logger::init();
+    match run() {
+        Ok(status) => status.into(),
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            utils::exit_status(&e).into()
+        }
+    }
+}
+
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn run() -> Result<ExitStatus> {
+    let mut stats = stats::Stats::new();
+    // Whether `--persist` wants the signals is only known once the config file is loaded, but
+    // they have to be blocked before starting any threads so that those inherit the signal mask.
+    utils::Signals::block()?;
+    // Connecting to X doesn't depend on the arguments, so it can happen while the font loads.
+    let connecting = thread::spawn(|| XCBConnection::connect(None));
+    let mut base_config = match args::parse_args(wm::I3.name())? {
+        args::Parsed::Config(config) => *config,
+        args::Parsed::Exit(status) => return Ok(status),
+    };
+    let signals = if base_config.persist {
+        Some(utils::Signals::new()?)
+    } else {
+        utils::Signals::unblock()?;
+        None
+    };
+    if base_config.browser_native_host {
+        browser::run_native_host()?;
+        return Ok(ExitStatus::Selected);
+    }
+    let mut app_config = base_config.with_mode(base_config.mode);
+    let events = events::Events::new(app_config.event_fd)?
+        .announcing(app_config.announce)
+        .reporting_status(app_config.status_file.clone(), &app_config.action_name());
+    stats.record("font loading", app_config.font.load_time);
+    let connected = connecting
+        .join()
+        .expect("Connecting to X panicked")
+        .context("No Xorg connection");
+    let (conn, screen_num) = match connected {
+        Ok(connected) => connected,
+        Err(e) => {
+            return match overlay::fallback(
+                overlay::Failure::NoDisplay,
+                app_config.no_fallback,
+                app_config.stdin,
+            ) {
+                overlay::Fallback::List => {
+                    let result = select_without_x(&app_config, &events, e);
+                    if result.is_err() {
+                        events.idle();
+                    }
+                    result
+                }
+                _ => Err(e),
+            };
+        }
+    };
     let screen = &conn.setup().roots[screen_num];
+    if let Some(args::Command::Bench { iterations }) = app_config.command {
+        bench(&conn, screen, &app_config, iterations)?;
+        return Ok(ExitStatus::Selected);
+    }
+    // Staying around anyway, we might as well keep track of the windows in the meantime.
+    let mut persistent = signals
+        .map(|signals| Persistent::new(signals, &base_config))
+        .transpose()?;
+    // With socket activation, whoever connected first is who we were started for.
+    let mut client = match &mut persistent {
+        Some(persistent) if persistent.socket.is_some() => {
+            let client = persistent.wait(&mut base_config)?;
+            app_config = base_config.with_mode(client.mode().unwrap_or(base_config.mode));
+            client
+        }
+        _ => Client::Signal,
+    };
+    loop {
+        let previous_focus = if app_config.restore_focus {
+            Some(conn.get_input_focus()?.reply()?.focus)
+        } else {
+            None
+        };
+        let previous_mode = match &app_config.binding_mode {
+            Some(mode) if !app_config.dry_run => enter_binding_mode(mode),
+            _ => None,
+        };
+        let dimmed = match app_config.dim {
+            Some(opacity) if !app_config.list && app_config.render_to_png.is_none() => {
+                dim_windows(&conn, screen, opacity)
+            }
+            _ => vec![],
+        };
+        let result = select_and_act(
+            &conn,
+            screen,
+            &base_config,
+            &mut app_config,
+            &events,
+            &stats,
+            persistent.as_mut(),
+        );
+        if let Err(e) = utils::undim_windows(&conn, &dimmed) {
+            warn!("Couldn't undim windows: {e:#}");
+        }
+        // Bars shouldn't be stuck showing a selection that failed.
+        if result.is_err() {
+            events.idle();
+        }
+        if let Some(previous_mode) = previous_mode {
+            if let Err(e) = wm::run_command(&wm::binding_mode_command(&previous_mode)) {
+                warn!("Couldn't switch back to binding mode {previous_mode}: {e:#}");
+            }
+        }
+        if let (Ok(ExitStatus::Canceled), Some(previous_focus)) = (&result, previous_focus) {
+            restore_focus(&conn, screen, &app_config, previous_focus)?;
+        }
+        if app_config.stats {
+            eprint!("{}", stats.report());
+        }
+        // Keep going in the same mode for as long as something is selected.
+        if base_config.repeat && matches!(result, Ok(ExitStatus::Selected)) {
+            info!("Showing hints again");
+            app_config = base_config.with_mode(app_config.mode);
+            stats = stats::Stats::new();
+            continue;
+        }
+        let Some(persistent) = &mut persistent else {
+            return result;
+        };
+        let status = match &result {
+            Ok(status) => *status,
+            Err(e) => utils::exit_status(e),
+        };
+        persistent.answer(client, status);
+        // A failed selection shouldn't take the next ones with it.
+        if let Err(e) = result {
+            eprintln!("Error: {e:?}");
+            utils::release_input(&conn)?;
+        }
+        client = persistent.wait(&mut base_config)?;
+        app_config = base_config.with_mode(client.mode().unwrap_or(base_config.mode));
+        stats = stats::Stats::new();
+    }
+}
 
-    // Assemble RenderWindows from DesktopWindows.
-    let mut render_windows = HashMap::new();
-    for desktop_window in &desktop_windows {
-        // We need to estimate the font size before rendering because we want the window to only be
-        // the size of the font.
-        let hint = utils::get_next_hint(
-            render_windows.keys().collect(),
-            &app_config.hint_chars,
-            desktop_windows.len(),
-        )
-        .context("Couldn't get next hint")?;
+/// Who asked a persistent wmfocus to show the hints, to let them know how it went.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+enum Client {
+    /// SIGUSR1 doesn't take an answer.
+    Signal,
+    /// A connection to the socket from socket activation.
+    Socket(UnixStream),
+    /// A call of `Select` on the D-Bus service.
+    DBus(dbus::Request),
+}
 
-        // Figure out how large the window actually needs to be.
-        let text_extents = utils::extents_for_text(
-            &hint,
-            &app_config.font.font_family,
-            app_config.font.font_size,
-        )
-        .context("Couldn't create extents for text")?;
-        let (width, height, margin_width, margin_height) = if app_config.fill {
-            (
-                desktop_window.size.0 as u16,
-                desktop_window.size.1 as u16,
-                (f64::from(desktop_window.size.0) - text_extents.width()) / 2.0,
-                (f64::from(desktop_window.size.1) - text_extents.height()) / 2.0,
-            )
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+impl Client {
+    /// The mode the client asked for, if any.
+    fn mode(&self) -> Option<args::Mode> {
+        match self {
+            Client::DBus(request) => request.mode,
+            _ => None,
+        }
+    }
+}
+
+/// What `--persist` waits on in between selections.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+struct Persistent {
+    signals: utils::Signals,
+    /// The socket systemd passed us, see `utils::activation_socket`.
+    socket: Option<UnixListener>,
+    /// The windows, if we can keep track of them.
+    warm: Option<wm::WarmWindows>,
+    /// The D-Bus service with `--dbus`.
+    selector: Option<dbus::Selector>,
+    /// What was selected since the hints were last asked for.
+    selected: Option<DesktopWindow>,
+}
+
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+impl Persistent {
+    fn new(signals: utils::Signals, base_config: &args::AppConfig) -> Result<Persistent> {
+        let socket = utils::activation_socket().context("Couldn't take over the socket")?;
+        if socket.is_some() {
+            info!("Showing hints for every connection to the socket systemd passed us");
+        }
+        let warm = wm::WarmWindows::new(base_config.containers)
+            .map_err(|e| warn!("Couldn't keep track of windows in the background: {e:#}"))
+            .ok();
+        let selector = if base_config.dbus {
+            Some(dbus::Selector::new().context("Couldn't offer the D-Bus service")?)
         } else {
-            let margin_factor = 1.0 + 0.2;
-            (
-                (text_extents.width() * margin_factor).round() as u16,
-                (text_extents.height() * margin_factor).round() as u16,
-                ((text_extents.width() * margin_factor) - text_extents.width()) / 2.0,
-                ((text_extents.height() * margin_factor) - text_extents.height()) / 2.0,
-            )
+            None
         };
+        Ok(Persistent {
+            signals,
+            socket,
+            warm,
+            selector,
+            selected: None,
+        })
+    }
 
-        // Due to the way cairo lays out text, we'll have to calculate the actual coordinates to
-        // put the cursor. See:
-        // https://www.cairographics.org/samples/text_align_center/
-        // https://www.cairographics.org/samples/text_extents/
-        // https://www.cairographics.org/tutorial/#L1understandingtext
-        let draw_pos = (
-            margin_width - text_extents.x_bearing(),
-            text_extents.height() + margin_height
-                - (text_extents.height() + text_extents.y_bearing()),
-        );
+    /// The windows `app_config` wants hints for, if they're already known.
+    fn warm_windows(&mut self, app_config: &args::AppConfig) -> Option<Vec<DesktopWindow>> {
+        if !matches!(
+            app_config.mode,
+            args::Mode::Windows | args::Mode::Inspect | args::Mode::Expose
+        ) {
+            return None;
+        }
+        match self.warm.as_mut()?.windows(app_config.containers) {
+            Ok(windows) => Some(windows),
+            Err(e) => {
+                warn!("Couldn't keep track of windows, fetching them when needed instead: {e:#}");
+                self.warm = None;
+                None
+            }
+        }
+    }
 
-        debug!(
-            "Spawning RenderWindow for this DesktopWindow: {:?}",
-            desktop_window
-        );
+    /// Remember `selected` to tell the client about it.
+    fn record_selection(&mut self, selected: &DesktopWindow) {
+        self.selected = Some(selected.clone());
+    }
 
-        let x_offset = app_config.offset.x;
-        let mut x = match app_config.horizontal_align {
-            args::HorizontalAlign::Left => (desktop_window.pos.0 + x_offset) as i16,
-            args::HorizontalAlign::Center => {
-                (desktop_window.pos.0 + desktop_window.size.0 / 2 - i32::from(width) / 2) as i16
+    /// Tell `client` how it went, `status` being the exit status a one-off wmfocus would have
+    /// exited with.
+    fn answer(&mut self, client: Client, status: ExitStatus) {
+        let selected = self.selected.take();
+        if let (Some(selector), ExitStatus::Selected, Some(selected)) =
+            (&mut self.selector, status, &selected)
+        {
+            if let Err(e) = selector.selection_made(selected) {
+                warn!("Couldn't announce the selection on D-Bus: {e:#}");
             }
-            args::HorizontalAlign::Right => {
-                (desktop_window.pos.0 + desktop_window.size.0 - i32::from(width) - x_offset) as i16
+        }
+        match client {
+            Client::Signal => {}
+            Client::Socket(mut stream) => {
+                if let Err(e) = writeln!(stream, "{}", status as u8) {
+                    warn!("Couldn't answer on the socket: {e}");
+                }
             }
-        };
-
-        let y_offset = app_config.offset.y;
-        let y = match app_config.vertical_align {
-            args::VerticalAlign::Top => (desktop_window.pos.1 + y_offset) as i16,
-            args::VerticalAlign::Center => {
-                (desktop_window.pos.1 + desktop_window.size.1 / 2 - i32::from(height) / 2) as i16
+            Client::DBus(request) => {
+                if let Some(selector) = &mut self.selector {
+                    selector.answer(request, status, selected.as_ref());
+                }
             }
-            args::VerticalAlign::Bottom => {
-                (desktop_window.pos.1 + desktop_window.size.1 - i32::from(height) - y_offset) as i16
+        }
+    }
+
+    /// Wait until someone asks for the hints again, reloading `base_config` on SIGHUP and keeping
+    /// track of the windows in the meantime.
+    fn wait(&mut self, base_config: &mut args::AppConfig) -> Result<Client> {
+        info!("Waiting for SIGUSR1 to show hints again");
+        let socket_index = self.socket.is_some().then_some(1);
+        let selector_index = self
+            .selector
+            .is_some()
+            .then_some(1 + usize::from(self.socket.is_some()));
+        loop {
+            let ready = {
+                let fds: Vec<_> = iter::once(self.signals.as_fd())
+                    .chain(self.socket.as_ref().map(AsFd::as_fd))
+                    .chain(self.selector.as_ref().map(AsFd::as_fd))
+                    .chain(self.warm.as_ref().map(AsFd::as_fd))
+                    .collect();
+                event_loop::wait_readable(&fds, None)?
+            };
+            match ready {
+                Some(0) => match self.signals.next()? {
+                    utils::Signal::Show => return Ok(Client::Signal),
+                    utils::Signal::Reload => reload_config(base_config),
+                },
+                Some(index) if Some(index) == socket_index => {
+                    let socket = self.socket.as_ref().expect("the socket is polled");
+                    match socket.accept() {
+                        Ok((stream, _)) => return Ok(Client::Socket(stream)),
+                        Err(e) => warn!("Couldn't accept a connection: {e}"),
+                    }
+                }
+                Some(index) if Some(index) == selector_index => {
+                    let selector = self.selector.as_mut().expect("the bus is polled");
+                    match selector.next_request() {
+                        Ok(Some(request)) => return Ok(Client::DBus(request)),
+                        Ok(None) => {}
+                        Err(e) => {
+                            warn!("No longer offering the D-Bus service: {e:#}");
+                            self.selector = None;
+                            return self.wait(base_config);
+                        }
+                    }
+                }
+                _ => {
+                    if let Some(Err(e)) = self.warm.as_mut().map(wm::WarmWindows::refresh) {
+                        warn!("Couldn't keep track of windows, fetching them when needed instead: {e:#}");
+                        self.warm = None;
+                    }
+                }
             }
-        };
+        }
+    }
+}
 
-        // If this is overlapping then we'll nudge the new RenderWindow a little bit out of the
-        // way.
-        let mut overlaps = utils::find_overlaps(
-            render_windows.values().collect(),
-            (x.into(), y.into(), width.into(), height.into()),
-        );
-        while !overlaps.is_empty() {
-            x += overlaps.pop().unwrap().2 as i16;
-            overlaps = utils::find_overlaps(
-                render_windows.values().collect(),
-                (x.into(), y.into(), width.into(), height.into()),
-            );
+/// Replace `base_config` with what the arguments and the config file say now.
+///
+/// Options that are only looked at on startup, like `--persist` or `--status-file`, keep their
+/// value. If the config file is broken, so does everything else.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn reload_config(base_config: &mut args::AppConfig) {
+    info!("Reloading the config file");
+    match args::reparse_args(wm::I3.name()) {
+        Ok(config) => *base_config = config,
+        Err(e) => eprintln!("Error: Couldn't reload the config file, keeping the old one: {e:?}"),
+    }
+}
+
+/// Dim all windows to `opacity` and return them for undimming later.
+///
+/// Hints work without dimming too, so failing to dim isn't an error.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn dim_windows(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    opacity: f64,
+) -> Vec<(u32, Option<u32>)> {
+    match overlay::Transparency::detect(conn, screen) {
+        Ok(overlay::Transparency::Compositor(_)) => {}
+        Ok(overlay::Transparency::Pseudo) => {
+            warn!("There's no compositor to dim windows");
+            return vec![];
         }
+        Err(e) => {
+            warn!("Couldn't look for a compositor: {e:#}");
+            return vec![];
+        }
+    }
+    utils::dim_windows(conn, screen, opacity).unwrap_or_else(|e| {
+        warn!("Couldn't dim windows: {e:#}");
+        vec![]
+    })
+}
 
-        let xcb_window_id = conn.generate_id()?;
+/// Switch the window manager into the binding `mode` and return the mode it was in before.
+///
+/// Hints work without the mode too, so failing to switch isn't an error.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn enter_binding_mode(mode: &str) -> Option<String> {
+    let switched = wm::get_binding_mode().and_then(|previous_mode| {
+        wm::run_command(&wm::binding_mode_command(mode))?;
+        Ok(previous_mode)
+    });
+    match switched {
+        Ok(previous_mode) => Some(previous_mode),
+        Err(e) => {
+            warn!("Couldn't switch to binding mode {mode}: {e:#}");
+            None
+        }
+    }
+}
+
+/// Print the hint table for `desktop_windows` instead of showing it.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn print_list(app_config: &args::AppConfig, desktop_windows: &[DesktopWindow]) -> Result<()> {
+    let mut rows = vec![];
+    for page in hints::paginate(desktop_windows, app_config.max_hints) {
+        let hints = hints::HintGenerator::new(
+            &app_config.hint_chars,
+            page.len(),
+            app_config.hint_strategy,
+        )?;
+        rows.extend(hints.zip(page));
+    }
+
+    let mut stdout = io::stdout().lock();
+    match app_config.output_format {
+        args::OutputFormat::Json => {
+            let json: Vec<_> = rows
+                .iter()
+                .map(|(hint, dw)| {
+                    let mut json = utils::selection_json(dw);
+                    json["hint"] = serde_json::Value::String(hint.clone());
+                    json
+                })
+                .collect();
+            writeln!(stdout, "{}", serde_json::Value::Array(json))?;
+        }
+        args::OutputFormat::Text => {
+            for (hint, dw) in rows {
+                writeln!(stdout, "{}", utils::list_row(&hint, dw))?;
+            }
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
 
-        let win_aux = xproto::CreateWindowAux::new()
-            .event_mask(
-                xproto::EventMask::EXPOSURE
-                    | xproto::EventMask::KEY_PRESS
-                    | xproto::EventMask::BUTTON_PRESS
-                    | xproto::EventMask::BUTTON_RELEASE,
+/// Draw the first page of hints for `desktop_windows` over a screenshot (if we can take one) and
+/// save that as a PNG at `path` instead of showing the hints.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn render_to_png(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    desktop_windows: &[DesktopWindow],
+    monitors: &[DesktopOutput],
+    stats: &stats::Stats,
+    path: &Path,
+) -> Result<()> {
+    let canvas = match utils::take_screenshot(conn, screen) {
+        Ok(screenshot) => screenshot,
+        Err(e) => {
+            warn!("Couldn't take a screenshot, drawing onto black instead: {e:#}");
+            cairo::ImageSurface::create(
+                cairo::Format::Rgb24,
+                screen.width_in_pixels.into(),
+                screen.height_in_pixels.into(),
             )
-            .backing_pixel(screen.black_pixel)
-            .override_redirect(1);
+            .context("Couldn't create ImageSurface")?
+        }
+    };
+    let cr = cairo::Context::new(&canvas).context("Couldn't create Cairo Context")?;
 
-        // Create the actual window.
-        xproto::create_window(
-            &conn,
-            x11rb::COPY_FROM_PARENT as u8,
-            xcb_window_id,
-            screen.root,
-            x,
-            y,
+    let pages = hints::paginate(desktop_windows, app_config.max_hints);
+    let render_windows =
+        overlay::create_render_windows(app_config, &pages[0], monitors, stats, |rect| {
+            let surface =
+                cairo::ImageSurface::create(cairo::Format::ARgb32, rect.2.into(), rect.3.into())
+                    .context("Couldn't create ImageSurface")?;
+            let cairo_context =
+                cairo::Context::new(&surface).context("Couldn't create Cairo Context")?;
+            Ok((x11rb::NONE, cairo_context, None))
+        })?;
+    for (hint, rw) in &render_windows {
+        utils::draw_hint_text(rw, app_config, hint, "").context("Couldn't draw hint text")?;
+        // The background alpha applies to the whole hint, just like the window opacity does.
+        cr.set_source_surface(
+            rw.cairo_context.target(),
+            rw.rect.0.into(),
+            rw.rect.1.into(),
+        )?;
+        cr.paint_with_alpha(app_config.bg_color.3)
+            .context("Error trying to draw")?;
+    }
+    drop(cr);
+
+    let mut file = std::fs::File::create(path)
+        .with_context(|| format!("Couldn't create {}", path.display()))?;
+    canvas
+        .write_to_png(&mut file)
+        .with_context(|| format!("Couldn't write {}", path.display()))?;
+    info!("Wrote hints to {}", path.display());
+    Ok(())
+}
+
+/// Print the properties of `selected` and show them in an overlay until a key or button is pressed.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn inspect_window(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    selected: &DesktopWindow,
+) -> Result<ExitStatus> {
+    let (instance, pid) = match selected.x_window_id {
+        Some(id) => utils::get_instance_and_pid(conn, id as u32).unwrap_or_else(|e| {
+            warn!("Couldn't get window properties from X: {e:#}");
+            (None, None)
+        }),
+        None => (None, selected.pid),
+    };
+    let lines = utils::inspect_lines(selected, instance.as_deref(), pid);
+
+    match app_config.output_format {
+        args::OutputFormat::Json => {
+            let mut json = utils::selection_json(selected);
+            json["instance"] = serde_json::json!(instance);
+            json["pid"] = serde_json::json!(pid);
+            println!("{json}");
+        }
+        args::OutputFormat::Text => println!("{}", lines.join("\n")),
+    }
+    io::stdout().flush().context("Couldn't write to stdout")?;
+
+    // Scripts only want the output.
+    if app_config.prints_selection() {
+        return Ok(ExitStatus::Selected);
+    }
+
+    // Size the overlay to fit the longest line.
+    let margin = app_config.font.font_size * 0.5;
+    let line_height = app_config.font.font_size * 1.25;
+    let mut text_width: f64 = 0.0;
+    for line in &lines {
+        let extents = utils::extents_for_text(
+            line,
+            &app_config.font.font_family,
+            app_config.font.font_size,
+        )?;
+        text_width = text_width.max(extents.x_advance());
+    }
+    let width = (text_width + 2.0 * margin).ceil() as i32;
+    let height = (line_height * lines.len() as f64 + 2.0 * margin).ceil() as i32;
+    let (x, y) = geometry::clamp_to_rect(
+        (
+            selected.pos.0 + selected.size.0 / 2 - width / 2,
+            selected.pos.1 + selected.size.1 / 2 - height / 2,
             width,
             height,
+        ),
+        (
             0,
-            xproto::WindowClass::INPUT_OUTPUT,
-            screen.root_visual,
-            &win_aux,
+            0,
+            screen.width_in_pixels.into(),
+            screen.height_in_pixels.into(),
+        ),
+    );
+    let (xcb_window_id, cairo_context, backdrop) = overlay::create_overlay_window(
+        conn,
+        screen,
+        app_config,
+        overlay::Transparency::detect(conn, screen)?,
+        (x as i16, y as i16, width as u16, height as u16),
+    )?;
+    conn.map_window(xcb_window_id)?;
+    conn.flush()?;
+    utils::snatch_keyboard(
+        conn,
+        screen,
+        app_config.grab_timeout,
+        app_config.grab_retry_interval,
+    )?;
+    utils::snatch_mouse(
+        conn,
+        screen,
+        app_config.grab_timeout,
+        app_config.grab_retry_interval,
+    )?;
+
+    let mut event_loop = event_loop::EventLoop::new(conn, vec![]);
+    loop {
+        let event_loop::LoopEvent::X(event) = event_loop.next_event()? else {
+            continue;
+        };
+        match event {
+            Event::Expose(_) => {
+                cairo_context.push_group();
+                let (r, g, b, _) = app_config.bg_color;
+                cairo_context.set_operator(cairo::Operator::Source);
+                cairo_context.set_source_rgb(r, g, b);
+                cairo_context.paint().context("Error trying to draw")?;
+                cairo_context.set_operator(cairo::Operator::Over);
+                cairo_context.select_font_face(
+                    &app_config.font.font_family,
+                    cairo::FontSlant::Normal,
+                    cairo::FontWeight::Normal,
+                );
+                cairo_context.set_font_size(app_config.font.font_size);
+                let (r, g, b, a) = app_config.text_color;
+                cairo_context.set_source_rgba(r, g, b, a);
+                for (i, line) in lines.iter().enumerate() {
+                    // The baseline sits a little above the bottom of each line.
+                    cairo_context.move_to(
+                        margin,
+                        margin + line_height * (i + 1) as f64 - app_config.font.font_size * 0.3,
+                    );
+                    cairo_context
+                        .show_text(line)
+                        .context("Couldn't draw text")?;
+                }
+                utils::paint_group(&cairo_context, backdrop.as_ref(), app_config.bg_color.3)?;
+                conn.flush()?;
+            }
+            Event::KeyPress(_) | Event::ButtonPress(_) => break,
+            _ => {}
+        }
+    }
+
+    conn.destroy_window(xcb_window_id)?;
+    utils::release_input(conn)?;
+    Ok(ExitStatus::Selected)
+}
+
+/// Go through enumerating windows and creating, mapping and unmapping their hints `iterations`
+/// times and print percentiles of how long each step took.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn bench(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    iterations: u32,
+) -> Result<()> {
+    let monitors = utils::get_monitors(conn, screen).unwrap_or_else(|e| {
+        warn!("Couldn't get monitors from X: {e:#}");
+        vec![]
+    });
+    let transparency = overlay::Transparency::detect(conn, screen)?;
+    // A round trip makes sure that the X server actually got through our requests.
+    let round_trip = || -> Result<()> {
+        conn.get_input_focus()?.reply()?;
+        Ok(())
+    };
+
+    let mut phases: Vec<(&str, Vec<Duration>)> =
+        ["enumeration", "layout", "map and draw", "unmap", "total"]
+            .into_iter()
+            .map(|phase| (phase, vec![]))
+            .collect();
+    let mut hint_count = 0;
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let desktop_windows = prepare_desktop_windows(
+            conn,
+            screen,
+            app_config,
+            &monitors,
+            fetch_desktop_windows(app_config)?,
         )?;
+        let enumerated = Instant::now();
 
-        conn.map_window(xcb_window_id)?;
-
-        // Set transparency.
-        let opacity_atom = conn
-            .intern_atom(false, b"_NET_WM_WINDOW_OPACITY")?
-            .reply()
-            .context("Couldn't create atom _NET_WM_WINDOW_OPACITY")?
-            .atom;
-        let opacity = (0xFFFFFFFFu64 as f64 * app_config.bg_color.3) as u64;
-        conn.change_property32(
-            xproto::PropMode::REPLACE,
-            xcb_window_id,
-            opacity_atom,
-            xproto::AtomEnum::CARDINAL,
-            &[opacity as u32],
+        let pages = hints::paginate(&desktop_windows, app_config.max_hints);
+        let render_windows = overlay::create_render_windows(
+            app_config,
+            &pages[0],
+            &monitors,
+            &stats::Stats::new(),
+            |rect| overlay::create_overlay_window(conn, screen, app_config, transparency, rect),
         )?;
+        hint_count = render_windows.len();
+        round_trip()?;
+        let laid_out = Instant::now();
 
-        conn.flush()?;
-
-        let mut visual = utils::find_xcb_visualtype(&conn, screen.root_visual)
-            .context("Couldn't find visual")?;
-        let cairo_conn =
-            unsafe { cairo::XCBConnection::from_raw_none(conn.get_raw_xcb_connection() as _) };
-        let cairo_visual =
-            unsafe { cairo::XCBVisualType::from_raw_none(&mut visual as *mut _ as _) };
-
-        let surface = cairo::XCBSurface::create(
-            &cairo_conn,
-            &cairo::XCBDrawable(xcb_window_id),
-            &cairo_visual,
-            width.into(),
-            height.into(),
-        )
-        .context("Couldn't create Cairo Surface")?;
-        let cairo_context =
-            cairo::Context::new(&surface).context("Couldn't create Cairo Context")?;
-
-        let render_window = RenderWindow {
-            desktop_window,
-            cairo_context,
-            draw_pos,
-            rect: (x.into(), y.into(), width.into(), height.into()),
-        };
+        for rw in render_windows.values() {
+            conn.map_window(rw.xcb_window_id)?;
+        }
+        for (hint, rw) in &render_windows {
+            utils::draw_hint_text(rw, app_config, hint, "").context("Couldn't draw hint text")?;
+        }
+        round_trip()?;
+        let mapped = Instant::now();
+
+        for rw in render_windows.values() {
+            conn.unmap_window(rw.xcb_window_id)?;
+            conn.destroy_window(rw.xcb_window_id)?;
+        }
+        round_trip()?;
+        let unmapped = Instant::now();
 
-        render_windows.insert(hint, render_window);
+        let timings = [
+            enumerated - start,
+            laid_out - enumerated,
+            mapped - laid_out,
+            unmapped - mapped,
+            unmapped - start,
+        ];
+        for ((_, samples), timing) in phases.iter_mut().zip(timings) {
+            samples.push(timing);
+        }
     }
 
-    // Receive keyboard events.
-    utils::snatch_keyboard(&conn, screen, Duration::from_secs(1))?;
+    println!("{hint_count} hints, {iterations} iterations");
+    print!("{}", stats::percentile_report(&phases));
+    Ok(())
+}
+
+/// Let the user select something and then do with it whatever `app_config` says.
+///
+/// With `--persist`, the windows come from `persistent` if they're already known there, and it gets
+/// to know what was selected.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn select_and_act(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    base_config: &args::AppConfig,
+    app_config: &mut args::AppConfig,
+    events: &events::Events,
+    stats: &stats::Stats,
+    mut persistent: Option<&mut Persistent>,
+) -> Result<ExitStatus> {
+    let warm_windows = persistent
+        .as_mut()
+        .and_then(|persistent| persistent.warm_windows(app_config));
+    // Listen for windows being opened or closed before enumerating them so that none of them slip
+    // through in between.
+    let window_events = if app_config.stdin || !app_config.windows.is_empty() {
+        None
+    } else {
+        match wm::subscribe_window_events() {
+            Ok(window_events) => Some(window_events),
+            Err(e) => {
+                warn!("Couldn't subscribe to window events: {e:#}");
+                None
+            }
+        }
+    };
+
+    // Asking the window manager and asking X don't depend on each other.
+    let (monitors, prefetched) = stats.measure("window enumeration", || {
+        let app_config: &args::AppConfig = app_config;
+        thread::scope(|scope| {
+            let fetching = scope.spawn(|| match warm_windows {
+                Some(windows) => Ok(windows),
+                None => fetch_desktop_windows(app_config),
+            });
+            let monitors = utils::get_monitors(conn, screen).unwrap_or_else(|e| {
+                warn!("Couldn't get monitors from X: {e:#}");
+                vec![]
+            });
+            let fetched = fetching.join().expect("Fetching windows panicked");
+            (monitors, fetched)
+        })
+    });
+    let mut prefetched = Some(prefetched);
+
+    let (desktop_windows, selected) = 'modes: loop {
+        let mut desktop_windows = stats.measure("window enumeration", || {
+            let desktop_windows_raw = match prefetched.take() {
+                Some(fetched) => fetched,
+                None => fetch_desktop_windows(app_config),
+            }?;
+            prepare_desktop_windows(conn, screen, app_config, &monitors, desktop_windows_raw)
+        })?;
+        if desktop_windows.is_empty() {
+            info!("There's nothing to show hints for");
+            return Ok(ExitStatus::NoWindows);
+        }
 
-    // Receive mouse events.
-    utils::snatch_mouse(&conn, screen, Duration::from_secs(1))?;
+        if app_config.list {
+            print_list(app_config, &desktop_windows)?;
+            return Ok(ExitStatus::Selected);
+        }
 
-    // Since we might have lots of windows on the desktop, it might be required
-    // to enter a sequence in order to get to the correct window.
-    // We'll have to track the keys pressed so far.
-    let mut pressed_keys = String::default();
-    let mut sequence = utils::Sequence::new(None);
+        if let Some(path) = &app_config.render_to_png {
+            render_to_png(
+                conn,
+                screen,
+                app_config,
+                &desktop_windows,
+                &monitors,
+                stats,
+                path,
+            )?;
+            return Ok(ExitStatus::Selected);
+        }
 
-    let mut closed = false;
-    while !closed {
-        let event = conn.wait_for_event().context("No events")?;
-        let event_option = Some(event);
-        if let Some(e) = event_option {
-            match e {
-                Event::Expose(_) => {
-                    for (hint, rw) in &render_windows {
-                        utils::draw_hint_text(rw, &app_config, hint, &pressed_keys)
-                            .context("Couldn't draw hint text")?;
-                        conn.flush()?;
+        // In crowded setups, it's quicker to pick an output first and then have fewer hints to
+        // choose from on it.
+        let mut selected_output = None;
+        if app_config.by_output
+            && !matches!(app_config.mode, args::Mode::Outputs | args::Mode::Grid)
+        {
+            let mut outputs = wm::get_outputs().context("Couldn't get outputs")?;
+            let desktop_outputs = wm::I3.outputs().context("Couldn't get outputs")?;
+            geometry::apply_output_geometry(&mut outputs, &desktop_outputs, &monitors);
+            if outputs.len() > 1 {
+                let output_config = args::AppConfig {
+                    fill: true,
+                    horizontal_align: args::HorizontalAlign::Center,
+                    vertical_align: args::VerticalAlign::Center,
+                    max_hints: None,
+                    ..app_config.clone()
+                };
+                let outputs = geometry::sort_by_pos(outputs);
+                match overlay::select_window(
+                    conn,
+                    screen,
+                    &output_config,
+                    &outputs,
+                    &monitors,
+                    events,
+                    stats,
+                    None,
+                )? {
+                    Selection::Window(output) => {
+                        desktop_windows.retain(|dw| dw.output == output.output);
+                        selected_output = Some(output.output);
                     }
+                    Selection::NextMode => {
+                        *app_config = base_config.with_mode(app_config.mode.next());
+                        continue 'modes;
+                    }
+                    Selection::Canceled => return Ok(ExitStatus::Canceled),
+                    Selection::NoMatch => return Ok(ExitStatus::NoMatch),
+                    Selection::Changed => unreachable!("outputs aren't watched for changes"),
                 }
-                Event::ButtonPress(_) => {
-                    closed = true;
-                }
-                Event::KeyRelease(_) => {
-                    let ksym = utils::get_pressed_symbol(&conn, e);
-                    let kstr = ksym
-                        .name()
-                        .context("Couldn't convert ksym to string")?
-                        .replace("XK_", "");
-                    sequence.remove(&kstr);
+                if desktop_windows.is_empty() {
+                    info!("There's nothing to show hints for on this output");
+                    return Ok(ExitStatus::NoWindows);
                 }
-                Event::KeyPress(_) => {
-                    let ksym = utils::get_pressed_symbol(&conn, e);
-                    let kstr = ksym
-                        .name()
-                        .context("Couldn't convert ksym to string")?
-                        .replace("XK_", "");
-
-                    sequence.push(kstr.to_owned());
-
-                    if app_config.hint_chars.contains(&kstr) {
-                        info!("Adding '{}' to key sequence", kstr);
-                        pressed_keys.push_str(&kstr);
-                    } else {
-                        warn!("Pressed key '{}' is not a valid hint characters", kstr);
-                    }
+            }
+        }
 
-                    info!("Current key sequence: '{}'", pressed_keys);
+        // Exposé hints thumbnails of the windows instead of the windows themselves.
+        let mut expose_window = None;
+        let hinted_windows = if app_config.mode == args::Mode::Expose {
+            let outputs = wm::get_outputs().context("Couldn't get outputs")?;
+            let focused = outputs
+                .iter()
+                .find(|output| output.is_focused)
+                .context("Couldn't find focused output")?;
+            let area = (focused.pos.0, focused.pos.1, focused.size.0, focused.size.1);
+            let (window, thumbnails) =
+                expose::show(conn, screen, app_config, &desktop_windows, area)
+                    .context("Couldn't show thumbnails")?;
+            expose_window = Some(window);
+            thumbnails
+        } else {
+            vec![]
+        };
 
-                    if ksym == xkeysym::key::Escape.into()
-                        || app_config.exit_keys.contains(&sequence)
-                    {
-                        info!("{:?} is exit sequence", sequence);
-                        closed = true;
-                        continue;
+        let changes = match app_config.mode {
+            args::Mode::Windows | args::Mode::Inspect => window_events
+                .as_ref()
+                .map(|window_events| window_events as &dyn overlay::WindowChanges),
+            _ => None,
+        };
+        let mut depth = 1;
+        loop {
+            let selection = overlay::select_window(
+                conn,
+                screen,
+                app_config,
+                if expose_window.is_some() {
+                    &hinted_windows
+                } else {
+                    &desktop_windows
+                },
+                &monitors,
+                events,
+                stats,
+                changes,
+            );
+            if let Some(window) = expose_window.take() {
+                conn.destroy_window(window)?;
+                conn.flush()?;
+            }
+            match selection? {
+                // Keep subdividing the selected grid cell until we're precise enough.
+                Selection::Window(selected)
+                    if app_config.mode == args::Mode::Grid && depth < app_config.grid_depth =>
+                {
+                    depth += 1;
+                    desktop_windows = geometry::sort_by_pos(geometry::grid_cells(
+                        &selected,
+                        app_config.grid_size,
+                    ));
+                }
+                // Thumbnails stand in for their windows.
+                Selection::Window(selected) if app_config.mode == args::Mode::Expose => {
+                    let selected = desktop_windows
+                        .iter()
+                        .find(|dw| dw.id == selected.id)
+                        .cloned()
+                        .context("Couldn't find the window of the selected thumbnail")?;
+                    break 'modes (desktop_windows, selected);
+                }
+                Selection::Window(selected) => break 'modes (desktop_windows, *selected),
+                Selection::NextMode => {
+                    *app_config = base_config.with_mode(app_config.mode.next());
+                    continue 'modes;
+                }
+                Selection::Changed => {
+                    let desktop_windows_raw = fetch_desktop_windows(app_config)?;
+                    desktop_windows = prepare_desktop_windows(
+                        conn,
+                        screen,
+                        app_config,
+                        &monitors,
+                        desktop_windows_raw,
+                    )?;
+                    if let Some(output) = &selected_output {
+                        desktop_windows.retain(|dw| &dw.output == output);
                     }
-
-                    // Attempt to match the current sequence of keys as a string to the window
-                    // hints shown.
-                    // If there is an exact match, we're done. We'll then focus the window
-                    // and exit. However, we also want to check whether there is still any
-                    // chance to focus any windows from the current key sequence. If there
-                    // is not then we will also just exit and focus no new window.
-                    // If there still is a chance we might find a window then we'll just
-                    // keep going for now.
-                    if sequence.is_started() {
-                        utils::remove_last_key(&mut pressed_keys, &kstr);
-                    } else if let Some(rw) = &render_windows.get(&pressed_keys) {
-                        info!("Found matching window, focusing");
-                        if app_config.print_only {
-                            println!("0x{:x}", rw.desktop_window.x_window_id.unwrap_or(0));
-                        } else if app_config.swap {
-                            let Some(active_window) =
-                                desktop_windows.iter().find(|window| window.is_focused)
-                            else {
-                                warn!("There's no active window.");
-                                closed = true;
-                                continue;
-                            };
-                            wm::swap_windows(active_window, rw.desktop_window)
-                                .context("Couldn't swap windows")?;
-                        } else {
-                            wm::focus_window(rw.desktop_window).context("Couldn't focus window")?;
-                        }
-                        closed = true;
-                    } else if !pressed_keys.is_empty()
-                        && render_windows.keys().any(|k| k.starts_with(&pressed_keys))
-                    {
-                        for (hint, rw) in &render_windows {
-                            utils::draw_hint_text(rw, &app_config, hint, &pressed_keys)
-                                .context("Couldn't draw hint text")?;
-                            conn.flush()?;
-                        }
-                        continue;
-                    } else {
-                        warn!("No more matches possible with current key sequence");
-                        closed = app_config.exit_keys.is_empty();
-                        utils::remove_last_key(&mut pressed_keys, &kstr);
+                    if desktop_windows.is_empty() {
+                        info!("All windows were closed");
+                        return Ok(ExitStatus::NoWindows);
                     }
                 }
-                _ => {}
+                Selection::Canceled => return Ok(ExitStatus::Canceled),
+                Selection::NoMatch => return Ok(ExitStatus::NoMatch),
             }
+        }
+    };
+
+    if let Some(persistent) = persistent {
+        persistent.record_selection(&selected);
+    }
+
+    if app_config.mode == args::Mode::Inspect {
+        return inspect_window(conn, screen, app_config, &selected);
+    }
+
+    info!("Found matching window, focusing");
+    print_selection(app_config, &selected)?;
+
+    if let Some(value) = app_config.copy {
+        utils::copy_to_clipboard(&utils::copy_text(&selected, value))?;
+    }
+
+    if app_config.prints_selection() || app_config.copy.is_some() {
+        return Ok(ExitStatus::Selected);
+    }
+
+    if app_config.mode == args::Mode::Grid {
+        let center = (
+            selected.pos.0 + selected.size.0 / 2,
+            selected.pos.1 + selected.size.1 / 2,
+        );
+        if app_config.dry_run {
+            println!("{} {}", center.0, center.1);
         } else {
-            closed = true;
+            utils::warp_and_click(conn, screen, center, !app_config.grid_no_click)
+                .context("Couldn't click into grid cell")?;
+        }
+        return Ok(ExitStatus::Selected);
+    }
+
+    let command = match app_config.mode {
+        args::Mode::Workspaces => wm::focus_workspace_command(&selected)?,
+        args::Mode::Outputs if app_config.action == args::Action::MoveWorkspace => {
+            wm::move_workspace_command(&selected)?
+        }
+        args::Mode::Outputs => wm::focus_output_command(&selected)?,
+        _ if app_config.swap => {
+            let Some(active_window) = desktop_windows.iter().find(|window| window.is_focused)
+            else {
+                warn!("There's no active window.");
+                return Ok(ExitStatus::Error);
+            };
+            wm::swap_windows_command(active_window, &selected)?
+        }
+        args::Mode::Windows if app_config.action == args::Action::Group => {
+            let others: Vec<_> = desktop_windows
+                .iter()
+                .filter(|dw| dw.id != selected.id)
+                .cloned()
+                .collect();
+            let mut group = vec![selected.clone()];
+            group.extend(pick_windows(
+                conn, screen, app_config, others, &monitors, events, stats,
+            )?);
+            if group.len() < 2 {
+                info!("Not enough windows picked for a group");
+                return Ok(ExitStatus::Canceled);
+            }
+            let stacked = app_config.group_layout == args::GroupLayout::Stacked;
+            wm::group_command(&group, stacked)?
+        }
+        _ => wm::focus_window_command(&selected)?,
+    };
+    if app_config.dry_run {
+        println!("{command}");
+    } else {
+        wm::run_command(&command).context("Couldn't send command to the window manager")?;
+        if !matches!(
+            app_config.mode,
+            args::Mode::Workspaces | args::Mode::Outputs
+        ) && !app_config.swap
+            && app_config.action != args::Action::Group
+        {
+            ensure_focused(conn, screen, app_config, &selected)?;
+        }
+        if app_config.browser_tabs
+            && app_config.mode == args::Mode::Windows
+            && browser::is_browser(&selected)
+        {
+            select_browser_tab(
+                conn, screen, app_config, &selected, &monitors, events, stats,
+            )?;
+        }
+        if app_config.kitty_windows
+            && app_config.mode == args::Mode::Windows
+            && kitty::is_kitty(&selected)
+        {
+            select_kitty_window(
+                conn, screen, app_config, &selected, &monitors, events, stats,
+            )?;
+        }
+        if app_config.action == args::Action::Place && app_config.mode == args::Mode::Windows {
+            place_window(conn, screen, app_config, &selected)?;
+        }
+        if app_config.preselect && app_config.mode == args::Mode::Windows && !app_config.swap {
+            preselect_split(
+                conn, screen, app_config, &selected, &monitors, events, stats,
+            )?;
+        }
+    }
+
+    Ok(ExitStatus::Selected)
+}
+
+/// Print `selected` in the configured output format, if it's supposed to be printed at all.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn print_selection(app_config: &args::AppConfig, selected: &DesktopWindow) -> Result<()> {
+    let terminator = if app_config.print0 { '\0' } else { '\n' };
+    match app_config.output_format {
+        args::OutputFormat::Json => print!("{}{terminator}", utils::selection_json(selected)),
+        args::OutputFormat::Text if app_config.prints_selection() => {
+            print!(
+                "{}{terminator}",
+                utils::selection_text(selected, app_config)
+            )
+        }
+        args::OutputFormat::Text => {}
+    }
+    io::stdout().flush().context("Couldn't write to stdout")
+}
+
+/// Pick from a list on stdout and stdin when there's no X server to show hints on (see
+/// `overlay::select_from_list`) and act on the choice.
+///
+/// Only what the window manager can do by itself works like this. Everything else fails with
+/// `error`.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn select_without_x(
+    app_config: &args::AppConfig,
+    events: &events::Events,
+    error: anyhow::Error,
+) -> Result<ExitStatus> {
+    let needs_x = matches!(
+        app_config.mode,
+        args::Mode::Inspect | args::Mode::Expose | args::Mode::Grid
+    ) || matches!(app_config.action, args::Action::Place | args::Action::Group)
+        || app_config.preselect
+        || app_config.command.is_some()
+        || app_config.render_to_png.is_some()
+        || !app_config.windows.is_empty();
+    if needs_x {
+        return Err(error);
+    }
+
+    let mut desktop_windows = utils::filter_windows(fetch_desktop_windows(app_config)?, app_config);
+    if !app_config.include_covered {
+        desktop_windows = geometry::drop_covered(desktop_windows);
+    }
+    let desktop_windows = geometry::sort_by_pos(desktop_windows);
+    if desktop_windows.is_empty() {
+        info!("There's nothing to show hints for");
+        return Ok(ExitStatus::NoWindows);
+    }
+    if app_config.list {
+        print_list(app_config, &desktop_windows)?;
+        return Ok(ExitStatus::Selected);
+    }
+
+    let selected = match overlay::select_from_list(app_config, &desktop_windows, events, error)? {
+        Selection::Window(selected) => selected,
+        Selection::Canceled => return Ok(ExitStatus::Canceled),
+        Selection::NoMatch => return Ok(ExitStatus::NoMatch),
+        Selection::NextMode | Selection::Changed => {
+            unreachable!("lists neither switch modes nor watch for changes")
+        }
+    };
+    print_selection(app_config, &selected)?;
+    if let Some(value) = app_config.copy {
+        utils::copy_to_clipboard(&utils::copy_text(&selected, value))?;
+    }
+    if app_config.prints_selection() || app_config.copy.is_some() {
+        return Ok(ExitStatus::Selected);
+    }
+
+    let command = match app_config.mode {
+        args::Mode::Workspaces => wm::focus_workspace_command(&selected)?,
+        args::Mode::Outputs if app_config.action == args::Action::MoveWorkspace => {
+            wm::move_workspace_command(&selected)?
+        }
+        args::Mode::Outputs => wm::focus_output_command(&selected)?,
+        _ if app_config.swap => {
+            let Some(active_window) = desktop_windows.iter().find(|window| window.is_focused)
+            else {
+                warn!("There's no active window.");
+                return Ok(ExitStatus::Error);
+            };
+            wm::swap_windows_command(active_window, &selected)?
+        }
+        _ => wm::focus_window_command(&selected)?,
+    };
+    if app_config.dry_run {
+        println!("{command}");
+    } else {
+        wm::run_command(&command).context("Couldn't send command to the window manager")?;
+    }
+    Ok(ExitStatus::Selected)
+}
+
+/// Check that `selected` actually got the focus and try the fallbacks enabled in `app_config` one
+/// after the other if it didn't.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn ensure_focused(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    selected: &DesktopWindow,
+) -> Result<()> {
+    if app_config.criteria_fallback && !wm::is_focused(selected)? {
+        if let Some(command) = wm::focus_criteria_command(selected) {
+            warn!("The container didn't get the focus, trying {command}");
+            wm::run_command(&command).context("Couldn't send command to the window manager")?;
+        }
+    }
+
+    let Some(x_window_id) = selected.x_window_id.map(|id| id as u32) else {
+        return Ok(());
+    };
+    let has_fallbacks = app_config.ewmh_fallback || app_config.click_fallback;
+    if !has_fallbacks || utils::wait_for_focus(conn, x_window_id, FOCUS_TIMEOUT)? {
+        return Ok(());
+    }
+
+    if app_config.ewmh_fallback {
+        warn!("0x{x_window_id:x} didn't get the focus, asking with _NET_ACTIVE_WINDOW");
+        utils::request_activation(conn, screen, x_window_id)?;
+        if utils::wait_for_focus(conn, x_window_id, FOCUS_TIMEOUT)? {
+            return Ok(());
+        }
+    }
+
+    if app_config.click_fallback {
+        warn!("0x{x_window_id:x} didn't get the focus, clicking into it");
+        let pointer = conn.query_pointer(screen.root)?.reply()?;
+        let center = (
+            selected.pos.0 + selected.size.0 / 2,
+            selected.pos.1 + selected.size.1 / 2,
+        );
+        utils::warp_and_click(conn, screen, center, true)
+            .context("Couldn't click into the window")?;
+        // The pointer goes back to where the user left it.
+        utils::warp_and_click(
+            conn,
+            screen,
+            (pointer.root_x.into(), pointer.root_y.into()),
+            false,
+        )?;
+        if utils::wait_for_focus(conn, x_window_id, FOCUS_TIMEOUT)? {
+            return Ok(());
         }
     }
 
+    warn!("0x{x_window_id:x} still doesn't have the focus");
+    Ok(())
+}
+
+/// Give the focus back to `previous_focus` in case showing hints moved it somewhere else.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn restore_focus(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    previous_focus: xproto::Window,
+) -> Result<()> {
+    // Without a window having the focus, there's nothing to give it back to.
+    let no_window = [
+        x11rb::NONE,
+        u32::from(xproto::InputFocus::POINTER_ROOT),
+        screen.root,
+    ];
+    if no_window.contains(&previous_focus)
+        || conn.get_input_focus()?.reply()?.focus == previous_focus
+    {
+        return Ok(());
+    }
+    info!("Focusing 0x{previous_focus:x} again");
+    let command = wm::focus_x_window_command(previous_focus);
+    if app_config.dry_run {
+        println!("{command}");
+    } else if let Err(e) = wm::run_command(&command) {
+        // It might have been something of the window manager's own, which it won't focus again.
+        warn!("Couldn't focus 0x{previous_focus:x} again: {e:#}");
+    }
+    Ok(())
+}
+
+/// Hint the tabs of the already focused `browser_window` and switch to the selected one.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn select_browser_tab(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    browser_window: &DesktopWindow,
+    monitors: &[DesktopOutput],
+    events: &events::Events,
+    stats: &stats::Stats,
+) -> Result<()> {
+    let tabs = browser::get_tabs(browser_window).context("Couldn't get browser tabs")?;
+    if tabs.len() < 2 {
+        return Ok(());
+    }
+    // The window is focused already, so not picking a tab is fine too.
+    if let Selection::Window(tab) = overlay::select_window(
+        conn, screen, app_config, &tabs, monitors, events, stats, None,
+    )? {
+        browser::activate_tab(&tab).context("Couldn't switch tabs")?;
+    }
+    Ok(())
+}
+
+/// Hint the kitty windows of the already focused `kitty_window` and focus the selected one.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn select_kitty_window(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    kitty_window: &DesktopWindow,
+    monitors: &[DesktopOutput],
+    events: &events::Events,
+    stats: &stats::Stats,
+) -> Result<()> {
+    // Every kitty instance listens on its own socket, named after its pid.
+    let pid = match (kitty_window.pid, kitty_window.x_window_id) {
+        (Some(pid), _) => Some(pid),
+        (None, Some(id)) => utils::get_instance_and_pid(conn, id as u32)?.1,
+        (None, None) => None,
+    };
+    let windows = kitty::get_windows(kitty_window, &app_config.kitty_socket, pid)
+        .context("Couldn't get kitty windows")?;
+    if windows.len() < 2 {
+        return Ok(());
+    }
+    // The OS window is focused already, so not picking a kitty window is fine too.
+    if let Selection::Window(selected) = overlay::select_window(
+        conn, screen, app_config, &windows, monitors, events, stats, None,
+    )? {
+        kitty::focus_window(&selected, &app_config.kitty_socket, pid)
+            .context("Couldn't focus kitty window")?;
+    }
+    Ok(())
+}
+
+/// Keep hinting `remaining` windows and return the picked ones until the user cancels or nothing is
+/// left.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn pick_windows(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    mut remaining: Vec<DesktopWindow>,
+    monitors: &[DesktopOutput],
+    events: &events::Events,
+    stats: &stats::Stats,
+) -> Result<Vec<DesktopWindow>> {
+    let config = args::AppConfig {
+        mode_key: None,
+        ..app_config.clone()
+    };
+    let mut picked = vec![];
+    while !remaining.is_empty() {
+        match overlay::select_window(
+            conn, screen, &config, &remaining, monitors, events, stats, None,
+        )? {
+            Selection::Window(window) => {
+                remaining.retain(|dw| dw.id != window.id);
+                picked.push(*window);
+            }
+            _ => break,
+        }
+    }
+    Ok(picked)
+}
+
+/// Let the already focused `window` follow the pointer until a click drops it, if it's floating.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn place_window(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    window: &DesktopWindow,
+) -> Result<()> {
+    if !window.is_floating {
+        warn!("Only floating windows can be placed");
+        return Ok(());
+    }
+    let mut move_to = |pos| {
+        wm::run_command(&wm::move_position_command(window, pos))
+            .context("Couldn't send command to the window manager")
+    };
+    if !overlay::place_window(conn, screen, app_config, window, &mut move_to)? {
+        info!("Placing the window was canceled");
+    }
+    Ok(())
+}
+
+/// Hint the edges of the already focused `window` and split it towards the selected one.
+#[cfg(any(feature = "i3", feature = "add_some_other_wm_here"))]
+fn preselect_split(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &args::AppConfig,
+    window: &DesktopWindow,
+    monitors: &[DesktopOutput],
+    events: &events::Events,
+    stats: &stats::Stats,
+) -> Result<()> {
+    // Ordered like the strips, so that the hints come out as h, j, k and l.
+    const DIRECTIONS: [(&str, bool); 4] = [("←", false), ("↓", true), ("↑", true), ("→", false)];
+    let strips = geometry::edge_strips((window.pos.0, window.pos.1, window.size.0, window.size.1));
+    let edges: Vec<DesktopWindow> = strips
+        .into_iter()
+        .zip(DIRECTIONS)
+        .enumerate()
+        .map(|(i, ((x, y, w, h), (arrow, _)))| DesktopWindow {
+            id: i as i64,
+            pos: (x, y),
+            size: (w, h),
+            workspace: window.workspace.clone(),
+            output: window.output.clone(),
+            label: Some(arrow.to_string()),
+            ..Default::default()
+        })
+        .collect();
+    let mut config = app_config.clone();
+    config.hint_chars = "hjkl".to_string();
+    config.hint_strategy = hints::HintStrategy::Fixed;
+    config.horizontal_align = args::HorizontalAlign::Center;
+    config.vertical_align = args::VerticalAlign::Center;
+    config.fill = false;
+    config.huge = false;
+    config.max_hints = None;
+    // The window is focused already, so not picking a direction is fine too.
+    if let Selection::Window(edge) =
+        overlay::select_window(conn, screen, &config, &edges, monitors, events, stats, None)?
+    {
+        let (_, vertical) = DIRECTIONS[edge.id as usize];
+        wm::run_command(&wm::split_command(window, vertical))
+            .context("Couldn't send command to the window manager")?;
+    }
     Ok(())
 }
 
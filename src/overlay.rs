@@ -0,0 +1,1690 @@
+//! Showing hints on top of everything and waiting for the user to pick one.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::os::fd::AsFd;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::shape::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{self, ConnectionExt as _};
+use x11rb::protocol::Event;
+use x11rb::wrapper::ConnectionExt;
+use x11rb::xcb_ffi::XCBConnection;
+
+use crate::args::{self, AppConfig};
+use crate::backend::Backend;
+use crate::geometry::{self, Rect};
+use crate::keys::{self, Sequence};
+use crate::{event_loop, events, hints, stats, utils, DesktopOutput, DesktopWindow};
+
+/// A hint window for a `DesktopWindow` along with where to draw the hint on it.
+#[derive(Debug)]
+pub struct RenderWindow<'a> {
+    pub desktop_window: &'a DesktopWindow,
+    pub xcb_window_id: xproto::Window,
+    pub cairo_context: cairo::Context,
+    pub draw_pos: (f64, f64),
+    pub rect: (i32, i32, i32, i32),
+    /// The font size of the hint, which is smaller than configured if it wouldn't fit otherwise.
+    pub font_size: f64,
+    /// What was below the hint when it was created, if it has to be blended with that itself.
+    pub backdrop: Option<cairo::ImageSurface>,
+}
+
+/// Something to draw a hint onto as created by `create_overlay_window`, see `RenderWindow`.
+pub type OverlayWindow = (xproto::Window, cairo::Context, Option<cairo::ImageSurface>);
+
+/// How hints are made see-through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transparency {
+    /// A compositor takes care of it according to the window opacity with this atom.
+    Compositor(xproto::Atom),
+    /// There is no compositor, so hints are blended with a capture of what's below them.
+    Pseudo,
+}
+
+impl Transparency {
+    /// Check whether a compositor is running on `screen`.
+    pub fn detect(conn: &XCBConnection, screen: &xproto::Screen) -> Result<Transparency> {
+        let screen_num = conn
+            .setup()
+            .roots
+            .iter()
+            .position(|root| root.root == screen.root)
+            .unwrap_or_default();
+        let selection = utils::intern_atom(conn, &format!("_NET_WM_CM_S{screen_num}"))?;
+        if conn.get_selection_owner(selection)?.reply()?.owner == x11rb::NONE {
+            info!("No compositor is running, faking transparency");
+            return Ok(Transparency::Pseudo);
+        }
+        Ok(Transparency::Compositor(utils::intern_atom(
+            conn,
+            "_NET_WM_WINDOW_OPACITY",
+        )?))
+    }
+}
+
+/// Assign hints to `desktop_windows` and create a `RenderWindow` for each of them.
+///
+/// `create_window` is given the rectangle of every hint and creates (but doesn't map) something to
+/// draw it onto.
+pub fn create_render_windows<'a>(
+    app_config: &AppConfig,
+    desktop_windows: &[&'a DesktopWindow],
+    monitors: &[DesktopOutput],
+    stats: &stats::Stats,
+    mut create_window: impl FnMut((i16, i16, u16, u16)) -> Result<OverlayWindow>,
+) -> Result<HashMap<String, RenderWindow<'a>>> {
+    // Assign a hint to every DesktopWindow and figure out how large its box needs to be.
+    let widest = stats.measure("text extents", || {
+        utils::widest_char(
+            &app_config.hint_chars,
+            &app_config.font.font_family,
+            app_config.font.font_size,
+        )
+    })?;
+    let hints = hints::HintGenerator::new(
+        &app_config.hint_chars,
+        desktop_windows.len(),
+        app_config.hint_strategy,
+    )
+    .context("Couldn't get hints")?;
+    let mut hint_boxes = vec![];
+    for (desktop_window, hint) in desktop_windows.iter().zip(hints) {
+        // We need to estimate the font size before rendering because we want the window to only be
+        // the size of the font.
+
+        // Figure out how large the window actually needs to be.
+        let text = utils::measured_text(&hint, desktop_window, widest);
+        let measure = |font_size| {
+            stats
+                .measure("text extents", || {
+                    utils::extents_for_text(&text, &app_config.font.font_family, font_size)
+                })
+                .context("Couldn't create extents for text")
+        };
+        let margin_factor = 1.0 + 0.2;
+        let mut font_size = app_config.font.font_size;
+        let mut text_extents = measure(font_size)?;
+
+        // Huge hints take up a fixed part of their window, other hints shrink their font when
+        // they would cover more than their own window.
+        if app_config.huge && !desktop_window.is_listed {
+            let scale = geometry::grow_scale(
+                (
+                    text_extents.width() * margin_factor,
+                    text_extents.height() * margin_factor,
+                ),
+                desktop_window.size,
+                app_config.huge_fraction,
+            );
+            font_size = (font_size * scale).max(app_config.min_font_size);
+            debug!("Scaling the font to {font_size} for {hint}");
+            text_extents = measure(font_size)?;
+        } else if !desktop_window.is_listed {
+            let scale = geometry::fit_scale(
+                (
+                    text_extents.width() * margin_factor,
+                    text_extents.height() * margin_factor,
+                ),
+                desktop_window.size,
+            );
+            if scale < 1.0 && font_size > app_config.min_font_size {
+                font_size = (font_size * scale).max(app_config.min_font_size);
+                debug!("Shrinking the font to {font_size} for {hint}");
+                text_extents = measure(font_size)?;
+            }
+        }
+
+        let (width, height, margin_width, margin_height) =
+            if app_config.fill && !desktop_window.is_listed {
+                (
+                    desktop_window.size.0 as u16,
+                    desktop_window.size.1 as u16,
+                    (f64::from(desktop_window.size.0) - text_extents.width()) / 2.0,
+                    (f64::from(desktop_window.size.1) - text_extents.height()) / 2.0,
+                )
+            } else {
+                (
+                    (text_extents.width() * margin_factor).round() as u16,
+                    (text_extents.height() * margin_factor).round() as u16,
+                    ((text_extents.width() * margin_factor) - text_extents.width()) / 2.0,
+                    ((text_extents.height() * margin_factor) - text_extents.height()) / 2.0,
+                )
+            };
+
+        // Due to the way cairo lays out text, we'll have to calculate the actual coordinates to
+        // put the cursor. See:
+        // https://www.cairographics.org/samples/text_align_center/
+        // https://www.cairographics.org/samples/text_extents/
+        // https://www.cairographics.org/tutorial/#L1understandingtext
+        let draw_pos = (
+            margin_width - text_extents.x_bearing(),
+            text_extents.height() + margin_height
+                - (text_extents.height() + text_extents.y_bearing()),
+        );
+
+        hint_boxes.push((desktop_window, hint, (width, height), draw_pos, font_size));
+    }
+
+    // Entries without a place on the screen are stacked in a column in the middle of their area.
+    let list_height: i32 = hint_boxes
+        .iter()
+        .filter(|(dw, _, _, _, _)| dw.is_listed)
+        .map(|(_, _, (_, height), _, _)| i32::from(*height))
+        .sum();
+    let mut list_y = None;
+
+    // Assemble RenderWindows from DesktopWindows.
+    let creation_start = Instant::now();
+    let mut render_windows = HashMap::new();
+    for (desktop_window, hint, (width, height), draw_pos, font_size) in hint_boxes {
+        debug!(
+            "Spawning RenderWindow for this DesktopWindow: {:?}",
+            desktop_window
+        );
+
+        // Windows spanning multiple monitors get their hint on the monitor showing most of them.
+        let (monitor, (pos, size)) = match geometry::find_spanned_monitor(desktop_window, monitors)
+        {
+            Some((monitor, part)) if !app_config.fill => {
+                (Some(monitor), ((part.0, part.1), (part.2, part.3)))
+            }
+            _ => (
+                geometry::find_monitor(desktop_window, monitors),
+                (desktop_window.pos, desktop_window.size),
+            ),
+        };
+
+        let (x, y) = if desktop_window.is_listed {
+            let y = *list_y
+                .get_or_insert(desktop_window.pos.1 + desktop_window.size.1 / 2 - list_height / 2);
+            list_y = Some(y + i32::from(height));
+            (
+                (desktop_window.pos.0 + desktop_window.size.0 / 2 - i32::from(width) / 2) as i16,
+                y as i16,
+            )
+        } else {
+            // Percentages in the offset are relative to either the window or its monitor.
+            let reference_size = match (app_config.offset_relative_to, monitor) {
+                (args::OffsetRelativeTo::Monitor, Some(monitor)) => {
+                    (monitor.rect.2, monitor.rect.3)
+                }
+                _ => size,
+            };
+
+            let x_offset = app_config.offset.x.resolve(reference_size.0);
+            let x = match app_config.horizontal_align {
+                args::HorizontalAlign::Left => (pos.0 + x_offset) as i16,
+                args::HorizontalAlign::Center => (pos.0 + size.0 / 2 - i32::from(width) / 2) as i16,
+                args::HorizontalAlign::Right => {
+                    (pos.0 + size.0 - i32::from(width) - x_offset) as i16
+                }
+            };
+
+            let y_offset = app_config.offset.y.resolve(reference_size.1);
+            let y = match app_config.vertical_align {
+                args::VerticalAlign::Top => (pos.1 + y_offset) as i16,
+                args::VerticalAlign::Center => (pos.1 + size.1 / 2 - i32::from(height) / 2) as i16,
+                args::VerticalAlign::Bottom => {
+                    (pos.1 + size.1 - i32::from(height) - y_offset) as i16
+                }
+            };
+
+            // Outside placements move the hint off the window, which the hint may then use all
+            // around it.
+            let side = match app_config.placement {
+                args::Placement::Inside => (0, 0),
+                args::Placement::OutsideTop => (0, -1),
+                args::Placement::OutsideCorner => (
+                    match app_config.horizontal_align {
+                        args::HorizontalAlign::Left => -1,
+                        args::HorizontalAlign::Center => 0,
+                        args::HorizontalAlign::Right => 1,
+                    },
+                    match app_config.vertical_align {
+                        args::VerticalAlign::Top => -1,
+                        args::VerticalAlign::Center => 0,
+                        args::VerticalAlign::Bottom => 1,
+                    },
+                ),
+            };
+            let rect = (x.into(), y.into(), width.into(), height.into());
+            let (x, y) = geometry::outside_rect(rect, (pos.0, pos.1, size.0, size.1), side);
+            let bounds = match side {
+                (0, 0) => (pos.0, pos.1, size.0, size.1),
+                _ => (
+                    pos.0 - i32::from(width),
+                    pos.1 - i32::from(height),
+                    size.0 + 2 * i32::from(width),
+                    size.1 + 2 * i32::from(height),
+                ),
+            };
+
+            // If this is overlapping then we'll move the new RenderWindow out of the way.
+            let taken: Vec<Rect> = render_windows
+                .values()
+                .map(|rw: &RenderWindow| rw.rect)
+                .collect();
+            let (x, y) = geometry::place_rect((x, y, width.into(), height.into()), &taken, bounds);
+            (x as i16, y as i16)
+        };
+
+        // Make sure the whole hint is visible on its monitor.
+        let (x, y) = match monitor {
+            Some(monitor) => {
+                let (x, y) = geometry::clamp_to_rect(
+                    (x.into(), y.into(), width.into(), height.into()),
+                    monitor.rect,
+                );
+                (x as i16, y as i16)
+            }
+            None => (x, y),
+        };
+
+        let (xcb_window_id, cairo_context, backdrop) = create_window((x, y, width, height))?;
+
+        let render_window = RenderWindow {
+            desktop_window,
+            xcb_window_id,
+            cairo_context,
+            draw_pos,
+            rect: (x.into(), y.into(), width.into(), height.into()),
+            font_size,
+            backdrop,
+        };
+
+        render_windows.insert(hint, render_window);
+    }
+    stats.record("window creation", creation_start.elapsed());
+
+    Ok(render_windows)
+}
+
+/// Capture what's on the screen in `rect` (before any hints are shown there).
+///
+/// Only the usual 24 bit visuals are supported, `None` is returned for anything else.
+pub fn capture_backdrop(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    (x, y, width, height): (i16, i16, u16, u16),
+) -> Result<Option<cairo::ImageSurface>> {
+    let image = conn
+        .get_image(
+            xproto::ImageFormat::Z_PIXMAP,
+            screen.root,
+            x,
+            y,
+            width,
+            height,
+            !0,
+        )?
+        .reply()
+        .context("Couldn't capture the screen")?;
+    let stride = cairo::Format::Rgb24
+        .stride_for_width(width.into())
+        .context("Couldn't figure out the stride")?;
+    if image.depth != 24 || image.data.len() != stride as usize * usize::from(height) {
+        debug!("Can't use a screen capture of depth {}", image.depth);
+        return Ok(None);
+    }
+    let backdrop = cairo::ImageSurface::create_for_data(
+        image.data,
+        cairo::Format::Rgb24,
+        width.into(),
+        height.into(),
+        stride,
+    )
+    .context("Couldn't create ImageSurface")?;
+    Ok(Some(backdrop))
+}
+
+/// Create (but don't map) an override-redirect window at `rect` that is see-through according to
+/// the background color and a Cairo context to draw onto it.
+///
+/// Without a compositor, what's on the screen at `rect` is captured as the backdrop to blend the
+/// hint with. Nothing is flushed so that many windows can be created in one go, `transparency` is
+/// passed in for the same reason.
+pub fn create_overlay_window(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &AppConfig,
+    transparency: Transparency,
+    rect: (i16, i16, u16, u16),
+) -> Result<OverlayWindow> {
+    let (x, y, width, height) = rect;
+    let backdrop = match transparency {
+        Transparency::Pseudo if app_config.bg_color.3 < 1.0 => capture_backdrop(conn, screen, rect)
+            .unwrap_or_else(|e| {
+                warn!("Hints won't be see-through: {e:#}");
+                None
+            }),
+        _ => None,
+    };
+    let xcb_window_id = conn.generate_id()?;
+
+    let win_aux = xproto::CreateWindowAux::new()
+        .event_mask(
+            xproto::EventMask::EXPOSURE
+                | xproto::EventMask::KEY_PRESS
+                | xproto::EventMask::BUTTON_PRESS
+                | xproto::EventMask::BUTTON_RELEASE,
+        )
+        .backing_pixel(screen.black_pixel)
+        .override_redirect(1);
+
+    // Create the actual window.
+    xproto::create_window(
+        conn,
+        x11rb::COPY_FROM_PARENT as u8,
+        xcb_window_id,
+        screen.root,
+        x,
+        y,
+        width,
+        height,
+        0,
+        xproto::WindowClass::INPUT_OUTPUT,
+        screen.root_visual,
+        &win_aux,
+    )?;
+
+    // Set transparency.
+    if let Transparency::Compositor(opacity_atom) = transparency {
+        let opacity = (0xFFFFFFFFu64 as f64 * app_config.bg_color.3) as u64;
+        conn.change_property32(
+            xproto::PropMode::REPLACE,
+            xcb_window_id,
+            opacity_atom,
+            xproto::AtomEnum::CARDINAL,
+            &[opacity as u32],
+        )?;
+    }
+
+    // Let the mouse through to whatever is below the hint.
+    if conn
+        .extension_information(shape::X11_EXTENSION_NAME)?
+        .is_some()
+    {
+        conn.shape_rectangles(
+            shape::SO::SET,
+            shape::SK::INPUT,
+            xproto::ClipOrdering::UNSORTED,
+            xcb_window_id,
+            0,
+            0,
+            &[],
+        )?;
+    } else {
+        debug!("No Shape extension, hints will catch the mouse");
+    }
+
+    let mut visual =
+        utils::find_xcb_visualtype(conn, screen.root_visual).context("Couldn't find visual")?;
+    let cairo_conn =
+        unsafe { cairo::XCBConnection::from_raw_none(conn.get_raw_xcb_connection() as _) };
+    let cairo_visual = unsafe { cairo::XCBVisualType::from_raw_none(&mut visual as *mut _ as _) };
+
+    let surface = cairo::XCBSurface::create(
+        &cairo_conn,
+        &cairo::XCBDrawable(xcb_window_id),
+        &cairo_visual,
+        width.into(),
+        height.into(),
+    )
+    .context("Couldn't create Cairo Surface")?;
+    let cairo_context = cairo::Context::new(&surface).context("Couldn't create Cairo Context")?;
+
+    Ok((xcb_window_id, cairo_context, backdrop))
+}
+
+/// Shown while keys are typed into a focused window because the keyboard couldn't be grabbed.
+const FOCUS_FALLBACK_MESSAGE: &str = "Keyboard is grabbed by another program, typing in here";
+
+/// How long hints light up after a key that was of no use with `--flash`.
+const FLASH_DURATION: Duration = Duration::from_millis(80);
+
+/// Show `message` in a box whose top edge is centered on `top_center`, created and mapped but not
+/// drawn yet.
+fn message_window<'a>(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &AppConfig,
+    transparency: Transparency,
+    indicator: &'a DesktopWindow,
+    message: &str,
+    top_center: (i32, i32),
+) -> Result<RenderWindow<'a>> {
+    let text_extents = utils::extents_for_text(
+        message,
+        &app_config.font.font_family,
+        app_config.font.font_size,
+    )?;
+    let margin_factor = 1.0 + 0.2;
+    let width = (text_extents.width() * margin_factor).round();
+    let height = (text_extents.height() * margin_factor).round();
+    let draw_pos = (
+        (width - text_extents.width()) / 2.0 - text_extents.x_bearing(),
+        (height - text_extents.height()) / 2.0 - text_extents.y_bearing(),
+    );
+    let (x, y) = (top_center.0 - width as i32 / 2, top_center.1);
+
+    let (xcb_window_id, cairo_context, backdrop) = create_overlay_window(
+        conn,
+        screen,
+        app_config,
+        transparency,
+        (x as i16, y as i16, width as u16, height as u16),
+    )?;
+    conn.map_window(xcb_window_id)?;
+    Ok(RenderWindow {
+        desktop_window: indicator,
+        xcb_window_id,
+        cairo_context,
+        draw_pos,
+        rect: (x, y, width as i32, height as i32),
+        font_size: app_config.font.font_size,
+        backdrop,
+    })
+}
+
+/// Show `FOCUS_FALLBACK_MESSAGE` at the top of the screen and give that window the keyboard focus,
+/// for when the keyboard can't be grabbed.
+///
+/// Returns the window along with whatever had the focus before, which should get it back later.
+fn focus_fallback_window<'a>(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &AppConfig,
+    transparency: Transparency,
+    indicator: &'a DesktopWindow,
+) -> Result<(RenderWindow<'a>, xproto::Window)> {
+    let previous_focus = conn.get_input_focus()?.reply()?.focus;
+    let render_window = message_window(
+        conn,
+        screen,
+        app_config,
+        transparency,
+        indicator,
+        FOCUS_FALLBACK_MESSAGE,
+        (i32::from(screen.width_in_pixels) / 2, 0),
+    )?;
+    let focused = conn
+        .set_input_focus(
+            xproto::InputFocus::PARENT,
+            render_window.xcb_window_id,
+            x11rb::CURRENT_TIME,
+        )?
+        .check();
+    if let Err(e) = focused {
+        conn.destroy_window(render_window.xcb_window_id)?;
+        return Err(e).context("Couldn't focus the fallback window");
+    }
+    Ok((render_window, previous_focus))
+}
+
+/// Shown on a window while it follows the pointer, see `place_window`.
+const PLACE_MESSAGE: &str = "Click to drop";
+
+/// Let the floating `window` follow the pointer until a click drops it, with `PLACE_MESSAGE` shown
+/// in its middle.
+///
+/// The pointer is warped into the middle of `window` first. `move_to` is asked to move the window to
+/// a new position whenever the pointer moved. With the cancel key, it's moved back to where it was
+/// and `false` is returned.
+pub fn place_window(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &AppConfig,
+    window: &DesktopWindow,
+    move_to: &mut dyn FnMut((i32, i32)) -> Result<()>,
+) -> Result<bool> {
+    let half = (window.size.0 / 2, window.size.1 / 2);
+    utils::warp_and_click(
+        conn,
+        screen,
+        (window.pos.0 + half.0, window.pos.1 + half.1),
+        false,
+    )?;
+    utils::snatch_keyboard(
+        conn,
+        screen,
+        app_config.grab_timeout,
+        app_config.grab_retry_interval,
+    )?;
+    utils::snatch_mouse(
+        conn,
+        screen,
+        app_config.grab_timeout,
+        app_config.grab_retry_interval,
+    )?;
+    conn.change_active_pointer_grab(
+        x11rb::NONE,
+        x11rb::CURRENT_TIME,
+        xproto::EventMask::BUTTON_PRESS | xproto::EventMask::POINTER_MOTION,
+    )?;
+
+    let transparency = Transparency::detect(conn, screen)?;
+    let message = message_window(
+        conn,
+        screen,
+        app_config,
+        transparency,
+        window,
+        PLACE_MESSAGE,
+        (window.pos.0 + half.0, window.pos.1 + half.1),
+    )?;
+    conn.flush()?;
+
+    let keymap = utils::Keymap::fetch(conn)?;
+    let mut pos = window.pos;
+    let dropped = loop {
+        let mut event = conn.wait_for_event()?;
+        // Only the latest of the pointer motions queued up so far matters.
+        while let Event::MotionNotify(_) = event {
+            match conn.poll_for_event()? {
+                Some(next) => event = next,
+                None => break,
+            }
+        }
+        match event {
+            Event::Expose(_) => {
+                utils::draw_hint_text(&message, app_config, PLACE_MESSAGE, "")
+                    .context("Couldn't draw the placing message")?;
+                conn.flush()?;
+            }
+            Event::MotionNotify(event) => {
+                pos = (
+                    i32::from(event.root_x) - half.0,
+                    i32::from(event.root_y) - half.1,
+                );
+                move_to(pos)?;
+                let message_pos = (
+                    message.rect.0 + pos.0 - window.pos.0,
+                    message.rect.1 + pos.1 - window.pos.1,
+                );
+                conn.configure_window(
+                    message.xcb_window_id,
+                    &xproto::ConfigureWindowAux::new()
+                        .x(message_pos.0)
+                        .y(message_pos.1),
+                )?;
+                conn.flush()?;
+            }
+            Event::ButtonPress(_) => break true,
+            Event::KeyPress(event) => {
+                let Some(kstr) = keymap.key_name(&event) else {
+                    continue;
+                };
+                let mut sequence = Sequence::default();
+                sequence.push(&kstr);
+                if sequence.with_modifiers(event.state.into()) == app_config.cancel_key {
+                    break false;
+                }
+            }
+            _ => {}
+        }
+    };
+    if !dropped && pos != window.pos {
+        info!("Moving the window back");
+        move_to(window.pos)?;
+    }
+
+    conn.destroy_window(message.xcb_window_id)?;
+    utils::release_input(conn)?;
+    conn.flush()?;
+    Ok(dropped)
+}
+
+/// What the user did with the hints shown by `select_window`.
+#[derive(Debug)]
+pub enum Selection {
+    /// The user picked this `DesktopWindow`.
+    Window(Box<DesktopWindow>),
+    /// The user wants to see hints for the next mode instead.
+    NextMode,
+    /// The user gave up.
+    Canceled,
+    /// The user typed something that doesn't match any hint.
+    NoMatch,
+    /// Windows were opened or closed while the hints were shown, so they're out of date.
+    Changed,
+}
+
+/// Tells `select_window` when windows are opened or closed while it waits.
+pub trait WindowChanges: AsFd {
+    /// Read what became readable on the descriptor and tell whether windows were opened or
+    /// closed.
+    fn changed(&self) -> Result<bool>;
+}
+
+/// Redraw only those of `render_windows` that look different with `pressed_keys` typed than with
+/// `drawn_keys`.
+fn redraw_changed_hints(
+    conn: &XCBConnection,
+    app_config: &AppConfig,
+    render_windows: &HashMap<String, RenderWindow>,
+    drawn_keys: &str,
+    pressed_keys: &str,
+) -> Result<()> {
+    for (hint, rw) in render_windows {
+        if hints::typed_len(hint, drawn_keys) != hints::typed_len(hint, pressed_keys) {
+            utils::draw_hint_text(rw, app_config, hint, pressed_keys)
+                .context("Couldn't draw hint text")?;
+        }
+    }
+    conn.flush()?;
+    Ok(())
+}
+
+/// How many hints may still match what was typed for them to be zoomed, see `--zoom`.
+const ZOOM_CANDIDATES: usize = 3;
+
+/// Enlarged copies of the hints under the pointer or still in the running, see `--zoom`.
+struct Zoom<'a> {
+    transparency: Transparency,
+    /// The hint under the pointer, if any.
+    hovered: Option<String>,
+    windows: HashMap<String, RenderWindow<'a>>,
+    /// What was typed when `windows` were last drawn.
+    drawn_keys: String,
+}
+
+impl<'a> Zoom<'a> {
+    fn new(transparency: Transparency) -> Zoom<'a> {
+        Zoom {
+            transparency,
+            hovered: None,
+            windows: HashMap::new(),
+            drawn_keys: String::new(),
+        }
+    }
+
+    /// Whether `window` is one of the zoomed hints.
+    fn owns(&self, window: xproto::Window) -> bool {
+        self.windows.values().any(|rw| rw.xcb_window_id == window)
+    }
+
+    /// Remember which of `render_windows` the pointer at `pos` is over.
+    ///
+    /// A zoomed hint counts as hovered as long as the pointer is over its enlarged box.
+    fn hover(&mut self, render_windows: &HashMap<String, RenderWindow>, pos: (i32, i32)) {
+        self.hovered = self
+            .windows
+            .iter()
+            .chain(render_windows)
+            .find(|(_, rw)| geometry::contains(rw.rect, pos))
+            .map(|(hint, _)| hint.clone());
+    }
+
+    /// Zoom the hovered hint and the ones of `render_windows` still matching `pressed_keys` if
+    /// there are few enough of them, and unzoom all others.
+    fn update(
+        &mut self,
+        conn: &XCBConnection,
+        screen: &xproto::Screen,
+        app_config: &AppConfig,
+        render_windows: &HashMap<String, RenderWindow<'a>>,
+        pressed_keys: &str,
+    ) -> Result<()> {
+        let Some(factor) = app_config.zoom else {
+            return Ok(());
+        };
+        let matching: Vec<&String> = render_windows
+            .keys()
+            .filter(|hint| !pressed_keys.is_empty() && hint.starts_with(pressed_keys))
+            .collect();
+        let mut wanted: HashSet<&String> = HashSet::new();
+        if matching.len() <= ZOOM_CANDIDATES {
+            wanted.extend(matching);
+        }
+        wanted.extend(
+            self.hovered
+                .as_ref()
+                .filter(|hint| render_windows.contains_key(*hint)),
+        );
+
+        let unwanted: Vec<String> = self
+            .windows
+            .keys()
+            .filter(|hint| !wanted.contains(hint))
+            .cloned()
+            .collect();
+        for hint in unwanted {
+            if let Some(rw) = self.windows.remove(&hint) {
+                conn.destroy_window(rw.xcb_window_id)?;
+            }
+        }
+
+        let screen_rect = (
+            0,
+            0,
+            screen.width_in_pixels.into(),
+            screen.height_in_pixels.into(),
+        );
+        for hint in wanted {
+            if self.windows.contains_key(hint) {
+                continue;
+            }
+            let rw = &render_windows[hint];
+            let (_, _, width, height) = geometry::zoom_rect(rw.rect, factor);
+            let (x, y) = geometry::clamp_to_rect(geometry::zoom_rect(rw.rect, factor), screen_rect);
+            let (xcb_window_id, cairo_context, backdrop) = create_overlay_window(
+                conn,
+                screen,
+                app_config,
+                self.transparency,
+                (x as i16, y as i16, width as u16, height as u16),
+            )?;
+            conn.map_window(xcb_window_id)?;
+            // It's drawn once it's exposed.
+            let zoomed = RenderWindow {
+                desktop_window: rw.desktop_window,
+                xcb_window_id,
+                cairo_context,
+                draw_pos: (rw.draw_pos.0 * factor, rw.draw_pos.1 * factor),
+                rect: (x, y, width, height),
+                font_size: rw.font_size * factor,
+                backdrop,
+            };
+            self.windows.insert(hint.clone(), zoomed);
+        }
+
+        if self.drawn_keys != pressed_keys {
+            self.draw(app_config, pressed_keys)?;
+        }
+        conn.flush()?;
+        Ok(())
+    }
+
+    /// Draw all zoomed hints with `pressed_keys` typed.
+    fn draw(&mut self, app_config: &AppConfig, pressed_keys: &str) -> Result<()> {
+        for (hint, rw) in &self.windows {
+            utils::draw_hint_text(rw, app_config, hint, pressed_keys)
+                .context("Couldn't draw zoomed hint")?;
+        }
+        self.drawn_keys = pressed_keys.to_string();
+        Ok(())
+    }
+
+    /// Put the zoomed hints back on top of the others.
+    fn raise(&self, conn: &XCBConnection) -> Result<()> {
+        raise_hints(conn, &self.windows)
+    }
+
+    /// Unzoom all hints.
+    fn clear(&mut self, conn: &XCBConnection) -> Result<()> {
+        for (_, rw) in self.windows.drain() {
+            conn.destroy_window(rw.xcb_window_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// How often the countdown bars of `--timeout` are redrawn.
+const COUNTDOWN_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Show on `render_windows` how much of `--timeout` is left until `deadline`, if there is one.
+fn draw_countdowns(
+    conn: &XCBConnection,
+    app_config: &AppConfig,
+    render_windows: &HashMap<String, RenderWindow>,
+    deadline: Option<Instant>,
+) -> Result<()> {
+    let (Some(deadline), Some(timeout)) = (deadline, app_config.timeout) else {
+        return Ok(());
+    };
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let fraction = remaining.as_secs_f64() / timeout.as_secs_f64();
+    for rw in render_windows.values() {
+        utils::draw_countdown(rw, app_config, fraction).context("Couldn't draw countdown")?;
+    }
+    conn.flush()?;
+    Ok(())
+}
+
+/// Let the user know that a key was of no use by ringing the bell and/or flashing the hints, if
+/// `app_config` says so.
+fn reject_key(
+    conn: &XCBConnection,
+    app_config: &AppConfig,
+    render_windows: &HashMap<String, RenderWindow>,
+    pressed_keys: &str,
+) -> Result<()> {
+    if app_config.bell {
+        conn.bell(0)?;
+    }
+    if app_config.flash {
+        for rw in render_windows.values() {
+            utils::draw_flash(rw, app_config).context("Couldn't flash hint")?;
+        }
+        conn.flush()?;
+        thread::sleep(FLASH_DURATION);
+        for (hint, rw) in render_windows {
+            utils::draw_hint_text(rw, app_config, hint, pressed_keys)
+                .context("Couldn't draw hint text")?;
+        }
+    }
+    conn.flush()?;
+    Ok(())
+}
+
+/// Where each hint of `rw` keeps its distance to within its window, see `geometry::follow_rect`.
+fn hint_anchor(app_config: &AppConfig, rw: &RenderWindow) -> (f64, f64) {
+    if rw.desktop_window.is_listed {
+        return (0.5, 0.5);
+    }
+    let x = match app_config.horizontal_align {
+        args::HorizontalAlign::Left => 0.0,
+        args::HorizontalAlign::Center => 0.5,
+        args::HorizontalAlign::Right => 1.0,
+    };
+    let y = match app_config.vertical_align {
+        args::VerticalAlign::Top => 0.0,
+        args::VerticalAlign::Center => 0.5,
+        args::VerticalAlign::Bottom => 1.0,
+    };
+    (x, y)
+}
+
+/// Where the X window `window` is on the root window right now.
+fn root_geometry(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    window: xproto::Window,
+) -> Result<Rect> {
+    let geometry = conn.get_geometry(window)?.reply()?;
+    let translated = conn
+        .translate_coordinates(window, screen.root, 0, 0)?
+        .reply()?;
+    Ok((
+        translated.dst_x.into(),
+        translated.dst_y.into(),
+        geometry.width.into(),
+        geometry.height.into(),
+    ))
+}
+
+/// Start listening for the X windows behind `pages` being moved or resized and return where each
+/// of them is right now.
+///
+/// Windows that are already gone are left out.
+fn watch_windows(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    pages: &[HashMap<String, RenderWindow>],
+) -> Result<HashMap<xproto::Window, Rect>> {
+    let aux =
+        xproto::ChangeWindowAttributesAux::new().event_mask(xproto::EventMask::STRUCTURE_NOTIFY);
+    let mut geometries = HashMap::new();
+    for rw in pages.iter().flat_map(|page| page.values()) {
+        let Some(window) = rw.desktop_window.x_window_id.map(|id| id as xproto::Window) else {
+            continue;
+        };
+        conn.change_window_attributes(window, &aux)?;
+        match root_geometry(conn, screen, window) {
+            Ok(geometry) => {
+                geometries.insert(window, geometry);
+            }
+            Err(e) => debug!("Not following window 0x{window:x}: {e}"),
+        }
+    }
+    Ok(geometries)
+}
+
+/// Start hearing about windows being mapped or restacked on top of the hints in `pages`, see
+/// `raise_hints`, and ask to keep them above everything else where that's honored.
+fn watch_stacking(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    pages: &[HashMap<String, RenderWindow>],
+) -> Result<()> {
+    let aux =
+        xproto::ChangeWindowAttributesAux::new().event_mask(xproto::EventMask::SUBSTRUCTURE_NOTIFY);
+    conn.change_window_attributes(screen.root, &aux)?;
+    // Override-redirect windows aren't managed, but compositors might still look at this.
+    let state = utils::intern_atom(conn, "_NET_WM_STATE")?;
+    let above = utils::intern_atom(conn, "_NET_WM_STATE_ABOVE")?;
+    for rw in pages.iter().flat_map(|page| page.values()) {
+        conn.change_property32(
+            xproto::PropMode::REPLACE,
+            rw.xcb_window_id,
+            state,
+            xproto::AtomEnum::ATOM,
+            &[above],
+        )?;
+    }
+    Ok(())
+}
+
+/// Put `render_windows` back on top after some other window got raised above them.
+fn raise_hints(conn: &XCBConnection, render_windows: &HashMap<String, RenderWindow>) -> Result<()> {
+    let aux = xproto::ConfigureWindowAux::new().stack_mode(xproto::StackMode::ABOVE);
+    for rw in render_windows.values() {
+        conn.configure_window(rw.xcb_window_id, &aux)?;
+    }
+    conn.flush()?;
+    Ok(())
+}
+
+/// Move the hints of `window` along with it now that it is at `geometry`.
+///
+/// `initial` is where `window` was when the hints were placed.
+fn follow_window(
+    conn: &XCBConnection,
+    app_config: &AppConfig,
+    pages: &[HashMap<String, RenderWindow>],
+    window: xproto::Window,
+    initial: Rect,
+    geometry: Rect,
+) -> Result<()> {
+    for rw in pages.iter().flat_map(|page| page.values()) {
+        if rw.desktop_window.x_window_id != Some(window as i32) {
+            continue;
+        }
+        let (x, y) = geometry::follow_rect(rw.rect, initial, geometry, hint_anchor(app_config, rw));
+        debug!("Moving hint for window 0x{window:x} to {x}x{y}");
+        conn.configure_window(
+            rw.xcb_window_id,
+            &xproto::ConfigureWindowAux::new().x(x).y(y),
+        )?;
+    }
+    conn.flush()?;
+    Ok(())
+}
+
+/// Show hints for `desktop_windows` and wait for the user to pick one of them.
+///
+/// If `changes` reports opened or closed windows in the meantime, `Selection::Changed` is returned
+/// so that the hints can be shown again for the current windows.
+///
+/// All hint windows are destroyed and keyboard and mouse are released again before returning.
+#[allow(clippy::too_many_arguments)]
+pub fn select_window(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    app_config: &AppConfig,
+    desktop_windows: &[DesktopWindow],
+    monitors: &[DesktopOutput],
+    events: &events::Events,
+    stats: &stats::Stats,
+    changes: Option<&dyn WindowChanges>,
+) -> Result<Selection> {
+    // If there are too many windows, we'll only show hints for some of them at a time.
+    let transparency = Transparency::detect(conn, screen)?;
+    let pages = match hints::paginate(desktop_windows, app_config.max_hints)
+        .iter()
+        .map(|page| {
+            create_render_windows(app_config, page, monitors, stats, |rect| {
+                create_overlay_window(conn, screen, app_config, transparency, rect)
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+    {
+        Ok(pages) => pages,
+        Err(e) => match fallback(Failure::Overlay, app_config.no_fallback, app_config.stdin) {
+            Fallback::List => return select_from_list(app_config, desktop_windows, events, e),
+            _ => return Err(e),
+        },
+    };
+    let indicator_window = DesktopWindow {
+        is_focused: true,
+        ..Default::default()
+    };
+    // Whichever way the selection ends, `remove_hints` cleans up after it.
+    let mut focus_fallback = None;
+    let mut watched = HashMap::new();
+    let mut zoom = Zoom::new(transparency);
+    let result = (|| -> Result<Selection> {
+        let mut page = 0;
+        let mut render_windows = &pages[page];
+        // Keys are handled right away either way, but the hints might only show up later.
+        let draw_at = app_config.draw_delay.map(|delay| Instant::now() + delay);
+        let mut mapped = draw_at.is_none();
+        if mapped {
+            for rw in render_windows.values() {
+                conn.map_window(rw.xcb_window_id)?;
+            }
+        }
+        conn.flush()?;
+        events.hints_shown(
+            render_windows
+                .iter()
+                .map(|(hint, rw)| (hint, rw.desktop_window)),
+        );
+
+        // Receive keyboard and mouse events. If some other program holds on to the keyboard, keys
+        // typed into a focused window of ours have to do.
+        if let Err(e) = utils::snatch_keyboard(
+            conn,
+            screen,
+            app_config.grab_timeout,
+            app_config.grab_retry_interval,
+        ) {
+            let focused = match fallback(
+                Failure::KeyboardGrab,
+                app_config.no_fallback,
+                app_config.stdin,
+            ) {
+                Fallback::FocusWindow => {
+                    focus_fallback_window(conn, screen, app_config, transparency, &indicator_window)
+                        .map_err(|focus_error| e.context(focus_error))
+                }
+                _ => Err(e),
+            };
+            match focused {
+                Ok(focused) => {
+                    warn!("Couldn't grab the keyboard, typing into a focused window instead");
+                    focus_fallback = Some(focused);
+                }
+                // The hints are already up, so this is a grab error rather than a reason for a list.
+                Err(e) => return Err(e),
+            }
+        }
+        // The mouse is only taken if something needs it, otherwise it goes through the hints to the
+        // windows below. Without it, clicking somewhere just doesn't cancel.
+        if !wants_pointer(app_config) {
+            debug!("Leaving the mouse alone");
+        } else if let Err(e) = utils::snatch_mouse(
+            conn,
+            screen,
+            app_config.grab_timeout,
+            app_config.grab_retry_interval,
+        ) {
+            warn!("{e:#}, the mouse won't do anything");
+        } else if app_config.zoom.is_some() {
+            // Hints under the pointer are zoomed.
+            conn.change_active_pointer_grab(
+                x11rb::NONE,
+                x11rb::CURRENT_TIME,
+                xproto::EventMask::BUTTON_PRESS | xproto::EventMask::POINTER_MOTION,
+            )?;
+        }
+
+        // Keys are translated with the layout that's active when they're typed, which the user might
+        // switch to only now.
+        let mut keymap = utils::Keymap::fetch(conn)?;
+        if let Err(e) = utils::watch_keymap(conn) {
+            debug!("Only following core keyboard mapping changes: {e:#}");
+        }
+
+        // Hints follow their windows when those are moved or resized while we wait.
+        watched = watch_windows(conn, screen, &pages)?;
+        // Newly raised windows or notifications shouldn't cover the hints.
+        watch_stacking(conn, screen, &pages)?;
+        let own_windows: HashSet<xproto::Window> = pages
+            .iter()
+            .flat_map(|page| page.values())
+            .chain(focus_fallback.as_ref().map(|(indicator, _)| indicator))
+            .map(|rw| rw.xcb_window_id)
+            .collect();
+
+        // Since we might have lots of windows on the desktop, it might be required
+        // to enter a sequence in order to get to the correct window.
+        // We'll have to track the keys pressed so far.
+        let mut pressed_keys = String::default();
+        // What was typed when the hints were last drawn.
+        let mut drawn_keys = String::default();
+        let mut sequence = Sequence::default();
+
+        let sources = changes.map(|changes| changes.as_fd()).into_iter().collect();
+        let mut event_loop = event_loop::EventLoop::new(conn, sources);
+        let deadline = app_config.timeout.map(|timeout| Instant::now() + timeout);
+
+        let mut selection = Selection::Canceled;
+        let mut closed = false;
+        let mut drawn = false;
+        while !closed {
+            if mapped {
+                zoom.update(conn, screen, app_config, render_windows, &pressed_keys)?;
+            }
+            // Wake up in between to let the countdown bars shrink and to show delayed hints.
+            let countdown =
+                deadline.map(|deadline| deadline.min(Instant::now() + COUNTDOWN_INTERVAL));
+            let delayed = draw_at.filter(|_| !mapped);
+            event_loop.set_deadline(countdown.into_iter().chain(delayed).min());
+            let event = match event_loop.next_event()? {
+                event_loop::LoopEvent::X(event) => event,
+                event_loop::LoopEvent::Timeout
+                    if delayed.is_some_and(|draw_at| Instant::now() >= draw_at) =>
+                {
+                    debug!("Showing the hints now");
+                    for rw in render_windows.values() {
+                        conn.map_window(rw.xcb_window_id)?;
+                    }
+                    conn.flush()?;
+                    mapped = true;
+                    continue;
+                }
+                event_loop::LoopEvent::Timeout
+                    if deadline.is_some_and(|deadline| Instant::now() < deadline) =>
+                {
+                    if drawn {
+                        draw_countdowns(conn, app_config, render_windows, deadline)?;
+                    }
+                    continue;
+                }
+                event_loop::LoopEvent::Timeout => {
+                    info!("Nothing was selected in time");
+                    break;
+                }
+                event_loop::LoopEvent::Readable(_) => {
+                    let Some(changes) = changes else {
+                        continue;
+                    };
+                    match changes.changed() {
+                        Ok(true) => {
+                            info!("Windows were opened or closed, updating hints");
+                            selection = Selection::Changed;
+                            break;
+                        }
+                        Ok(false) => {}
+                        Err(e) => {
+                            // Don't keep waking up for a source that's broken.
+                            warn!("Can't follow window changes anymore: {e:#}");
+                            event_loop = event_loop::EventLoop::new(conn, vec![]);
+                        }
+                    }
+                    continue;
+                }
+            };
+            let event_option = Some(event);
+            if let Some(e) = event_option {
+                match e {
+                    Event::Expose(_) => {
+                        let draw_start = Instant::now();
+                        for (hint, rw) in render_windows {
+                            utils::draw_hint_text(rw, app_config, hint, &pressed_keys)
+                                .context("Couldn't draw hint text")?;
+                        }
+                        if let Some((indicator, _)) = &focus_fallback {
+                            utils::draw_hint_text(
+                                indicator,
+                                app_config,
+                                FOCUS_FALLBACK_MESSAGE,
+                                "",
+                            )
+                            .context("Couldn't draw the keyboard indicator")?;
+                        }
+                        draw_countdowns(conn, app_config, render_windows, deadline)?;
+                        zoom.draw(app_config, &pressed_keys)?;
+                        conn.flush()?;
+                        drawn_keys.clone_from(&pressed_keys);
+                        if !drawn {
+                            drawn = true;
+                            stats.record("first draw", draw_start.elapsed());
+                            stats.record_since_start("until hints were drawn");
+                        }
+                    }
+                    Event::ButtonPress(_) if app_config.click_cancel => {
+                        closed = true;
+                    }
+                    Event::MotionNotify(event) => {
+                        zoom.hover(render_windows, (event.root_x.into(), event.root_y.into()));
+                    }
+                    Event::ConfigureNotify(event)
+                        if event.event == screen.root
+                            && !own_windows.contains(&event.window)
+                            && !zoom.owns(event.window) =>
+                    {
+                        raise_hints(conn, render_windows)?;
+                        zoom.raise(conn)?;
+                    }
+                    Event::MapNotify(event)
+                        if event.event == screen.root
+                            && !own_windows.contains(&event.window)
+                            && !zoom.owns(event.window) =>
+                    {
+                        raise_hints(conn, render_windows)?;
+                        zoom.raise(conn)?;
+                    }
+                    Event::ConfigureNotify(event) => {
+                        let Some(initial) = watched.get(&event.window) else {
+                            continue;
+                        };
+                        // The coordinates in the event are relative to the window's parent, which
+                        // is a frame of the window manager more often than not.
+                        match root_geometry(conn, screen, event.window) {
+                            Ok(geometry) => follow_window(
+                                conn,
+                                app_config,
+                                &pages,
+                                event.window,
+                                *initial,
+                                geometry,
+                            )?,
+                            Err(e) => debug!("Couldn't follow window 0x{:x}: {e}", event.window),
+                        }
+                    }
+                    Event::KeyRelease(event) => {
+                        let Some(kstr) = keymap.key_name(&event) else {
+                            continue;
+                        };
+                        sequence.remove(&kstr);
+                    }
+                    Event::KeyPress(event) => {
+                        let Some(kstr) = keymap.key_name(&event) else {
+                            continue;
+                        };
+
+                        sequence.push(&kstr);
+                        // Sticky modifiers aren't held down anymore, but they're still in the state.
+                        let sequence = sequence.with_modifiers(event.state.into());
+
+                        if sequence == app_config.cancel_key
+                            || app_config.exit_keys.contains(&sequence)
+                        {
+                            info!("{sequence} is an exit sequence");
+                            closed = true;
+                            continue;
+                        }
+
+                        if pages.len() > 1 && sequence == app_config.page_key {
+                            info!("Showing next page of hints");
+                            for rw in render_windows.values() {
+                                conn.unmap_window(rw.xcb_window_id)?;
+                            }
+                            zoom.clear(conn)?;
+                            page = (page + 1) % pages.len();
+                            render_windows = &pages[page];
+                            if mapped {
+                                for rw in render_windows.values() {
+                                    conn.map_window(rw.xcb_window_id)?;
+                                }
+                            }
+                            conn.flush()?;
+                            pressed_keys.clear();
+                            events.hints_shown(
+                                render_windows
+                                    .iter()
+                                    .map(|(hint, rw)| (hint, rw.desktop_window)),
+                            );
+                            continue;
+                        }
+
+                        if !app_config.stdin && app_config.mode_key.as_ref() == Some(&sequence) {
+                            info!("Switching to the next mode");
+                            selection = Selection::NextMode;
+                            closed = true;
+                            continue;
+                        }
+
+                        if sequence == app_config.undo_key {
+                            pressed_keys.pop();
+                            info!("Current key sequence: '{}'", pressed_keys);
+                            events.key_typed(&pressed_keys);
+                            redraw_changed_hints(
+                                conn,
+                                app_config,
+                                render_windows,
+                                &drawn_keys,
+                                &pressed_keys,
+                            )?;
+                            drawn_keys.clone_from(&pressed_keys);
+                            continue;
+                        }
+
+                        // Take the first of the hints still matching what was typed so far.
+                        if sequence == app_config.confirm_key {
+                            let first_match = render_windows
+                                .iter()
+                                .filter(|(hint, _)| hint.starts_with(&pressed_keys))
+                                .min_by_key(|(hint, _)| {
+                                    hint.chars()
+                                        .map(|c| app_config.hint_chars.find(c))
+                                        .collect::<Vec<_>>()
+                                });
+                            if let Some((_, rw)) = first_match {
+                                selection = Selection::Window(Box::new(rw.desktop_window.clone()));
+                                closed = true;
+                            }
+                            continue;
+                        }
+
+                        if app_config.hint_chars.contains(&kstr) {
+                            info!("Adding '{}' to key sequence", kstr);
+                            pressed_keys.push_str(&kstr);
+                            events.key_typed(&pressed_keys);
+                        } else {
+                            warn!("Pressed key '{}' is not a valid hint characters", kstr);
+                            if (app_config.bell || app_config.flash)
+                                && !keys::is_modifier(&kstr)
+                                && !sequence.is_started()
+                            {
+                                reject_key(conn, app_config, render_windows, &pressed_keys)?;
+                                continue;
+                            }
+                        }
+
+                        info!("Current key sequence: '{}'", pressed_keys);
+
+                        // Attempt to match the current sequence of keys as a string to the window
+                        // hints shown.
+                        // If there is an exact match, we're done. We'll then focus the window
+                        // and exit. However, we also want to check whether there is still any
+                        // chance to focus any windows from the current key sequence. If there
+                        // is not then we will also just exit and focus no new window.
+                        // If there still is a chance we might find a window then we'll just
+                        // keep going for now.
+                        if sequence.is_started() {
+                            utils::remove_last_key(&mut pressed_keys, &kstr);
+                        } else if let Some(rw) = &render_windows.get(&pressed_keys) {
+                            selection = Selection::Window(Box::new(rw.desktop_window.clone()));
+                            closed = true;
+                        } else if !pressed_keys.is_empty()
+                            && render_windows.keys().any(|k| k.starts_with(&pressed_keys))
+                        {
+                            redraw_changed_hints(
+                                conn,
+                                app_config,
+                                render_windows,
+                                &drawn_keys,
+                                &pressed_keys,
+                            )?;
+                            drawn_keys.clone_from(&pressed_keys);
+                            continue;
+                        } else {
+                            warn!("No more matches possible with current key sequence");
+                            if app_config.exit_keys.is_empty() {
+                                selection = Selection::NoMatch;
+                                closed = true;
+                            }
+                            utils::remove_last_key(&mut pressed_keys, &kstr);
+                            if !closed {
+                                reject_key(conn, app_config, render_windows, &pressed_keys)?;
+                            }
+                        }
+                    }
+                    _ if utils::is_keymap_change(&e) => {
+                        info!("The keyboard mapping changed");
+                        keymap = utils::Keymap::fetch(conn)?;
+                    }
+                    _ => {}
+                }
+            } else {
+                closed = true;
+            }
+        }
+
+        Ok(selection)
+    })();
+
+    let removed = remove_hints(conn, screen, &pages, &mut zoom, focus_fallback, &watched);
+    let selection = result?;
+    removed?;
+    report_selection(events, &selection);
+    Ok(selection)
+}
+
+/// Destroy the hints in `pages` (and the keyboard indicator of `focus_fallback`), stop listening
+/// for changes to `watched` windows and release keyboard and mouse again.
+fn remove_hints(
+    conn: &XCBConnection,
+    screen: &xproto::Screen,
+    pages: &[HashMap<String, RenderWindow>],
+    zoom: &mut Zoom,
+    focus_fallback: Option<(RenderWindow<'_>, xproto::Window)>,
+    watched: &HashMap<xproto::Window, Rect>,
+) -> Result<()> {
+    for rw in pages.iter().flat_map(|page| page.values()) {
+        conn.destroy_window(rw.xcb_window_id)?;
+    }
+    zoom.clear(conn)?;
+    if let Some((indicator, previous_focus)) = focus_fallback {
+        conn.destroy_window(indicator.xcb_window_id)?;
+        conn.set_input_focus(
+            xproto::InputFocus::POINTER_ROOT,
+            previous_focus,
+            x11rb::CURRENT_TIME,
+        )?;
+    }
+    let unwatch = xproto::ChangeWindowAttributesAux::new().event_mask(xproto::EventMask::NO_EVENT);
+    for window in watched.keys().chain([&screen.root]) {
+        conn.change_window_attributes(*window, &unwatch)?;
+    }
+    utils::release_input(conn)?;
+
+    Ok(())
+}
+
+/// Let whoever listens to `events` know how a selection ended.
+pub fn report_selection(events: &events::Events, selection: &Selection) {
+    match selection {
+        Selection::Window(selected) => events.selection(selected),
+        Selection::Canceled => events.canceled(),
+        Selection::NoMatch => events.no_match(),
+        // There'll be new hints right away.
+        Selection::NextMode | Selection::Changed => {}
+    }
+}
+
+/// Whether hints need the pointer grabbed, for zooming or for clicking to cancel.
+///
+/// The hints let the mouse through to the windows below (see `create_overlay_window`), which a
+/// grab would undo.
+fn wants_pointer(app_config: &AppConfig) -> bool {
+    app_config.zoom.is_some() || app_config.click_cancel
+}
+
+/// What kept hints from being shown the usual way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// There's no X server to show hints on.
+    NoDisplay,
+    /// The hint windows couldn't be created.
+    Overlay,
+    /// Some other program holds on to the keyboard.
+    KeyboardGrab,
+}
+
+/// What to do about a `Failure`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fallback {
+    /// Take the keys typed into a focused window of ours.
+    FocusWindow,
+    /// Print the windows as a list and read the choice from stdin, see `select_from_list`.
+    List,
+    /// Give up with the error, which decides the exit status (see `utils::exit_status`).
+    Fail,
+}
+
+/// Decide what to do about `failure` with `--no-fallback` and `--stdin` set as given.
+///
+/// A list only stands in for hints that can't be shown at all. If it's just the keyboard that's
+/// taken, the hints are up already and not getting keys for them is a grab error.
+pub fn fallback(failure: Failure, no_fallback: bool, stdin: bool) -> Fallback {
+    match failure {
+        _ if no_fallback => Fallback::Fail,
+        Failure::KeyboardGrab => Fallback::FocusWindow,
+        // With --stdin, stdin was already used up for reading the windows.
+        Failure::NoDisplay | Failure::Overlay if stdin => Fallback::Fail,
+        Failure::NoDisplay | Failure::Overlay => Fallback::List,
+    }
+}
+
+/// Print `desktop_windows` as a list and read the choice from stdin, for when no hints can be
+/// shown because of `error`.
+///
+/// This works with `dmenu` and `rofi -dmenu` as well as by typing a hint. Whether to do this at all
+/// is up to `fallback`.
+pub fn select_from_list(
+    app_config: &AppConfig,
+    desktop_windows: &[DesktopWindow],
+    events: &events::Events,
+    error: anyhow::Error,
+) -> Result<Selection> {
+    warn!("Couldn't show hints, falling back to a list: {error:#}");
+
+    let hints: Vec<_> = hints::HintGenerator::new(
+        &app_config.hint_chars,
+        desktop_windows.len(),
+        app_config.hint_strategy,
+    )?
+    .collect();
+    let mut stdout = io::stdout().lock();
+    for (hint, desktop_window) in hints.iter().zip(desktop_windows) {
+        writeln!(stdout, "{}", utils::list_entry(hint, desktop_window))?;
+    }
+    stdout.flush()?;
+    drop(stdout);
+    events.hints_shown(hints.iter().zip(desktop_windows));
+
+    let mut line = String::new();
+    io::stdin()
+        .read_line(&mut line)
+        .context("Couldn't read choice from stdin")?;
+    let selection = match utils::list_choice(&line) {
+        Some(choice) => hints
+            .iter()
+            .position(|hint| hint == choice)
+            .map_or(Selection::NoMatch, |i| {
+                Selection::Window(Box::new(desktop_windows[i].clone()))
+            }),
+        None => Selection::Canceled,
+    };
+    report_selection(events, &selection);
+    Ok(selection)
+}
+
+/// What `Renderer` does with a selection.
+type OnSelect = dyn Fn(&DesktopWindow) -> Result<()>;
+
+/// Shows hints for arbitrary labeled rectangles and lets the user pick one of them, without any
+/// window manager involved.
+///
+/// Hints look and behave the same as they do in wmfocus itself and are configured the same way, eg
+/// with `AppConfig::parse_from(["wmfocus", "--fill"])`. What happens with a selection is up to the
+/// caller, either with the returned `Selection` or a callback given to `Renderer::on_select`.
+pub struct Renderer {
+    conn: XCBConnection,
+    screen_num: usize,
+    app_config: AppConfig,
+    on_select: Option<Box<OnSelect>>,
+}
+
+impl Renderer {
+    /// Connect to the X server to show hints the way `app_config` says.
+    pub fn new(app_config: AppConfig) -> Result<Renderer> {
+        let (conn, screen_num) = XCBConnection::connect(None).context("No Xorg connection")?;
+        Ok(Renderer {
+            conn,
+            screen_num,
+            app_config,
+            on_select: None,
+        })
+    }
+
+    /// Call `on_select` with whatever the user selects, once the hints are gone and keyboard and
+    /// mouse are released again. Errors it returns are returned by `Renderer::show`.
+    pub fn on_select(
+        mut self,
+        on_select: impl Fn(&DesktopWindow) -> Result<()> + 'static,
+    ) -> Renderer {
+        self.on_select = Some(Box::new(on_select));
+        self
+    }
+
+    /// Show a hint with a label for every rectangle in `labels` and wait for the user to pick one.
+    ///
+    /// The `id` of the selected `DesktopWindow` is its index in `labels`.
+    pub fn show(&self, labels: &[(String, Rect)]) -> Result<Selection> {
+        let desktop_windows = labels
+            .iter()
+            .enumerate()
+            .map(|(i, (label, rect))| DesktopWindow {
+                id: i as i64,
+                pos: (rect.0, rect.1),
+                size: (rect.2, rect.3),
+                label: Some(label.clone()),
+                ..Default::default()
+            })
+            .collect();
+        let monitors = self.monitors();
+        self.select(desktop_windows, &monitors)
+    }
+
+    /// Show hints for the windows `backend` knows about and focus the one the user picks.
+    ///
+    /// `Renderer::on_select` is called before focusing the selected window.
+    pub fn focus_window(&self, backend: &dyn Backend) -> Result<Selection> {
+        let mut desktop_windows = backend
+            .windows(self.app_config.containers)
+            .with_context(|| format!("Couldn't get windows from {}", backend.name()))?;
+        let monitors = self.monitors();
+        match backend.outputs() {
+            Ok(outputs) => {
+                geometry::apply_output_geometry(&mut desktop_windows, &outputs, &monitors);
+            }
+            Err(e) => warn!("Couldn't get outputs from {}: {e:#}", backend.name()),
+        }
+        let selection = self.select(desktop_windows, &monitors)?;
+        if let Selection::Window(selected) = &selection {
+            backend
+                .focus(selected)
+                .context("Couldn't focus the window")?;
+        }
+        Ok(selection)
+    }
+
+    /// The monitors of the X screen, or none if X doesn't say.
+    fn monitors(&self) -> Vec<DesktopOutput> {
+        let screen = &self.conn.setup().roots[self.screen_num];
+        utils::get_monitors(&self.conn, screen).unwrap_or_else(|e| {
+            warn!("Couldn't get monitors from X: {e:#}");
+            vec![]
+        })
+    }
+
+    /// Show hints for `desktop_windows` on `monitors` and let `on_select` know about the
+    /// selection.
+    fn select(
+        &self,
+        desktop_windows: Vec<DesktopWindow>,
+        monitors: &[DesktopOutput],
+    ) -> Result<Selection> {
+        let screen = &self.conn.setup().roots[self.screen_num];
+        let selection = select_window(
+            &self.conn,
+            screen,
+            &self.app_config,
+            &geometry::sort_by_pos(desktop_windows),
+            monitors,
+            &events::Events::default(),
+            &stats::Stats::new(),
+            None,
+        )?;
+        if let (Selection::Window(selected), Some(on_select)) = (&selection, &self.on_select) {
+            on_select(selected).context("Couldn't act on the selection")?;
+        }
+        Ok(selection)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fallback() {
+        assert_eq!(
+            fallback(Failure::KeyboardGrab, false, false),
+            Fallback::FocusWindow
+        );
+        assert_eq!(
+            fallback(Failure::KeyboardGrab, false, true),
+            Fallback::FocusWindow
+        );
+        assert_eq!(fallback(Failure::Overlay, false, false), Fallback::List);
+        assert_eq!(fallback(Failure::NoDisplay, false, false), Fallback::List);
+        // Stdin is taken up by the windows already.
+        assert_eq!(fallback(Failure::Overlay, false, true), Fallback::Fail);
+        assert_eq!(fallback(Failure::NoDisplay, false, true), Fallback::Fail);
+        for failure in [Failure::NoDisplay, Failure::Overlay, Failure::KeyboardGrab] {
+            assert_eq!(fallback(failure, true, false), Fallback::Fail);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::cell::RefCell;
+use std::time::{Duration, Instant};
+
+use log::info;
+
+/// How long the different phases of getting hints on the screen took.
+#[derive(Debug)]
+pub struct Stats {
+    start: Instant,
+    phases: RefCell<Vec<(&'static str, Duration)>>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            start: Instant::now(),
+            phases: RefCell::new(vec![]),
+        }
+    }
+
+    /// Add `duration` to the time spent in `phase`.
+    pub fn record(&self, phase: &'static str, duration: Duration) {
+        info!("{phase} took {duration:?}");
+        let mut phases = self.phases.borrow_mut();
+        match phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => phases.push((phase, duration)),
+        }
+    }
+
+    /// Run `f` and add the time it took to `phase`.
+    pub fn measure<T>(&self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let now = Instant::now();
+        let result = f();
+        self.record(phase, now.elapsed());
+        result
+    }
+
+    /// Record the time since startup as `phase`, unless that was already done.
+    pub fn record_since_start(&self, phase: &'static str) {
+        if !self.phases.borrow().iter().any(|(name, _)| *name == phase) {
+            self.record(phase, self.start.elapsed());
+        }
+    }
+
+    /// One line per phase in the order they first happened.
+    pub fn report(&self) -> String {
+        self.phases
+            .borrow()
+            .iter()
+            .map(|(phase, duration)| format!("{phase}: {:.3}ms\n", duration.as_secs_f64() * 1000.0))
+            .collect()
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats::new()
+    }
+}
+
+/// The `p`th percentile (between 0 and 100) of `samples` by the nearest rank method.
+pub fn percentile(samples: &[Duration], p: f64) -> Duration {
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted
+        .get(rank.clamp(1, sorted.len().max(1)) - 1)
+        .copied()
+        .unwrap_or_default()
+}
+
+/// Percentiles of the `samples` of each phase, one line per phase.
+pub fn percentile_report(phases: &[(&str, Vec<Duration>)]) -> String {
+    let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+    phases
+        .iter()
+        .map(|(phase, samples)| {
+            format!(
+                "{phase}: p50 {:.3}ms, p90 {:.3}ms, p99 {:.3}ms, max {:.3}ms\n",
+                ms(percentile(samples, 50.0)),
+                ms(percentile(samples, 90.0)),
+                ms(percentile(samples, 99.0)),
+                ms(percentile(samples, 100.0)),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<_> = (1..=10).rev().map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 50.0), Duration::from_millis(5));
+        assert_eq!(percentile(&samples, 90.0), Duration::from_millis(9));
+        assert_eq!(percentile(&samples, 100.0), Duration::from_millis(10));
+        assert_eq!(percentile(&samples, 0.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+        assert_eq!(
+            percentile_report(&[("map", vec![Duration::from_millis(2)])]),
+            "map: p50 2.000ms, p90 2.000ms, p99 2.000ms, max 2.000ms\n"
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let stats = Stats::new();
+        stats.record("window enumeration", Duration::from_millis(3));
+        stats.record("text extents", Duration::from_micros(250));
+        stats.record("window enumeration", Duration::from_millis(2));
+        assert_eq!(
+            stats.report(),
+            "window enumeration: 5.000ms\ntext extents: 0.250ms\n"
+        );
+
+        assert_eq!(stats.measure("drawing", || 42), 42);
+        stats.record_since_start("startup");
+        let startup = stats.phases.borrow()[3].1;
+        stats.record_since_start("startup");
+        assert_eq!(stats.phases.borrow()[3].1, startup);
+    }
+}
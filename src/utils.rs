@@ -1,49 +1,281 @@
-use std::iter;
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::io::{self, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+use std::process;
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
+use crate::{geometry, hints};
+use crate::{DesktopOutput, DesktopWindow, ExitStatus, Transform};
 use anyhow::{bail, Context, Result};
-use itertools::Itertools;
-use log::debug;
-use regex::Regex;
+use log::{debug, warn};
+use serde::Deserialize;
 use x11rb::connection::Connection;
+use x11rb::protocol::randr::ConnectionExt as _;
+use x11rb::protocol::res::{self, ConnectionExt as _};
+use x11rb::protocol::xkb::{self, ConnectionExt as _};
 use x11rb::protocol::xproto::{
-    grab_keyboard, grab_pointer, ConnectionExt, EventMask, GrabMode, GrabStatus, Screen, Visualtype,
+    grab_keyboard, grab_pointer, AtomEnum, ClientMessageEvent, ConnectionExt, EventMask, GrabMode,
+    GrabStatus, ImageFormat, ImageOrder, InputFocus, KeyPressEvent, MapState, Mapping, PropMode,
+    Screen, Visualtype, Window, BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT,
 };
+use x11rb::protocol::xtest::ConnectionExt as _;
 use x11rb::protocol::Event;
+use x11rb::wrapper::ConnectionExt as _;
 
-use crate::args::AppConfig;
-use crate::{DesktopWindow, RenderWindow};
-
-/// Given a list of `current_hints` and a bunch of `hint_chars`, this finds a unique combination
-/// of characters that doesn't yet exist in `current_hints`. `max_count` is the maximum possible
-/// number of hints we need.
-pub fn get_next_hint(
-    current_hints: Vec<&String>,
-    hint_chars: &str,
-    max_count: usize,
-) -> Result<String> {
-    // Figure out which size we need.
-    let mut size_required = 1;
-    while hint_chars.len().pow(size_required) < max_count {
-        size_required += 1;
-    }
-    let mut ret = hint_chars
-        .chars()
-        .next()
-        .context("No hint_chars found")?
-        .to_string();
-    let it = iter::repeat(hint_chars.chars().rev())
-        .take(size_required as usize)
-        .multi_cartesian_product();
-    for c in it {
-        let folded = c.into_iter().collect();
-        if !current_hints.contains(&&folded) {
-            ret = folded;
+use crate::args::{AppConfig, CopyValue, Mode, PrintId};
+use crate::overlay::RenderWindow;
+
+thread_local! {
+    /// Text extents by text, font family and font size (as bits since `f64` isn't `Hash`).
+    static EXTENTS_CACHE: RefCell<HashMap<(String, String, u64), cairo::TextExtents>> =
+        RefCell::new(HashMap::new());
+
+    /// The context `extents_for_text` measures with, created on first use.
+    static MEASUREMENT_CONTEXT: OnceCell<cairo::Context> = const { OnceCell::new() };
+}
+
+/// A rectangle to hint as read from stdin.
+#[derive(Debug, Deserialize)]
+struct StdinRect {
+    id: serde_json::Value,
+    x: i32,
+    y: i32,
+    w: i32,
+    h: i32,
+    label: Option<String>,
+}
+
+/// Parse a JSON array of rectangles from `input` into `DesktopWindow`s.
+///
+/// The `id` of every rectangle can be any JSON value and is passed through as-is.
+pub fn parse_stdin_rects(input: &str) -> Result<Vec<DesktopWindow>> {
+    let rects: Vec<StdinRect> = serde_json::from_str(input).context("Invalid JSON")?;
+    Ok(rects
+        .into_iter()
+        .enumerate()
+        .map(|(i, rect)| DesktopWindow {
+            id: i as i64,
+            pos: (rect.x, rect.y),
+            size: (rect.w, rect.h),
+            label: rect.label,
+            external_id: Some(match rect.id {
+                serde_json::Value::String(id) => id,
+                id => id.to_string(),
+            }),
+            ..Default::default()
+        })
+        .collect())
+}
+
+/// Fill the `{placeholders}` in `template` with the details of `dw`.
+///
+/// Literal braces are written as `{{` and `}}`.
+pub fn format_selection(template: &str, dw: &DesktopWindow) -> Result<String, String> {
+    let mut output = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("Unclosed placeholder '{{{name}'")),
+                    }
+                }
+                let value = match name.as_str() {
+                    "x_id" => format!("0x{:x}", dw.x_window_id.unwrap_or(0)),
+                    "con_id" => dw.id.to_string(),
+                    "id" => dw.external_id.clone().unwrap_or_default(),
+                    "class" => dw.class.clone().unwrap_or_default(),
+                    "title" => dw.title.clone().unwrap_or_default(),
+                    "workspace" => dw.workspace.clone().unwrap_or_default(),
+                    "output" => dw.output.clone().unwrap_or_default(),
+                    "label" => dw.label.clone().unwrap_or_default(),
+                    "marks" => dw.marks.join(","),
+                    "x" => dw.pos.0.to_string(),
+                    "y" => dw.pos.1.to_string(),
+                    "width" => dw.size.0.to_string(),
+                    "height" => dw.size.1.to_string(),
+                    _ => return Err(format!("Unknown placeholder '{{{name}}}'")),
+                };
+                output.push_str(&value);
+            }
+            '}' => return Err("Unmatched '}', write '}}' for a literal one".to_string()),
+            c => output.push(c),
         }
     }
-    debug!("Returning next hint: {}", ret);
-    Ok(ret)
+    Ok(output)
+}
+
+/// What to print for the selected `DesktopWindow` in the current mode.
+pub fn selection_text(dw: &DesktopWindow, app_config: &AppConfig) -> String {
+    if let Some(format) = &app_config.format {
+        // The template was already checked when parsing the arguments.
+        return format_selection(format, dw).unwrap_or_default();
+    }
+    if app_config.print == Some(PrintId::Geometry) {
+        return geometry_text(dw);
+    }
+    if app_config.stdin {
+        return dw.external_id.clone().unwrap_or_default();
+    }
+    match app_config.mode {
+        Mode::Windows | Mode::Tabs | Mode::Inspect | Mode::Expose => {
+            let x_id = format!("0x{:x}", dw.x_window_id.unwrap_or(0));
+            match app_config.print.unwrap_or(PrintId::XId) {
+                PrintId::XId => x_id,
+                PrintId::ConId => dw.id.to_string(),
+                PrintId::Both => format!("{} {x_id}", dw.id),
+                PrintId::Geometry => unreachable!("geometry is printed the same in every mode"),
+            }
+        }
+        Mode::Workspaces => dw.workspace.clone().unwrap_or_default(),
+        Mode::Outputs => dw.output.clone().unwrap_or_default(),
+        Mode::Marks => dw.label.clone().unwrap_or_default(),
+        Mode::Grid => format!("{} {}", dw.pos.0 + dw.size.0 / 2, dw.pos.1 + dw.size.1 / 2),
+    }
+}
+
+/// The geometry of `dw` as `X,Y WxH`, which is what slurp prints and grim and wf-recorder take.
+pub fn geometry_text(dw: &DesktopWindow) -> String {
+    format!("{},{} {}x{}", dw.pos.0, dw.pos.1, dw.size.0, dw.size.1)
+}
+
+/// A row of the `--list` table: hint, id, class, title and geometry separated by tabs.
+pub fn list_row(hint: &str, dw: &DesktopWindow) -> String {
+    let id = dw.external_id.clone().unwrap_or_else(|| dw.id.to_string());
+    let title = dw
+        .title
+        .as_deref()
+        .unwrap_or_default()
+        .replace(['\t', '\n', '\r'], " ");
+    format!(
+        "{hint}\t{id}\t{}\t{title}\t{}",
+        dw.class.as_deref().unwrap_or_default(),
+        geometry_text(dw)
+    )
+}
+
+/// What to put on the clipboard for `dw`.
+pub fn copy_text(dw: &DesktopWindow, value: CopyValue) -> String {
+    match value {
+        CopyValue::XId => format!("0x{:x}", dw.x_window_id.unwrap_or(0)),
+        CopyValue::Title => dw.title.clone().unwrap_or_default(),
+        CopyValue::Class => dw.class.clone().unwrap_or_default(),
+    }
+}
+
+/// Put `text` on the clipboard.
+///
+/// Someone has to keep owning the clipboard after we exit, so this hands `text` to the first of
+/// wl-copy (on Wayland), xclip and xsel that is installed.
+pub fn copy_to_clipboard(text: &str) -> Result<()> {
+    let mut candidates = vec![];
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        candidates.push(("wl-copy", &[][..]));
+    }
+    candidates.push(("xclip", &["-selection", "clipboard"][..]));
+    candidates.push(("xsel", &["--clipboard", "--input"][..]));
+
+    for (program, args) in candidates {
+        // The clipboard tools stay around in the background, they mustn't hold on to our stdout
+        // or $(wmfocus --copy ...) would never finish.
+        let child = process::Command::new(program)
+            .args(args)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("{program} isn't installed");
+                continue;
+            }
+            Err(e) => return Err(e).with_context(|| format!("Couldn't run {program}")),
+        };
+        child
+            .stdin
+            .take()
+            .context("No stdin")?
+            .write_all(text.as_bytes())
+            .with_context(|| format!("Couldn't write to {program}"))?;
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("{program} failed with {status}");
+        }
+        return Ok(());
+    }
+    bail!("Couldn't copy to the clipboard, please install wl-copy, xclip or xsel")
+}
+
+/// Everything we know about the selected `DesktopWindow` as JSON.
+pub fn selection_json(dw: &DesktopWindow) -> serde_json::Value {
+    let mut json = serde_json::json!({
+        "con_id": dw.id,
+        "x_window_id": dw.x_window_id,
+        "class": dw.class,
+        "title": dw.title,
+        "workspace": dw.workspace,
+        "output": dw.output,
+        "marks": dw.marks,
+        "label": dw.label,
+        "focused": dw.is_focused,
+        "urgent": dw.is_urgent,
+        "sticky": dw.is_sticky,
+        "geometry": {
+            "x": dw.pos.0,
+            "y": dw.pos.1,
+            "width": dw.size.0,
+            "height": dw.size.1,
+        },
+    });
+    if let Some(id) = &dw.external_id {
+        json["id"] = serde_json::Value::String(id.clone());
+    }
+    json
+}
+
+/// A line for `desktop_window` in the fallback list, in the one-choice-per-line format that
+/// `dmenu` and `rofi -dmenu` read.
+pub fn list_entry(hint: &str, dw: &DesktopWindow) -> String {
+    let description = match (&dw.class, &dw.title) {
+        (Some(class), Some(title)) => format!("{class}: {title}"),
+        (Some(text), None) | (None, Some(text)) => text.clone(),
+        (None, None) => dw
+            .label
+            .clone()
+            .or_else(|| dw.workspace.clone())
+            .or_else(|| dw.output.clone())
+            .unwrap_or_else(|| format!("{}x{}+{}+{}", dw.size.0, dw.size.1, dw.pos.0, dw.pos.1)),
+    };
+    // Keep each choice on a single line no matter what the title contains.
+    let description = description.replace(['\n', '\r'], " ");
+    format!("{hint}\t{description}")
+}
+
+/// The hint of a line chosen from the fallback list, which is everything up to the first tab or
+/// space so that both whole lines and just the hint can be given.
+pub fn list_choice(line: &str) -> Option<&str> {
+    line.split(['\t', ' '])
+        .next()
+        .filter(|hint| !hint.trim().is_empty())
+        .map(str::trim)
 }
 
 /// A rust version of XCB's `xcb_visualtype_t` struct. This is used in a FFI-way.
@@ -75,6 +307,15 @@ impl From<Visualtype> for xcb_visualtype_t {
     }
 }
 
+/// Get the atom called `name`, creating it if needed.
+pub fn intern_atom(conn: &impl Connection, name: &str) -> Result<u32> {
+    Ok(conn
+        .intern_atom(false, name.as_bytes())?
+        .reply()
+        .with_context(|| format!("Couldn't create atom {name}"))?
+        .atom)
+}
+
 /// Find a `xcb_visualtype_t` based on its ID number
 pub fn find_xcb_visualtype(conn: &impl Connection, visual_id: u32) -> Option<xcb_visualtype_t> {
     for root in &conn.setup().roots {
@@ -90,41 +331,95 @@ pub fn find_xcb_visualtype(conn: &impl Connection, visual_id: u32) -> Option<xcb
 }
 
 pub fn extents_for_text(text: &str, family: &str, size: f64) -> Result<cairo::TextExtents> {
-    // Create a buffer image that should be large enough.
-    // TODO: Figure out the maximum size from the largest window on the desktop.
-    // For now we'll use made-up maximum values.
-    let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, 1024, 1024)
+    let key = (text.to_string(), family.to_string(), size.to_bits());
+    if let Some(extents) = EXTENTS_CACHE.with(|cache| cache.borrow().get(&key).copied()) {
+        return Ok(extents);
+    }
+
+    let extents = MEASUREMENT_CONTEXT.with(|cell| {
+        let cr = match cell.get() {
+            Some(cr) => cr,
+            None => {
+                let cr = new_measurement_context()?;
+                cell.get_or_init(|| cr)
+            }
+        };
+        cr.select_font_face(family, cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+        cr.set_font_size(size);
+        cr.text_extents(text).context("Couldn't create TextExtents")
+    })?;
+    EXTENTS_CACHE.with(|cache| cache.borrow_mut().insert(key, extents));
+    Ok(extents)
+}
+
+/// A context that is only used for measuring text and never drawn onto, so its surface can be tiny.
+fn new_measurement_context() -> Result<cairo::Context> {
+    let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, 1, 1)
         .context("Couldn't create ImageSurface")?;
-    let cr = cairo::Context::new(&surface).context("Couldn't create Cairo Surface")?;
-    cr.select_font_face(family, cairo::FontSlant::Normal, cairo::FontWeight::Normal);
-    cr.set_font_size(size);
-    cr.text_extents(text).context("Couldn't create TextExtents")
+    cairo::Context::new(&surface).context("Couldn't create Cairo Context")
+}
+
+/// The hint character that takes up the most space in the given font.
+pub fn widest_char(hint_chars: &str, family: &str, size: f64) -> Result<char> {
+    let mut widest = (None, f64::MIN);
+    for c in hint_chars.chars() {
+        let width = extents_for_text(&c.to_string(), family, size)?.x_advance();
+        if width > widest.1 {
+            widest = (Some(c), width);
+        }
+    }
+    widest.0.context("No hint characters")
+}
+
+/// The text to measure to size the hint box for `hint` on `dw`.
+///
+/// Hints without a label are measured as if they were made of the `widest` hint character only.
+/// That way all hints of the same length share one measurement (and one size) and none of them
+/// ends up too small for its text.
+pub fn measured_text(hint: &str, dw: &DesktopWindow, widest: char) -> String {
+    match &dw.label {
+        Some(_) => hint_text(hint, dw),
+        None => widest.to_string().repeat(hint.chars().count()),
+    }
+}
+
+/// The full text to show for `hint` on `dw`, including its label if it has one.
+pub fn hint_text(hint: &str, dw: &DesktopWindow) -> String {
+    match &dw.label {
+        Some(label) => format!("{hint} {label}"),
+        None => hint.to_string(),
+    }
+}
+
+/// Background color of the first `--class-color` rule matching the class of `dw`, if any.
+fn class_color(dw: &DesktopWindow, app_config: &AppConfig) -> Option<(f64, f64, f64, f64)> {
+    let class = dw.class.as_deref()?;
+    app_config
+        .class_color
+        .iter()
+        .find(|rule| rule.regex.is_match(class))
+        .map(|rule| rule.color)
 }
 
 /// Draw a `text` onto `rw`. In case any `current_hints` are already typed, it will draw those in a
 /// different color to show that they were in fact typed.
+///
+/// Everything is drawn offscreen first and then copied onto the window at once, so that a
+/// compositor never gets to show the background without the text.
 pub fn draw_hint_text(
     rw: &RenderWindow,
     app_config: &AppConfig,
     text: &str,
     current_hints: &str,
 ) -> Result<()> {
+    rw.cairo_context.push_group();
+
     // Paint background.
     rw.cairo_context.set_operator(cairo::Operator::Source);
 
-    if rw.desktop_window.is_focused {
-        rw.cairo_context.set_source_rgb(
-            app_config.bg_color_current.0,
-            app_config.bg_color_current.1,
-            app_config.bg_color_current.2,
-        );
-    } else {
-        rw.cairo_context.set_source_rgb(
-            app_config.bg_color.0,
-            app_config.bg_color.1,
-            app_config.bg_color.2,
-        );
-    }
+    let bg_color = background_color(rw, app_config);
+    rw.cairo_context
+        .set_source_rgb(bg_color.0, bg_color.1, bg_color.2);
     rw.cairo_context.paint().context("Error trying to draw")?;
     rw.cairo_context.set_operator(cairo::Operator::Over);
 
@@ -133,9 +428,10 @@ pub fn draw_hint_text(
         cairo::FontSlant::Normal,
         cairo::FontWeight::Normal,
     );
-    rw.cairo_context.set_font_size(app_config.font.font_size);
+    rw.cairo_context.set_font_size(rw.font_size);
     rw.cairo_context.move_to(rw.draw_pos.0, rw.draw_pos.1);
-    if text.starts_with(current_hints) {
+    let (typed, untyped) = hints::split_typed(text, current_hints);
+    if !typed.is_empty() {
         // Paint already selected chars.
         if rw.desktop_window.is_focused {
             rw.cairo_context.set_source_rgba(
@@ -152,11 +448,9 @@ pub fn draw_hint_text(
                 app_config.text_color_alt.3,
             );
         }
-        for c in current_hints.chars() {
-            rw.cairo_context
-                .show_text(&c.to_string())
-                .context("Couldn't display text")?;
-        }
+        rw.cairo_context
+            .show_text(typed)
+            .context("Couldn't display text")?;
     }
 
     // Paint unselected chars.
@@ -175,28 +469,142 @@ pub fn draw_hint_text(
             app_config.text_color.3,
         );
     }
-    let re = Regex::new(&format!("^{current_hints}")).unwrap();
-    for c in re.replace(text, "").chars() {
+    rw.cairo_context
+        .show_text(untyped)
+        .context("Couldn't show text")?;
+    if let Some(label) = &rw.desktop_window.label {
         rw.cairo_context
-            .show_text(&c.to_string())
-            .context("Couldn't show text")?;
+            .show_text(&format!(" {label}"))
+            .context("Couldn't show label")?;
+    }
+
+    paint_group(
+        &rw.cairo_context,
+        rw.backdrop.as_ref(),
+        app_config.bg_color.3,
+    )
+}
+
+/// The color the hint `rw` is drawn onto.
+fn background_color(rw: &RenderWindow, app_config: &AppConfig) -> (f64, f64, f64, f64) {
+    if rw.desktop_window.is_focused {
+        app_config.bg_color_current
+    } else {
+        class_color(rw.desktop_window, app_config).unwrap_or(app_config.bg_color)
     }
-    rw.cairo_context.target().flush();
+}
+
+/// Draw the bar along the bottom of the hint `rw` showing that a `fraction` of `--timeout` is
+/// left, leaving the rest of the hint alone.
+pub fn draw_countdown(rw: &RenderWindow, app_config: &AppConfig, fraction: f64) -> Result<()> {
+    let (_, y, width, height) = geometry::countdown_bar((rw.rect.2, rw.rect.3), fraction);
+    let cr = &rw.cairo_context;
+    cr.save()?;
+    cr.rectangle(0.0, y.into(), rw.rect.2.into(), height.into());
+    cr.clip();
 
+    cr.push_group();
+    cr.set_operator(cairo::Operator::Source);
+    let bg_color = background_color(rw, app_config);
+    cr.set_source_rgb(bg_color.0, bg_color.1, bg_color.2);
+    cr.paint().context("Error trying to draw")?;
+    let text_color = if rw.desktop_window.is_focused {
+        app_config.text_color_current
+    } else {
+        app_config.text_color
+    };
+    cr.set_source_rgb(text_color.0, text_color.1, text_color.2);
+    cr.rectangle(0.0, y.into(), width.into(), height.into());
+    cr.fill().context("Error trying to draw")?;
+    paint_group(cr, rw.backdrop.as_ref(), app_config.bg_color.3)?;
+
+    cr.restore()?;
     Ok(())
 }
 
-/// Try to grab the keyboard until `timeout` is reached.
+/// Fill the hint `rw` with the text color, for flashing it.
+pub fn draw_flash(rw: &RenderWindow, app_config: &AppConfig) -> Result<()> {
+    rw.cairo_context.push_group();
+    rw.cairo_context.set_operator(cairo::Operator::Source);
+    rw.cairo_context.set_source_rgb(
+        app_config.text_color.0,
+        app_config.text_color.1,
+        app_config.text_color.2,
+    );
+    rw.cairo_context.paint().context("Error trying to draw")?;
+    paint_group(
+        &rw.cairo_context,
+        rw.backdrop.as_ref(),
+        app_config.bg_color.3,
+    )
+}
+
+/// Paint what was drawn onto `cr` since `push_group` onto its target.
+///
+/// With a `backdrop`, the drawing is blended with it the way a compositor would blend a window
+/// with an opacity of `alpha` with what's below it.
+pub fn paint_group(
+    cr: &cairo::Context,
+    backdrop: Option<&cairo::ImageSurface>,
+    alpha: f64,
+) -> Result<()> {
+    let group = cr
+        .pop_group()
+        .context("Couldn't finish drawing offscreen")?;
+    cr.set_operator(cairo::Operator::Source);
+    match backdrop {
+        Some(backdrop) => {
+            cr.set_source_surface(backdrop, 0.0, 0.0)?;
+            cr.paint().context("Error trying to draw")?;
+            cr.set_operator(cairo::Operator::Over);
+            cr.set_source(&group)?;
+            cr.paint_with_alpha(alpha).context("Error trying to draw")?;
+        }
+        None => {
+            cr.set_source(&group)?;
+            cr.paint().context("Error trying to draw")?;
+        }
+    }
+    cr.set_operator(cairo::Operator::Over);
+    cr.target().flush();
+
+    Ok(())
+}
+
+/// Keyboard or mouse input couldn't be grabbed, usually because another client holds it.
+#[derive(Debug)]
+pub struct GrabError(String);
+
+impl fmt::Display for GrabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for GrabError {}
+
+/// How to exit after failing with `error`.
+pub fn exit_status(error: &anyhow::Error) -> ExitStatus {
+    if error.downcast_ref::<GrabError>().is_some() {
+        ExitStatus::GrabFailed
+    } else {
+        ExitStatus::Error
+    }
+}
+
+/// Try to grab the keyboard until `timeout` is reached, trying again every `retry_interval`.
 ///
 /// Generally with X, I found that you can't grab global keyboard input without it failing
 /// sometimes due to other clients grabbing it occasionally. Hence, we'll have to keep retrying
 /// until we eventually succeed.
-pub fn snatch_keyboard(conn: &impl Connection, screen: &Screen, timeout: Duration) -> Result<()> {
+pub fn snatch_keyboard(
+    conn: &impl Connection,
+    screen: &Screen,
+    timeout: Duration,
+    retry_interval: Duration,
+) -> Result<()> {
     let now = Instant::now();
     loop {
-        if now.elapsed() > timeout {
-            bail!("Couldn't grab keyboard input within {:?}", now.elapsed());
-        }
         let grab_keyboard_cookie = grab_keyboard(
             conn,
             true,
@@ -211,21 +619,32 @@ pub fn snatch_keyboard(conn: &impl Connection, screen: &Screen, timeout: Duratio
         if grab_keyboard_reply.status == GrabStatus::SUCCESS {
             return Ok(());
         }
-        sleep(Duration::from_millis(1));
+        if now.elapsed() > timeout {
+            return Err(grab_error(
+                conn,
+                screen,
+                "keyboard",
+                now.elapsed(),
+                grab_keyboard_reply.status,
+            ));
+        }
+        sleep(retry_interval);
     }
 }
 
-/// Try to grab the mouse until `timeout` is reached.
+/// Try to grab the mouse until `timeout` is reached, trying again every `retry_interval`.
 ///
 /// Generally with X, I found that you can't grab global mouse input without it failing sometimes
 /// due to other clients grabbing it occasionally. Hence, we'll have to keep retrying until we
 /// eventually succeed.
-pub fn snatch_mouse(conn: &impl Connection, screen: &Screen, timeout: Duration) -> Result<()> {
+pub fn snatch_mouse(
+    conn: &impl Connection,
+    screen: &Screen,
+    timeout: Duration,
+    retry_interval: Duration,
+) -> Result<()> {
     let now = Instant::now();
     loop {
-        if now.elapsed() > timeout {
-            bail!("Couldn't grab keyboard input within {:?}", now.elapsed());
-        }
         let grab_pointer_cookie = grab_pointer(
             conn,
             true,
@@ -243,115 +662,737 @@ pub fn snatch_mouse(conn: &impl Connection, screen: &Screen, timeout: Duration)
         if grab_pointer_reply.status == GrabStatus::SUCCESS {
             return Ok(());
         }
-        sleep(Duration::from_millis(1));
+        if now.elapsed() > timeout {
+            return Err(grab_error(
+                conn,
+                screen,
+                "mouse",
+                now.elapsed(),
+                grab_pointer_reply.status,
+            ));
+        }
+        sleep(retry_interval);
+    }
+}
+
+/// The error for not being able to grab `device` within `elapsed`, with the last grab attempt
+/// ending in `status`.
+fn grab_error(
+    conn: &impl Connection,
+    screen: &Screen,
+    device: &str,
+    elapsed: Duration,
+    status: GrabStatus,
+) -> anyhow::Error {
+    let mut message = format!("Couldn't grab {device} input within {elapsed:?}");
+    if status == GrabStatus::ALREADY_GRABBED {
+        match guess_grabbing_client(conn, screen) {
+            Ok(Some(client)) => message.push_str(&format!(", it's probably held by {client}")),
+            Ok(None) => {}
+            Err(e) => debug!("Couldn't guess who holds the grab: {e:#}"),
+        }
     }
+    GrabError(message).into()
 }
 
-/// Sort list of `DesktopWindow`s by position.
+/// Guess which client holds a grab, since X won't tell.
 ///
-/// This sorts by column first and row second.
-pub fn sort_by_pos(mut dws: Vec<DesktopWindow>) -> Vec<DesktopWindow> {
-    dws.sort_by_key(|w| w.pos.0);
-    dws.sort_by_key(|w| w.pos.1);
+/// Menus, launchers and screen lockers usually show an override-redirect window while they grab,
+/// so the topmost of those is the prime suspect. Otherwise, it's likely whoever has the focus.
+fn guess_grabbing_client(conn: &impl Connection, screen: &Screen) -> Result<Option<String>> {
+    let children = conn.query_tree(screen.root)?.reply()?.children;
+    let cookies = children
+        .iter()
+        .map(|window| conn.get_window_attributes(*window))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut suspect = None;
+    // Children are in stacking order, from the bottom to the top.
+    for (window, cookie) in children.iter().zip(cookies) {
+        let Ok(attributes) = cookie.reply() else {
+            continue;
+        };
+        if attributes.override_redirect
+            && attributes.map_state == MapState::VIEWABLE
+            && !is_own_window(conn, *window)
+        {
+            suspect = Some(*window);
+        }
+    }
+    let suspect = match suspect {
+        Some(window) => window,
+        None => {
+            let focus = conn.get_input_focus()?.reply()?.focus;
+            if focus == x11rb::NONE
+                || focus == u32::from(InputFocus::POINTER_ROOT)
+                || focus == screen.root
+            {
+                return Ok(None);
+            }
+            focus
+        }
+    };
+
+    let pid = match client_pid(conn, suspect) {
+        Ok(Some(pid)) => Some(pid),
+        _ => get_instance_and_pid(conn, suspect)?.1,
+    };
+    Ok(Some(describe_client(suspect, pid)))
+}
+
+/// Whether `window` was created by us.
+fn is_own_window(conn: &impl Connection, window: u32) -> bool {
+    let setup = conn.setup();
+    window & !setup.resource_id_mask == setup.resource_id_base
+}
+
+/// The pid of the client that created `window`, as reported by the X-Resource extension.
+fn client_pid(conn: &impl Connection, window: u32) -> Result<Option<u32>> {
+    let spec = res::ClientIdSpec {
+        client: window,
+        mask: res::ClientIdMask::LOCAL_CLIENT_PID,
+    };
+    let reply = conn
+        .res_query_client_ids(&[spec])?
+        .reply()
+        .context("Couldn't query client ids")?;
+    Ok(reply
+        .ids
+        .iter()
+        .find(|id| id.spec.mask == res::ClientIdMask::LOCAL_CLIENT_PID)
+        .and_then(|id| id.value.first().copied()))
+}
+
+/// Describe the client with `pid` by its name if it runs on this machine, and by `window`
+/// otherwise.
+fn describe_client(window: u32, pid: Option<u32>) -> String {
+    let Some(pid) = pid else {
+        return format!("the client of window 0x{window:x}");
+    };
+    match std::fs::read_to_string(format!("/proc/{pid}/comm")) {
+        Ok(name) => format!("{} (pid {pid})", name.trim_end()),
+        Err(_) => format!("pid {pid}"),
+    }
+}
+
+/// Give back keyboard and mouse to everyone else.
+pub fn release_input(conn: &impl Connection) -> Result<()> {
+    conn.ungrab_keyboard(x11rb::CURRENT_TIME)?;
+    conn.ungrab_pointer(x11rb::CURRENT_TIME)?;
+    conn.flush()?;
+    Ok(())
+}
+
+/// What a persistent wmfocus is asked to do through signals, see `Signals`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    /// SIGUSR1 asks to show the hints again.
+    Show,
+    /// SIGHUP asks to reload the config file.
+    Reload,
+}
+
+/// SIGUSR1 and SIGHUP, the signals `Signals` reads.
+fn signal_set() -> libc::sigset_t {
+    // SAFETY: sigemptyset initializes the set before sigaddset uses it.
+    unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGUSR1);
+        libc::sigaddset(&mut set, libc::SIGHUP);
+        set
+    }
+}
+
+/// Block (`SIG_BLOCK`) or unblock (`SIG_UNBLOCK`) the signals in `signal_set` for the calling
+/// thread.
+fn set_signal_mask(how: libc::c_int) -> io::Result<()> {
+    let set = signal_set();
+    // SAFETY: `set` is a valid signal set and we don't care about the old mask.
+    let result = unsafe { libc::pthread_sigmask(how, &set, std::ptr::null_mut()) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+    Ok(())
+}
+
+/// SIGUSR1 and SIGHUP, which are read from a file descriptor instead of terminating us.
+#[derive(Debug)]
+pub struct Signals(OwnedFd);
+
+impl Signals {
+    /// Block SIGUSR1 and SIGHUP and start reading them.
+    ///
+    /// Blocking only affects the calling thread and the threads it starts afterwards, see
+    /// `Signals::block`.
+    pub fn new() -> Result<Signals> {
+        Signals::block()?;
+        let set = signal_set();
+        // SAFETY: `set` is a valid signal set, -1 asks for a new file descriptor.
+        let fd = unsafe { libc::signalfd(-1, &set, libc::SFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).context("Couldn't read signals");
+        }
+        // SAFETY: signalfd just gave us this file descriptor.
+        Ok(Signals(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Block SIGUSR1 and SIGHUP for the calling thread and the threads it starts afterwards.
+    ///
+    /// A signal that isn't blocked by every thread can end up with a thread that doesn't read
+    /// it, which terminates us. So this has to happen before starting any threads when it's not
+    /// yet known whether signals will be read at all.
+    pub fn block() -> Result<()> {
+        set_signal_mask(libc::SIG_BLOCK).context("Couldn't block signals")
+    }
+
+    /// Undo `Signals::block` for the calling thread, which is enough for the signals to terminate
+    /// us again.
+    pub fn unblock() -> Result<()> {
+        set_signal_mask(libc::SIG_UNBLOCK).context("Couldn't unblock signals")
+    }
+
+    /// Wait for the next signal.
+    pub fn next(&self) -> Result<Signal> {
+        // SAFETY: signalfd_siginfo is plain data.
+        let mut info: libc::signalfd_siginfo = unsafe { std::mem::zeroed() };
+        let size = std::mem::size_of::<libc::signalfd_siginfo>();
+        loop {
+            // SAFETY: `info` is valid for writing `size` bytes.
+            let read = unsafe {
+                libc::read(
+                    self.0.as_raw_fd(),
+                    &mut info as *mut _ as *mut libc::c_void,
+                    size,
+                )
+            };
+            if read == size as isize {
+                break;
+            }
+            let e = io::Error::last_os_error();
+            if read >= 0 || e.kind() != io::ErrorKind::Interrupted {
+                return Err(e).context("Couldn't wait for a signal");
+            }
+        }
+        Ok(if info.ssi_signo == libc::SIGHUP as u32 {
+            Signal::Reload
+        } else {
+            Signal::Show
+        })
+    }
+}
+
+impl AsFd for Signals {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+/// Where systemd starts passing file descriptors, see `sd_listen_fds(3)`.
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Take the listening socket systemd passed us for socket activation, if there is one.
+///
+/// This has to be called before starting any threads, since it changes the environment.
+pub fn activation_socket() -> Result<Option<UnixListener>> {
+    let count = listen_fds(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        process::id(),
+    );
+    // Whatever we start shouldn't think it was passed sockets as well.
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    if count == 0 {
+        return Ok(None);
+    }
+    if count > 1 {
+        warn!("Got {count} sockets from systemd, only using the first one");
+    }
+    // SAFETY: systemd passed us this file descriptor and nothing else owns it.
+    let fd = unsafe { OwnedFd::from_raw_fd(SD_LISTEN_FDS_START) };
+    rustix::io::fcntl_setfd(&fd, rustix::io::FdFlags::CLOEXEC)?;
+    Ok(Some(UnixListener::from(fd)))
+}
+
+/// How many file descriptors systemd passed to the process `pid` according to `$LISTEN_PID` and
+/// `$LISTEN_FDS`.
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> u32 {
+    // The variables might be left over from whoever started us.
+    if listen_pid.and_then(|listen_pid| listen_pid.parse().ok()) != Some(pid) {
+        return 0;
+    }
+    listen_fds
+        .and_then(|listen_fds| listen_fds.parse().ok())
+        .unwrap_or_default()
+}
+
+/// Move the mouse to `pos` and optionally click there with the left button.
+///
+/// Clicking is done through the XTEST extension so it looks like a real click to the window below.
+pub fn warp_and_click(
+    conn: &impl Connection,
+    screen: &Screen,
+    pos: (i32, i32),
+    click: bool,
+) -> Result<()> {
+    conn.warp_pointer(
+        x11rb::NONE,
+        screen.root,
+        0,
+        0,
+        0,
+        0,
+        pos.0 as i16,
+        pos.1 as i16,
+    )?;
+    if click {
+        for event_type in [BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT] {
+            conn.xtest_fake_input(event_type, 1, x11rb::CURRENT_TIME, screen.root, 0, 0, 0)?
+                .check()
+                .context("Couldn't fake a click with XTEST")?;
+        }
+    }
+    conn.flush()?;
+    Ok(())
+}
+
+/// Whether `window` (or one of its children) has the keyboard focus.
+pub fn has_focus(conn: &impl Connection, window: u32) -> Result<bool> {
+    let mut focus = conn.get_input_focus()?.reply()?.focus;
+    // Some clients hand the focus on to a window of their own inside of their top level window.
+    while focus != x11rb::NONE && focus != u32::from(InputFocus::POINTER_ROOT) {
+        if focus == window {
+            return Ok(true);
+        }
+        focus = conn.query_tree(focus)?.reply()?.parent;
+    }
+    Ok(false)
+}
+
+/// Wait for up to `timeout` for `window` to get the keyboard focus.
+pub fn wait_for_focus(conn: &impl Connection, window: u32, timeout: Duration) -> Result<bool> {
+    let start = Instant::now();
+    loop {
+        if has_focus(conn, window)? {
+            return Ok(true);
+        }
+        if start.elapsed() >= timeout {
+            return Ok(false);
+        }
+        sleep(Duration::from_millis(10));
+    }
+}
+
+/// Ask the window manager to activate `window` with a `_NET_ACTIVE_WINDOW` client message.
+pub fn request_activation(conn: &impl Connection, screen: &Screen, window: u32) -> Result<()> {
+    let atom = intern_atom(conn, "_NET_ACTIVE_WINDOW")?;
+    // A source indication of 2 says that this comes from a pager, which window managers follow
+    // more willingly than requests of applications.
+    let event = ClientMessageEvent::new(32, window, atom, [2, x11rb::CURRENT_TIME, 0, 0, 0]);
+    conn.send_event(
+        false,
+        screen.root,
+        EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+        event,
+    )?
+    .check()
+    .context("Couldn't send _NET_ACTIVE_WINDOW")?;
+    Ok(())
+}
+
+/// Remove all `DesktopWindow`s that shouldn't be hinted according to `app_config`.
+pub fn filter_windows(mut dws: Vec<DesktopWindow>, app_config: &AppConfig) -> Vec<DesktopWindow> {
+    dws.retain(|w| {
+        !w.marks
+            .iter()
+            .any(|m| app_config.exclude_marked.contains(m))
+    });
+    if app_config.urgent_only {
+        dws.retain(|w| w.is_urgent);
+    }
+    if app_config.exclude_sticky {
+        dws.retain(|w| !w.is_sticky);
+    }
+    if app_config.sticky_only {
+        dws.retain(|w| w.is_sticky);
+    }
+    if app_config.same_app {
+        match dws.iter().find(|w| w.is_focused).map(|w| w.class.clone()) {
+            Some(Some(class)) => dws.retain(|w| w.class.as_ref() == Some(&class)),
+            _ => warn!("Couldn't figure out the class of the focused window, showing all windows"),
+        }
+    }
+    if !app_config.windows.is_empty() {
+        dws.retain(|w| {
+            w.x_window_id
+                .is_some_and(|id| app_config.windows.contains(&(id as u32)))
+        });
+    }
     dws
 }
 
-/// Returns true if `r1` and `r2` overlap.
-fn intersects(r1: (i32, i32, i32, i32), r2: (i32, i32, i32, i32)) -> bool {
-    let left_corner_inside = r1.0 < r2.0 + r2.2;
-    let right_corner_inside = r1.0 + r1.2 > r2.0;
-    let top_corner_inside = r1.1 < r2.1 + r2.3;
-    let bottom_corner_inside = r1.1 + r1.3 > r2.1;
-    left_corner_inside && right_corner_inside && top_corner_inside && bottom_corner_inside
-}
-
-/// Finds overlaps and returns a list of those rects in the format (x, y, w, h).
-pub fn find_overlaps(
-    rws: Vec<&RenderWindow>,
-    rect: (i32, i32, i32, i32),
-) -> Vec<(i32, i32, i32, i32)> {
-    let mut overlaps = vec![];
-    for rw in rws {
-        if intersects(rw.rect, rect) {
-            overlaps.push(rw.rect);
+/// Replace the geometry of all `dws` with what the X server reports for their X windows.
+pub fn update_x_geometry(
+    conn: &impl Connection,
+    screen: &Screen,
+    dws: &mut [DesktopWindow],
+) -> Result<()> {
+    for dw in dws {
+        let Some(x_window_id) = dw.x_window_id else {
+            continue;
+        };
+        let geometry = conn.get_geometry(x_window_id as u32)?.reply()?;
+        let translated = conn
+            .translate_coordinates(x_window_id as u32, screen.root, 0, 0)?
+            .reply()?;
+        dw.pos = (translated.dst_x.into(), translated.dst_y.into());
+        dw.size = (geometry.width.into(), geometry.height.into());
+        debug!("Got geometry from X for {:?}", dw);
+    }
+    Ok(())
+}
+
+/// Shrink those of `dws` with client side decorations to their visible frame according to
+/// `_GTK_FRAME_EXTENTS`, which leaves out their shadows.
+pub fn apply_frame_extents(conn: &impl Connection, dws: &mut [DesktopWindow]) -> Result<()> {
+    let atom = intern_atom(conn, "_GTK_FRAME_EXTENTS")?;
+    let cookies = dws
+        .iter()
+        .map(|dw| {
+            dw.x_window_id
+                .map(|id| conn.get_property(false, id as u32, atom, AtomEnum::CARDINAL, 0, 4))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for (dw, cookie) in dws.iter_mut().zip(cookies) {
+        // Windows might go away in the meantime.
+        let Some(Ok(reply)) = cookie.map(|cookie| cookie.reply()) else {
+            continue;
+        };
+        let Some(extents) = reply.value32().map(|values| values.collect::<Vec<_>>()) else {
+            continue;
+        };
+        if let Ok(extents) = <[u32; 4]>::try_from(extents) {
+            debug!("Leaving out frame extents {extents:?} of {:?}", dw.title);
+            geometry::apply_frame_extents(dw, extents);
         }
     }
-    overlaps
+    Ok(())
 }
 
-/// Remove last pressed key from pressed keys
-pub fn remove_last_key(pressed_keys: &mut String, kstr: &str) {
-    if pressed_keys.contains(kstr) {
-        pressed_keys.replace_range(pressed_keys.len() - kstr.len().., "");
+/// Ask the X server for all monitors.
+pub fn get_monitors(conn: &impl Connection, screen: &Screen) -> Result<Vec<DesktopOutput>> {
+    let reply = conn
+        .randr_get_monitors(screen.root, true)?
+        .reply()
+        .context("Couldn't get monitors")?;
+    let mut monitors = vec![];
+    for monitor in reply.monitors {
+        let name = conn.get_atom_name(monitor.name)?.reply()?.name;
+        monitors.push(DesktopOutput {
+            name: String::from_utf8_lossy(&name).to_string(),
+            rect: (
+                monitor.x.into(),
+                monitor.y.into(),
+                monitor.width.into(),
+                monitor.height.into(),
+            ),
+            transform: Transform::Normal,
+            scale: 1.0,
+        });
     }
+    Ok(monitors)
 }
 
-pub fn get_pressed_symbol(conn: &impl Connection, event: Event) -> xkeysym::Keysym {
-    let mapping = conn
-        .get_keyboard_mapping(
-            conn.setup().min_keycode,
-            conn.setup().max_keycode - conn.setup().min_keycode + 1,
-        )
-        .unwrap()
+/// Ask the X server for the stacking order of all client windows (bottom to top).
+pub fn get_stacking_order(conn: &impl Connection, screen: &Screen) -> Result<Vec<u32>> {
+    let atom = conn
+        .intern_atom(false, b"_NET_CLIENT_LIST_STACKING")?
         .reply()
-        .unwrap();
+        .context("Couldn't create atom _NET_CLIENT_LIST_STACKING")?
+        .atom;
+    let reply = conn
+        .get_property(false, screen.root, atom, AtomEnum::WINDOW, 0, u32::MAX)?
+        .reply()
+        .context("Couldn't get _NET_CLIENT_LIST_STACKING")?;
+    Ok(reply.value32().map(Iterator::collect).unwrap_or_default())
+}
 
-    match event {
-        Event::KeyPress(event) | Event::KeyRelease(event) => xkeysym::keysym(
-            event.detail.into(),
-            0,
-            conn.setup().min_keycode.into(),
-            mapping.keysyms_per_keycode,
-            mapping.keysyms.as_slice(),
-        )
-        .unwrap(),
-        _ => unreachable!(),
+/// Take a screenshot of the whole root window of `screen`.
+///
+/// Only the common 24 bit depth with 32 bits per pixel is supported since that's what Cairo's
+/// `Rgb24` format is.
+pub fn take_screenshot(conn: &impl Connection, screen: &Screen) -> Result<cairo::ImageSurface> {
+    let (width, height) = (screen.width_in_pixels, screen.height_in_pixels);
+    let image = conn
+        .get_image(ImageFormat::Z_PIXMAP, screen.root, 0, 0, width, height, !0)?
+        .reply()
+        .context("Couldn't get image of the root window")?;
+    let bits_per_pixel = conn
+        .setup()
+        .pixmap_formats
+        .iter()
+        .find(|format| format.depth == image.depth)
+        .map(|format| format.bits_per_pixel);
+    if image.depth != 24 || bits_per_pixel != Some(32) {
+        bail!("Unsupported depth {} of the root window", image.depth);
+    }
+    // Cairo wants its pixels in our byte order.
+    let native_order = if cfg!(target_endian = "little") {
+        ImageOrder::LSB_FIRST
+    } else {
+        ImageOrder::MSB_FIRST
+    };
+    if conn.setup().image_byte_order != native_order {
+        bail!("The X server uses a different byte order");
+    }
+    let stride = cairo::Format::Rgb24
+        .stride_for_width(width.into())
+        .context("Couldn't get stride")?;
+    if image.data.len() != stride as usize * usize::from(height) {
+        bail!("Unexpected size of the root window image");
     }
+    cairo::ImageSurface::create_for_data(
+        image.data,
+        cairo::Format::Rgb24,
+        width.into(),
+        height.into(),
+        stride,
+    )
+    .context("Couldn't create ImageSurface")
 }
 
-/// Struct helps to write sequence and check if it is found in list of exit sequences
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Sequence {
-    sequence: Vec<String>,
-}
+/// Get the instance (the first part of `WM_CLASS`) and the pid (`_NET_WM_PID`) of the X window
+/// `window`, as far as it has set them.
+pub fn get_instance_and_pid(
+    conn: &impl Connection,
+    window: u32,
+) -> Result<(Option<String>, Option<u32>)> {
+    let wm_class = conn
+        .get_property(false, window, AtomEnum::WM_CLASS, AtomEnum::STRING, 0, 1024)?
+        .reply()
+        .context("Couldn't get WM_CLASS")?;
+    let instance = wm_class
+        .value
+        .split(|&b| b == 0)
+        .next()
+        .filter(|instance| !instance.is_empty())
+        .map(|instance| String::from_utf8_lossy(instance).into_owned());
 
-impl Sequence {
-    pub fn new(string: Option<&str>) -> Sequence {
-        match string {
-            Some(string) => {
-                let mut vec: Vec<String> = Sequence::explode(string, "+");
+    let pid_atom = conn
+        .intern_atom(false, b"_NET_WM_PID")?
+        .reply()
+        .context("Couldn't create atom _NET_WM_PID")?
+        .atom;
+    let pid = conn
+        .get_property(false, window, pid_atom, AtomEnum::CARDINAL, 0, 1)?
+        .reply()
+        .context("Couldn't get _NET_WM_PID")?
+        .value32()
+        .and_then(|mut values| values.next());
+    Ok((instance, pid))
+}
 
-                Sequence::sort(&mut vec);
+/// The `_NET_WM_WINDOW_OPACITY` of a window with opacity `old` (fully opaque without one) dimmed
+/// by `factor`.
+pub fn dimmed_opacity(old: Option<u32>, factor: f64) -> u32 {
+    (f64::from(old.unwrap_or(u32::MAX)) * factor).round() as u32
+}
 
-                Sequence { sequence: vec }
-            }
-            None => Sequence {
-                sequence: Vec::new(),
-            },
+/// Dim all mapped top level windows by `factor` with `_NET_WM_WINDOW_OPACITY`, which compositors
+/// like picom go by.
+///
+/// Returns the dimmed windows along with the opacity they had before, for `undim_windows`.
+pub fn dim_windows(
+    conn: &impl Connection,
+    screen: &Screen,
+    factor: f64,
+) -> Result<Vec<(Window, Option<u32>)>> {
+    let opacity_atom = intern_atom(conn, "_NET_WM_WINDOW_OPACITY")?;
+    let children = conn
+        .query_tree(screen.root)?
+        .reply()
+        .context("Couldn't list the windows")?
+        .children;
+    let cookies = children
+        .iter()
+        .map(|&window| {
+            Ok((
+                window,
+                conn.get_window_attributes(window)?,
+                conn.get_property(false, window, opacity_atom, AtomEnum::CARDINAL, 0, 1)?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut dimmed = vec![];
+    for (window, attributes, opacity) in cookies {
+        // Windows might go away in the meantime.
+        let (Ok(attributes), Ok(opacity)) = (attributes.reply(), opacity.reply()) else {
+            continue;
+        };
+        if attributes.map_state != MapState::VIEWABLE {
+            continue;
         }
+        let old = opacity.value32().and_then(|mut values| values.next());
+        conn.change_property32(
+            PropMode::REPLACE,
+            window,
+            opacity_atom,
+            AtomEnum::CARDINAL,
+            &[dimmed_opacity(old, factor)],
+        )?;
+        dimmed.push((window, old));
+    }
+    conn.flush()?;
+    Ok(dimmed)
+}
+
+/// Give the windows dimmed by `dim_windows` their opacity back.
+pub fn undim_windows(conn: &impl Connection, dimmed: &[(Window, Option<u32>)]) -> Result<()> {
+    let opacity_atom = intern_atom(conn, "_NET_WM_WINDOW_OPACITY")?;
+    for &(window, old) in dimmed {
+        // Errors about windows that are gone by now are ignored.
+        match old {
+            Some(old) => conn.change_property32(
+                PropMode::REPLACE,
+                window,
+                opacity_atom,
+                AtomEnum::CARDINAL,
+                &[old],
+            )?,
+            None => conn.delete_property(window, opacity_atom)?,
+        };
+    }
+    conn.flush()?;
+    Ok(())
+}
+
+/// The properties of `dw` shown in `--mode inspect`, one `name: value` per line.
+pub fn inspect_lines(dw: &DesktopWindow, instance: Option<&str>, pid: Option<u32>) -> Vec<String> {
+    let unknown = || "-".to_string();
+    vec![
+        format!("class: {}", dw.class.clone().unwrap_or_else(unknown)),
+        format!(
+            "instance: {}",
+            instance.map_or_else(unknown, str::to_string)
+        ),
+        format!("title: {}", dw.title.clone().unwrap_or_else(unknown)),
+        format!("pid: {}", pid.map_or_else(unknown, |pid| pid.to_string())),
+        format!("geometry: {}", geometry_text(dw)),
+        format!(
+            "workspace: {}",
+            dw.workspace.clone().unwrap_or_else(unknown)
+        ),
+        format!("output: {}", dw.output.clone().unwrap_or_else(unknown)),
+        format!(
+            "x id: {}",
+            dw.x_window_id
+                .map_or_else(unknown, |id| format!("0x{id:x}"))
+        ),
+        format!("con id: {}", dw.id),
+    ]
+}
+
+/// Remove last pressed key from pressed keys
+pub fn remove_last_key(pressed_keys: &mut String, kstr: &str) {
+    if pressed_keys.contains(kstr) {
+        pressed_keys.replace_range(pressed_keys.len() - kstr.len().., "");
     }
+}
 
-    fn explode(string: &str, separator: &str) -> Vec<String> {
-        string.split(separator).map(|s| s.to_string()).collect()
+/// Translates the keycodes of key events to keysyms.
+///
+/// The keyboard mapping changes when the user switches to another layout with different keys, so
+/// it has to be fetched again whenever `is_keymap_change` says so.
+#[derive(Debug)]
+pub struct Keymap {
+    min_keycode: u8,
+    keysyms_per_keycode: u8,
+    keysyms: Vec<u32>,
+}
+
+impl Keymap {
+    /// Get the current keyboard mapping from the X server.
+    pub fn fetch(conn: &impl Connection) -> Result<Keymap> {
+        let min_keycode = conn.setup().min_keycode;
+        let mapping = conn
+            .get_keyboard_mapping(min_keycode, conn.setup().max_keycode - min_keycode + 1)?
+            .reply()
+            .context("Couldn't get the keyboard mapping")?;
+        Ok(Keymap {
+            min_keycode,
+            keysyms_per_keycode: mapping.keysyms_per_keycode,
+            keysyms: mapping.keysyms,
+        })
     }
 
-    /// Sort vector alphabetically
-    fn sort(vec: &mut [String]) {
-        vec.sort_by_key(|a| a.to_lowercase());
+    /// The keysym of the key in a key press or release `event`, in the keyboard group (that is
+    /// layout) the event happened in.
+    pub fn keysym(&self, event: &KeyPressEvent) -> xkeysym::Keysym {
+        // With XKB, the group is in bits 13 and 14 of the state. The core keyboard mapping only
+        // has columns for the first two groups though.
+        let group = (u16::from(event.state) >> 13) & 0b11;
+        let lookup = |column| {
+            xkeysym::keysym(
+                event.detail.into(),
+                column,
+                self.min_keycode.into(),
+                self.keysyms_per_keycode,
+                &self.keysyms,
+            )
+            .filter(|keysym| *keysym != xkeysym::NO_SYMBOL)
+        };
+        let column = if group == 1 { 2 } else { 0 };
+        lookup(column)
+            .or_else(|| lookup(0))
+            .unwrap_or(xkeysym::NO_SYMBOL)
     }
 
-    pub fn remove(&mut self, key: &str) {
-        self.sequence.retain(|x| x != key);
+    /// The name of the keysym for `event` as used in key sequences, eg `Control_L`.
+    ///
+    /// Keys whose keysym doesn't have a name can't be part of any hint or key binding, so they're
+    /// best ignored.
+    pub fn key_name(&self, event: &KeyPressEvent) -> Option<String> {
+        let keysym = self.keysym(event);
+        match keysym.name() {
+            Some(name) => Some(name.replace("XK_", "")),
+            None => {
+                debug!("Ignoring keysym {:#x} without a name", keysym.raw());
+                None
+            }
+        }
     }
+}
 
-    pub fn push(&mut self, key: String) {
-        self.sequence.push(key);
-        Sequence::sort(&mut self.sequence);
+/// Ask for XKB events about keyboard mapping changes on top of the core MappingNotify, which not
+/// every layout switch causes.
+pub fn watch_keymap(conn: &impl Connection) -> Result<()> {
+    let reply = conn
+        .xkb_use_extension(1, 0)?
+        .reply()
+        .context("Couldn't enable XKB")?;
+    if !reply.supported {
+        bail!("The X server doesn't support XKB 1.0");
     }
+    conn.xkb_select_events(
+        xkb::ID::USE_CORE_KBD.into(),
+        xkb::EventType::from(0u16),
+        xkb::EventType::MAP_NOTIFY | xkb::EventType::NEW_KEYBOARD_NOTIFY,
+        xkb::MapPart::from(0u16),
+        xkb::MapPart::from(0u16),
+        &xkb::SelectEventsAux::new(),
+    )?
+    .check()
+    .context("Couldn't select XKB events")
+}
 
-    /// Sequence is started if more than one key is pressed
-    pub fn is_started(&self) -> bool {
-        self.sequence.len() > 1
+/// Whether `event` says that the keyboard mapping changed.
+pub fn is_keymap_change(event: &Event) -> bool {
+    match event {
+        Event::MappingNotify(event) => event.request == Mapping::KEYBOARD,
+        Event::XkbMapNotify(_) | Event::XkbNewKeyboardNotify(_) => true,
+        _ => false,
     }
 }
 
@@ -360,59 +1401,322 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_intersects() {
-        assert!(intersects((1905, 705, 31, 82), (1905, 723, 38, 64)));
+    fn test_signals() {
+        let signals = Signals::new().unwrap();
+        // Only this thread blocks the signals, so they have to be sent to this thread in
+        // particular.
+        for (signal, expected) in [
+            (libc::SIGUSR1, Signal::Show),
+            (libc::SIGHUP, Signal::Reload),
+        ] {
+            assert_eq!(
+                unsafe { libc::pthread_kill(libc::pthread_self(), signal) },
+                0
+            );
+            assert_eq!(signals.next().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_listen_fds() {
+        assert_eq!(listen_fds(Some("42"), Some("1"), 42), 1);
+        assert_eq!(listen_fds(Some("42"), Some("2"), 42), 2);
+        // Meant for someone else.
+        assert_eq!(listen_fds(Some("41"), Some("1"), 42), 0);
+        assert_eq!(listen_fds(None, Some("1"), 42), 0);
+        assert_eq!(listen_fds(Some("42"), None, 42), 0);
+        assert_eq!(listen_fds(Some("42"), Some("many"), 42), 0);
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let grab_error: anyhow::Error =
+            GrabError("Couldn't grab keyboard input".to_string()).into();
+        assert_eq!(exit_status(&grab_error), ExitStatus::GrabFailed);
+        // Failing to fall back to a focused window is still about the grab.
+        let grab_error = grab_error.context(anyhow::anyhow!("Couldn't focus the fallback window"));
+        assert_eq!(exit_status(&grab_error), ExitStatus::GrabFailed);
+        let error = anyhow::anyhow!("Couldn't get desktop windows");
+        assert_eq!(exit_status(&error), ExitStatus::Error);
     }
 
     #[test]
-    fn test_no_intersect() {
-        assert!(!intersects((1905, 705, 31, 82), (2000, 723, 38, 64)));
+    fn test_list_entry() {
+        let window = DesktopWindow {
+            class: Some("Firefox".to_string()),
+            title: Some("Two\nlines".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(list_entry("sa", &window), "sa\tFirefox: Two lines");
+        let workspace = DesktopWindow {
+            workspace: Some("3".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(list_entry("d", &workspace), "d\t3");
+        let cell = DesktopWindow {
+            pos: (10, 20),
+            size: (300, 200),
+            ..Default::default()
+        };
+        assert_eq!(list_entry("f", &cell), "f\t300x200+10+20");
     }
 
     #[test]
-    fn test_sequences_equal() {
-        let a = Sequence::new(Some("Control_L+Shift_L+a"));
-        let b = Sequence::new(Some("Control_L+a+Shift_L"));
+    fn test_list_choice() {
+        assert_eq!(list_choice("sa\tFirefox: Two lines\n"), Some("sa"));
+        assert_eq!(list_choice("sa\n"), Some("sa"));
+        assert_eq!(list_choice("\n"), None);
+        assert_eq!(list_choice(""), None);
+    }
+
+    #[test]
+    fn test_parse_stdin_rects() {
+        let dws = parse_stdin_rects(
+            r#"[
+                {"id": "0x1a2b", "x": 10, "y": 20, "w": 300, "h": 400},
+                {"id": 42, "x": 0, "y": 0, "w": 1, "h": 1, "label": "foo"}
+            ]"#,
+        )
+        .unwrap();
 
-        assert_eq!(a, b);
+        assert_eq!(dws.len(), 2);
+        assert_eq!(dws[0].external_id.as_deref(), Some("0x1a2b"));
+        assert_eq!(dws[0].pos, (10, 20));
+        assert_eq!(dws[0].size, (300, 400));
+        assert_eq!(dws[0].label, None);
+        assert_eq!(dws[1].external_id.as_deref(), Some("42"));
+        assert_eq!(dws[1].label.as_deref(), Some("foo"));
+    }
 
-        let mut c = Sequence::new(None);
+    #[test]
+    fn test_parse_stdin_rects_invalid() {
+        assert!(parse_stdin_rects(r#"[{"id": 1, "x": 0}]"#).is_err());
+        assert!(parse_stdin_rects("not json").is_err());
+    }
 
-        c.push("Shift_L".to_owned());
-        c.push("Control_L".to_owned());
-        c.push("a".to_owned());
+    #[test]
+    fn test_measured_text() {
+        let window = DesktopWindow::default();
+        assert_eq!(measured_text("sa", &window, 'w'), "ww");
+        let mark = DesktopWindow {
+            label: Some("mail".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(measured_text("sa", &mark, 'w'), "sa mail");
+    }
+
+    #[test]
+    fn test_extents_cache() {
+        let extents = extents_for_text("as", "monospace", 20.0).unwrap();
+        let cached = EXTENTS_CACHE.with(|cache| cache.borrow().len());
+        assert_eq!(
+            extents_for_text("as", "monospace", 20.0)
+                .unwrap()
+                .x_advance(),
+            extents.x_advance()
+        );
+        assert_eq!(EXTENTS_CACHE.with(|cache| cache.borrow().len()), cached);
+        extents_for_text("as", "monospace", 30.0).unwrap();
+        assert_eq!(EXTENTS_CACHE.with(|cache| cache.borrow().len()), cached + 1);
+    }
+
+    #[test]
+    fn test_paint_group() {
+        let pixel = |surface: &mut cairo::ImageSurface| surface.data().unwrap()[0];
+        let mut target = cairo::ImageSurface::create(cairo::Format::Rgb24, 1, 1).unwrap();
+        let mut backdrop = cairo::ImageSurface::create(cairo::Format::Rgb24, 1, 1).unwrap();
+        let cr = cairo::Context::new(&backdrop).unwrap();
+        cr.set_source_rgb(1.0, 1.0, 1.0);
+        cr.paint().unwrap();
+        drop(cr);
+        assert_eq!(pixel(&mut backdrop), 255);
+
+        let draw_black = |target: &cairo::ImageSurface, backdrop: Option<&cairo::ImageSurface>| {
+            let cr = cairo::Context::new(target).unwrap();
+            cr.push_group();
+            cr.set_source_rgb(0.0, 0.0, 0.0);
+            cr.paint().unwrap();
+            paint_group(&cr, backdrop, 0.5).unwrap();
+        };
+
+        // Without a backdrop, the alpha is left to the compositor.
+        draw_black(&target, None);
+        assert_eq!(pixel(&mut target), 0);
+        draw_black(&target, Some(&backdrop));
+        assert!((127..=128).contains(&pixel(&mut target)));
+    }
 
-        assert_eq!(a, c);
+    #[test]
+    fn test_inspect_lines() {
+        let dw = DesktopWindow {
+            id: 94,
+            x_window_id: Some(0x1a2b),
+            class: Some("Firefox".to_string()),
+            title: Some("Mozilla Firefox".to_string()),
+            workspace: Some("2".to_string()),
+            pos: (0, 24),
+            size: (960, 1056),
+            ..Default::default()
+        };
+        assert_eq!(
+            inspect_lines(&dw, Some("Navigator"), Some(4242)),
+            vec![
+                "class: Firefox",
+                "instance: Navigator",
+                "title: Mozilla Firefox",
+                "pid: 4242",
+                "geometry: 0,24 960x1056",
+                "workspace: 2",
+                "output: -",
+                "x id: 0x1a2b",
+                "con id: 94",
+            ]
+        );
     }
 
     #[test]
-    fn test_sequences_not_equal() {
-        let a = Sequence::new(Some("Control_L+Shift_L+a"));
-        let b = Sequence::new(Some("Control_L+a"));
+    fn test_copy_text() {
+        let dw = DesktopWindow {
+            x_window_id: Some(0x1a2b),
+            title: Some("vim".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(copy_text(&dw, CopyValue::XId), "0x1a2b");
+        assert_eq!(copy_text(&dw, CopyValue::Title), "vim");
+        assert_eq!(copy_text(&dw, CopyValue::Class), "");
+    }
 
-        assert_ne!(a, b);
+    #[test]
+    fn test_list_row() {
+        let dw = DesktopWindow {
+            id: 94,
+            class: Some("kitty".to_string()),
+            title: Some("vim\tmain.rs".to_string()),
+            pos: (0, 24),
+            size: (960, 1056),
+            ..Default::default()
+        };
+        assert_eq!(
+            list_row("a", &dw),
+            "a\t94\tkitty\tvim main.rs\t0,24 960x1056"
+        );
+    }
 
-        let mut c = Sequence::new(None);
+    #[test]
+    fn test_geometry_text() {
+        let dw = DesktopWindow {
+            pos: (1920, 24),
+            size: (800, 600),
+            ..Default::default()
+        };
+        assert_eq!(geometry_text(&dw), "1920,24 800x600");
+    }
 
-        c.push("Shift_L".to_owned());
-        c.push("a".to_owned());
+    #[test]
+    fn test_selection_json() {
+        let dw = DesktopWindow {
+            id: 94,
+            x_window_id: Some(0x1a2b),
+            pos: (10, 20),
+            size: (300, 400),
+            class: Some("kitty".to_string()),
+            title: Some("vim".to_string()),
+            workspace: Some("1".to_string()),
+            output: Some("DP-1".to_string()),
+            ..Default::default()
+        };
+        let json = selection_json(&dw);
+        assert_eq!(json["con_id"], 94);
+        assert_eq!(json["x_window_id"], 0x1a2b);
+        assert_eq!(json["class"], "kitty");
+        assert_eq!(json["title"], "vim");
+        assert_eq!(json["geometry"]["width"], 300);
+        assert_eq!(json["label"], serde_json::Value::Null);
+        assert!(json.get("id").is_none());
+    }
 
-        assert_ne!(a, c);
+    #[test]
+    fn test_format_selection() {
+        let dw = DesktopWindow {
+            id: 94,
+            x_window_id: Some(0x1a2b),
+            size: (300, 400),
+            class: Some("kitty".to_string()),
+            marks: vec!["a".to_string(), "b".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            format_selection("{x_id} {con_id} {class} {title}|{marks}", &dw).unwrap(),
+            "0x1a2b 94 kitty |a,b"
+        );
+        assert_eq!(
+            format_selection("{{{width}x{height}}}", &dw).unwrap(),
+            "{300x400}"
+        );
+        assert!(format_selection("{nonsense}", &dw).is_err());
+        assert!(format_selection("{class", &dw).is_err());
+        assert!(format_selection("class}", &dw).is_err());
     }
 
     #[test]
-    fn test_sequences_is_started() {
-        let mut sequence = Sequence::new(None);
-        assert!(!sequence.is_started());
+    fn test_keymap() {
+        use x11rb::protocol::xproto::MappingNotifyEvent;
+        use xkeysym::Keysym;
 
-        sequence.push("Control_L".to_owned());
-        assert!(!sequence.is_started());
+        // A German and a Russian layout, with only the first group having a letter on the second
+        // key.
+        let keymap = Keymap {
+            min_keycode: 8,
+            keysyms_per_keycode: 4,
+            keysyms: vec![
+                Keysym::a.into(),
+                Keysym::A.into(),
+                Keysym::Cyrillic_ef.into(),
+                Keysym::Cyrillic_EF.into(),
+                Keysym::z.into(),
+                Keysym::Z.into(),
+                0,
+                0,
+            ],
+        };
+        let key = |detail, group: u16| KeyPressEvent {
+            detail,
+            state: (group << 13).into(),
+            ..Default::default()
+        };
+        assert_eq!(keymap.keysym(&key(8, 0)), Keysym::a);
+        assert_eq!(keymap.keysym(&key(8, 1)), Keysym::Cyrillic_ef);
+        assert_eq!(keymap.keysym(&key(9, 1)), Keysym::z);
+        // There are no columns for the third group.
+        assert_eq!(keymap.keysym(&key(8, 2)), Keysym::a);
 
-        sequence.push("g".to_owned());
-        assert!(sequence.is_started());
+        let mapping = |request| {
+            Event::MappingNotify(MappingNotifyEvent {
+                request,
+                ..Default::default()
+            })
+        };
+        assert!(is_keymap_change(&mapping(Mapping::KEYBOARD)));
+        assert!(!is_keymap_change(&mapping(Mapping::POINTER)));
+    }
 
-        sequence.remove("g");
+    #[test]
+    fn test_describe_client() {
+        let name = std::fs::read_to_string("/proc/self/comm").unwrap();
+        assert_eq!(
+            describe_client(0x1a00003, Some(std::process::id())),
+            format!("{} (pid {})", name.trim_end(), std::process::id())
+        );
+        assert_eq!(
+            describe_client(0x1a00003, None),
+            "the client of window 0x1a00003"
+        );
+    }
 
-        assert!(!sequence.is_started());
+    #[test]
+    fn test_dimmed_opacity() {
+        assert_eq!(dimmed_opacity(None, 1.0), u32::MAX);
+        assert_eq!(dimmed_opacity(None, 0.5), 0x8000_0000);
+        assert_eq!(dimmed_opacity(Some(0x8000_0000), 0.5), 0x4000_0000);
     }
 }
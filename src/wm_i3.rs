@@ -1,9 +1,453 @@
-use anyhow::{Context, Result};
-use i3ipc::reply::{Node, NodeLayout, NodeType, Workspace};
+use std::collections::HashMap;
+use std::env;
+use std::io::{Read, Write};
+use std::iter;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::net::UnixStream;
+use std::process::Command;
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use i3ipc::reply::{Node, NodeBorder, NodeLayout, NodeType, WindowProperty, Workspace};
 use i3ipc::I3Connection;
+use itertools::Itertools;
 use log::{debug, info};
+use serde_json::Value;
+
+use wmfocus::backend::Backend;
+use wmfocus::event_loop;
+use wmfocus::overlay::WindowChanges;
+use wmfocus::{DesktopOutput, DesktopWindow, Transform};
+
+/// Magic string that starts every i3 IPC message.
+const IPC_MAGIC: &[u8] = b"i3-ipc";
+
+/// i3 IPC message type to subscribe to events.
+const IPC_SUBSCRIBE: u32 = 2;
+
+/// i3 IPC message type to request the outputs.
+const IPC_GET_OUTPUTS: u32 = 3;
+
+/// i3 IPC message type to request the layout tree.
+const IPC_GET_TREE: u32 = 4;
+
+/// i3 IPC message type to request the loaded config.
+const IPC_GET_CONFIG: u32 = 9;
+
+/// i3 IPC message type to request the current binding mode.
+const IPC_GET_BINDING_STATE: u32 = 12;
+
+/// i3 IPC message type of window events (which have the highest bit set).
+const IPC_EVENT_WINDOW: u32 = 0x8000_0003;
+
+/// i3 IPC message type of binding events, sent whenever a key binding is run.
+const IPC_EVENT_BINDING: u32 = 0x8000_0005;
+
+/// Properties of a container that i3ipc doesn't give us.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct NodeProperties {
+    marks: Vec<String>,
+    app_id: Option<String>,
+    sticky: bool,
+    fullscreen: bool,
+    pid: Option<u32>,
+}
+
+/// i3 or sway, whichever we're running on.
+#[derive(Debug, Clone, Copy)]
+pub struct I3;
+
+impl Backend for I3 {
+    fn name(&self) -> &str {
+        backend_name()
+    }
+
+    fn windows(&self, include_containers: bool) -> Result<Vec<DesktopWindow>> {
+        get_windows(include_containers)
+    }
+
+    fn outputs(&self) -> Result<Vec<DesktopOutput>> {
+        get_desktop_outputs()
+    }
+
+    fn focus(&self, window: &DesktopWindow) -> Result<()> {
+        run_command(&focus_window_command(window)?)
+    }
+}
+
+/// Name of the window manager we're talking to, used to pick its section in the config file.
+///
+/// sway speaks the same IPC protocol as i3 but always sets `SWAYSOCK`.
+fn backend_name() -> &'static str {
+    if env::var_os("SWAYSOCK").is_some() {
+        "sway"
+    } else {
+        "i3"
+    }
+}
+
+/// Figure out where the i3 (or sway) IPC socket lives.
+fn get_socket_path() -> Result<String> {
+    if let Ok(path) = env::var("I3SOCK") {
+        return Ok(path);
+    }
+    if let Ok(path) = env::var("SWAYSOCK") {
+        return Ok(path);
+    }
+    let output = Command::new("i3")
+        .arg("--get-socketpath")
+        .output()
+        .context("Couldn't run i3 --get-socketpath")?;
+    if !output.status.success() {
+        bail!("i3 --get-socketpath didn't return 0");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string())
+}
+
+/// Write an IPC message to i3.
+fn write_message(mut stream: &UnixStream, message_type: u32, payload: &str) -> Result<()> {
+    let mut message = IPC_MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&message_type.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    stream
+        .write_all(&message)
+        .context("Couldn't send message to i3")
+}
 
-use crate::DesktopWindow;
+/// Read the next IPC message (a reply or an event) from i3 and return its type and parsed body.
+fn read_message(mut stream: &UnixStream) -> Result<(u32, Value)> {
+    let mut header = [0; 14];
+    stream
+        .read_exact(&mut header)
+        .context("Couldn't receive reply header from i3")?;
+    if &header[..6] != IPC_MAGIC {
+        bail!("Got invalid reply from i3");
+    }
+    let length = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]);
+    let message_type = u32::from_ne_bytes([header[10], header[11], header[12], header[13]]);
+    let mut body = vec![0; length as usize];
+    stream
+        .read_exact(&mut body)
+        .context("Couldn't receive reply from i3")?;
+    let body = serde_json::from_slice(&body).context("Couldn't parse reply from i3")?;
+    Ok((message_type, body))
+}
+
+/// Send a raw IPC message to i3 and return the parsed reply.
+///
+/// i3ipc drops some properties we're interested in (such as marks), so for those we'll have to
+/// talk to i3 ourselves.
+fn send_raw_message(message_type: u32, payload: &str) -> Result<Value> {
+    let stream =
+        UnixStream::connect(get_socket_path()?).context("Couldn't connect to i3 socket")?;
+    write_message(&stream, message_type, payload)?;
+    Ok(read_message(&stream)?.1)
+}
+
+/// Window events from i3, see `subscribe_window_events`.
+#[derive(Debug)]
+pub struct WindowEvents {
+    stream: UnixStream,
+}
+
+/// Start listening for windows being opened or closed.
+pub fn subscribe_window_events() -> Result<WindowEvents> {
+    let stream =
+        UnixStream::connect(get_socket_path()?).context("Couldn't connect to i3 socket")?;
+    write_message(&stream, IPC_SUBSCRIBE, r#"["window"]"#)?;
+    let (_, reply) = read_message(&stream)?;
+    if reply["success"] != true {
+        bail!("i3 didn't let us subscribe to window events");
+    }
+    Ok(WindowEvents { stream })
+}
+
+/// Whether the window event `event` is about a window being opened or closed.
+fn is_opened_or_closed(event: &Value) -> bool {
+    matches!(event["change"].as_str(), Some("new" | "close"))
+}
+
+impl AsFd for WindowEvents {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+impl WindowChanges for WindowEvents {
+    fn changed(&self) -> Result<bool> {
+        let (message_type, event) = read_message(&self.stream)?;
+        if message_type != IPC_EVENT_WINDOW {
+            debug!("Ignoring i3 message of type 0x{message_type:x}");
+            return Ok(false);
+        }
+        Ok(is_opened_or_closed(&event))
+    }
+}
+
+/// The windows `get_windows` returns, fetched ahead of time and again whenever i3 reports a change
+/// so that showing hints doesn't have to wait for them.
+///
+/// Besides windows and workspaces, this includes outputs and key bindings. Resizing and layout
+/// changes don't have events of their own, but they're mostly done through key bindings. Bindings
+/// that only start programs (like the one showing the hints) don't count.
+#[derive(Debug)]
+pub struct WarmWindows {
+    stream: UnixStream,
+    include_containers: bool,
+    windows: Vec<DesktopWindow>,
+}
+
+impl WarmWindows {
+    /// Start listening for changes and get the windows for the first time.
+    pub fn new(include_containers: bool) -> Result<WarmWindows> {
+        let stream =
+            UnixStream::connect(get_socket_path()?).context("Couldn't connect to i3 socket")?;
+        write_message(
+            &stream,
+            IPC_SUBSCRIBE,
+            r#"["window", "workspace", "output", "binding"]"#,
+        )?;
+        let (_, reply) = read_message(&stream)?;
+        if reply["success"] != true {
+            bail!("i3 didn't let us subscribe to events");
+        }
+        Ok(WarmWindows {
+            stream,
+            include_containers,
+            windows: get_windows(include_containers)?,
+        })
+    }
+
+    /// Take in the events that arrived so far and get the windows again if there were any.
+    pub fn refresh(&mut self) -> Result<()> {
+        if drain_events(&self.stream)? {
+            self.windows = get_windows(self.include_containers)?;
+        }
+        Ok(())
+    }
+
+    /// Return what `get_windows(include_containers)` would.
+    pub fn windows(&mut self, include_containers: bool) -> Result<Vec<DesktopWindow>> {
+        if include_containers == self.include_containers {
+            self.refresh()?;
+        } else {
+            self.include_containers = include_containers;
+            self.windows = get_windows(include_containers)?;
+        }
+        Ok(self.windows.clone())
+    }
+}
+
+/// Read all events that already arrived on `stream` and return whether any of them might have
+/// changed the windows.
+fn drain_events(stream: &UnixStream) -> Result<bool> {
+    let mut any = false;
+    while event_loop::wait_readable(&[stream.as_fd()], Some(Instant::now()))?.is_some() {
+        let (message_type, event) = read_message(stream)?;
+        debug!("Got i3 event of type 0x{message_type:x}");
+        let command = event["binding"]["command"].as_str().unwrap_or_default();
+        if message_type == IPC_EVENT_BINDING && command.starts_with("exec ") {
+            continue;
+        }
+        any = true;
+    }
+    Ok(any)
+}
+
+impl AsFd for WarmWindows {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+/// Parse an output transform as reported by sway.
+fn parse_transform(transform: &str) -> Transform {
+    match transform {
+        "90" => Transform::Rotate90,
+        "180" => Transform::Rotate180,
+        "270" => Transform::Rotate270,
+        "flipped" => Transform::Flipped,
+        "flipped-90" => Transform::Flipped90,
+        "flipped-180" => Transform::Flipped180,
+        "flipped-270" => Transform::Flipped270,
+        _ => Transform::Normal,
+    }
+}
+
+/// Parse the reply of a `GET_OUTPUTS` message into `DesktopOutput`s.
+///
+/// i3 doesn't know about output transforms or scaling (it leaves that to X), so they'll only be set
+/// on sway.
+fn parse_desktop_outputs(reply: &Value) -> Vec<DesktopOutput> {
+    reply
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|o| o["active"].as_bool().unwrap_or_default())
+        .filter_map(|o| {
+            let rect = &o["rect"];
+            Some(DesktopOutput {
+                name: o["name"].as_str()?.to_string(),
+                rect: (
+                    rect["x"].as_i64()? as i32,
+                    rect["y"].as_i64()? as i32,
+                    rect["width"].as_i64()? as i32,
+                    rect["height"].as_i64()? as i32,
+                ),
+                transform: o["transform"]
+                    .as_str()
+                    .map(parse_transform)
+                    .unwrap_or_default(),
+                scale: o["scale"].as_f64().unwrap_or(1.0),
+            })
+        })
+        .collect()
+}
+
+/// Return all active outputs.
+pub fn get_desktop_outputs() -> Result<Vec<DesktopOutput>> {
+    Ok(parse_desktop_outputs(&send_raw_message(
+        IPC_GET_OUTPUTS,
+        "",
+    )?))
+}
+
+/// Recursively collect the `NodeProperties` of `node` and all its children by container id.
+fn collect_node_properties(node: &Value, properties: &mut HashMap<i64, NodeProperties>) {
+    if let Some(id) = node["id"].as_i64() {
+        let marks = node["marks"]
+            .as_array()
+            .map(|marks| {
+                marks
+                    .iter()
+                    .filter_map(|m| m.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let app_id = node["app_id"].as_str().map(str::to_string);
+        let sticky = node["sticky"].as_bool().unwrap_or_default();
+        let fullscreen = node["fullscreen_mode"]
+            .as_i64()
+            .is_some_and(|mode| mode != 0);
+        let pid = node["pid"].as_u64().and_then(|pid| u32::try_from(pid).ok());
+        properties.insert(
+            id,
+            NodeProperties {
+                marks,
+                app_id,
+                sticky,
+                fullscreen,
+                pid,
+            },
+        );
+    }
+    for key in ["nodes", "floating_nodes"] {
+        for child in node[key].as_array().into_iter().flatten() {
+            collect_node_properties(child, properties);
+        }
+    }
+}
+
+/// Build an i3ipc `Node` out of a `GET_TREE` reply the way i3ipc does.
+///
+/// This way, the same reply also gives us the `NodeProperties` that i3ipc doesn't parse.
+fn node_from_json(value: &Value) -> Node {
+    let rect = |key: &str| {
+        let r = &value[key];
+        let get = |k: &str| r[k].as_i64().unwrap_or_default() as i32;
+        (get("x"), get("y"), get("width"), get("height"))
+    };
+    let children = |key: &str| {
+        value[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(node_from_json)
+            .collect()
+    };
+    let window_properties = value["window_properties"].as_object().map(|properties| {
+        properties
+            .iter()
+            .filter_map(|(key, value)| {
+                let property = match key.as_str() {
+                    "class" => WindowProperty::Class,
+                    "instance" => WindowProperty::Instance,
+                    "window_role" => WindowProperty::WindowRole,
+                    "title" => WindowProperty::Title,
+                    "transient_for" => WindowProperty::TransientFor,
+                    _ => return None,
+                };
+                Some((property, value.as_str().unwrap_or_default().to_string()))
+            })
+            .collect()
+    });
+    Node {
+        focus: value["focus"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_i64)
+            .collect(),
+        nodes: children("nodes"),
+        floating_nodes: children("floating_nodes"),
+        id: value["id"].as_i64().unwrap_or_default(),
+        name: value["name"].as_str().map(str::to_string),
+        nodetype: match value["type"].as_str() {
+            Some("root") => NodeType::Root,
+            Some("output") => NodeType::Output,
+            Some("con") => NodeType::Con,
+            Some("floating_con") => NodeType::FloatingCon,
+            Some("workspace") => NodeType::Workspace,
+            Some("dockarea") => NodeType::DockArea,
+            _ => NodeType::Unknown,
+        },
+        border: match value["border"].as_str() {
+            Some("normal") => NodeBorder::Normal,
+            Some("none") => NodeBorder::None,
+            Some("pixel") => NodeBorder::Pixel,
+            _ => NodeBorder::Unknown,
+        },
+        current_border_width: value["current_border_width"].as_i64().unwrap_or_default() as i32,
+        layout: match value["layout"].as_str() {
+            Some("splith") => NodeLayout::SplitH,
+            Some("splitv") => NodeLayout::SplitV,
+            Some("stacked") => NodeLayout::Stacked,
+            Some("tabbed") => NodeLayout::Tabbed,
+            Some("dockarea") => NodeLayout::DockArea,
+            Some("output") => NodeLayout::Output,
+            _ => NodeLayout::Unknown,
+        },
+        percent: value["percent"].as_f64(),
+        rect: rect("rect"),
+        window_rect: rect("window_rect"),
+        deco_rect: rect("deco_rect"),
+        geometry: rect("geometry"),
+        window: value["window"].as_i64().map(|window| window as i32),
+        window_properties,
+        urgent: value["urgent"].as_bool().unwrap_or_default(),
+        focused: value["focused"].as_bool().unwrap_or_default(),
+    }
+}
+
+/// Get the layout tree along with the `NodeProperties` of all its containers.
+fn get_tree() -> Result<(Node, HashMap<i64, NodeProperties>)> {
+    let reply = send_raw_message(IPC_GET_TREE, "").context("Couldn't get the layout tree")?;
+    let mut properties = HashMap::new();
+    collect_node_properties(&reply, &mut properties);
+    Ok((node_from_json(&reply), properties))
+}
+
+/// Return the class of the window in `node`, falling back to its app_id for native Wayland
+/// windows on sway.
+fn window_class(node: &Node, properties: &HashMap<i64, NodeProperties>) -> Option<String> {
+    node.window_properties
+        .as_ref()
+        .and_then(|p| p.get(&WindowProperty::Class).cloned())
+        .or_else(|| properties.get(&node.id).and_then(|p| p.app_id.clone()))
+}
 
 /// Find first `Node` that fulfills a given criterion.
 fn find_first_node_with_attr<F>(start_node: &Node, predicate: F) -> Option<&Node>
@@ -40,53 +484,152 @@ fn find_parent_of<'a>(start_node: &'a Node, child: &'a Node) -> Option<&'a Node>
     None
 }
 
+/// Return the path of `Node`s from `start_node` down to the first `Node` that fulfills a given
+/// criterion (including both ends).
+fn find_path_to_node_with_attr<F>(start_node: &Node, predicate: F) -> Option<Vec<&Node>>
+where
+    F: Fn(&Node) -> bool + Copy,
+{
+    if predicate(start_node) {
+        return Some(vec![start_node]);
+    }
+    for node in start_node
+        .nodes
+        .iter()
+        .chain(start_node.floating_nodes.iter())
+    {
+        if let Some(mut path) = find_path_to_node_with_attr(node, predicate) {
+            path.insert(0, start_node);
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Figure out where to draw the hint for a parent `node` (a split, tabbed or stacked container).
+///
+/// If the container has a decoration (because it's inside of a tabbed or stacked container), we'll
+/// use that. Otherwise, we'll use the whole area of the container.
+fn container_geometry(root_node: &Node, node: &Node) -> ((i32, i32), (i32, i32)) {
+    if node.deco_rect.3 == 0 {
+        return ((node.rect.0, node.rect.1), (node.rect.2, node.rect.3));
+    }
+    let pos_y = match find_parent_of(root_node, node) {
+        Some(parent) if parent.layout == NodeLayout::Stacked => parent.rect.1 + node.deco_rect.1,
+        _ => node.rect.1 + node.deco_rect.1,
+    };
+    (
+        (node.rect.0 + node.deco_rect.0, pos_y),
+        (node.deco_rect.2, node.deco_rect.3),
+    )
+}
+
+/// Figure out where to draw the hint for a `node` with a window in it.
+///
+/// That's the window itself without any borders or gaps around it, which `window_rect` has
+/// relative to the container. Inside of a tabbed or stacked `parent`, the title of the window (in
+/// the tab bar or stack of titles of the parent) is included so that the hints of the windows in
+/// there don't all end up in the same place.
+fn window_geometry(node: &Node, parent: Option<&Node>) -> ((i32, i32), (i32, i32)) {
+    let (x, y, width, height) = (
+        node.rect.0 + node.window_rect.0,
+        node.rect.1 + node.window_rect.1,
+        node.window_rect.2,
+        node.window_rect.3,
+    );
+    match parent {
+        Some(parent)
+            if matches!(parent.layout, NodeLayout::Tabbed | NodeLayout::Stacked)
+                && node.deco_rect.3 > 0 =>
+        {
+            // The decoration is drawn onto the parent here and so is relative to it.
+            let title_x = parent.rect.0 + node.deco_rect.0;
+            let title_y = parent.rect.1 + node.deco_rect.1;
+            ((title_x, title_y), (node.deco_rect.2, y + height - title_y))
+        }
+        _ => ((x, y), (width, height)),
+    }
+}
+
 /// Return a list of all `DesktopWindow`s for the given `Workspace`.
-fn crawl_windows(root_node: &Node, workspace: &Workspace) -> Result<Vec<DesktopWindow>> {
+///
+/// If `include_containers` is set, this will also return parent containers as `DesktopWindow`s.
+fn crawl_windows(
+    root_node: &Node,
+    workspace: &Workspace,
+    properties: &HashMap<i64, NodeProperties>,
+    include_containers: bool,
+) -> Result<Vec<DesktopWindow>> {
     let workspace_node = find_first_node_with_attr(root_node, |x| {
         x.name == Some(workspace.name.clone()) && x.nodetype == NodeType::Workspace
     })
     .context("Couldn't find the Workspace node")?;
 
-    let mut nodes_to_explore: Vec<&Node> = workspace_node.nodes.iter().collect();
-    nodes_to_explore.extend(workspace_node.floating_nodes.iter());
+    // Along with every node, keep track of whether it's floating.
+    let mut nodes_to_explore: Vec<(&Node, bool)> =
+        workspace_node.nodes.iter().map(|n| (n, false)).collect();
+    nodes_to_explore.extend(workspace_node.floating_nodes.iter().map(|n| (n, true)));
     let mut windows = vec![];
     while !nodes_to_explore.is_empty() {
         let mut next_vec = vec![];
-        for node in &nodes_to_explore {
-            next_vec.extend(node.nodes.iter());
-            next_vec.extend(node.floating_nodes.iter());
+        for &(node, is_floating) in &nodes_to_explore {
+            next_vec.extend(node.nodes.iter().map(|n| (n, is_floating)));
+            next_vec.extend(node.floating_nodes.iter().map(|n| (n, true)));
             if node.window.is_some() {
-                let root_node = find_parent_of(root_node, node);
-
-                let (pos_x, size_x) = if let Some(root_node) = root_node {
-                    if root_node.layout == NodeLayout::Tabbed {
-                        (node.rect.0 + node.deco_rect.0, node.deco_rect.2)
-                    } else {
-                        (node.rect.0, node.rect.2)
-                    }
-                } else {
-                    (node.rect.0, node.rect.2)
-                };
-
-                let pos_y = if let Some(root_node) = root_node {
-                    if root_node.layout == NodeLayout::Stacked {
-                        root_node.rect.1 + node.deco_rect.1
-                    } else {
-                        node.rect.1 + node.deco_rect.3
-                    }
-                } else {
-                    node.rect.1 + node.deco_rect.3
-                };
-
+                let (pos, size) = window_geometry(node, find_parent_of(root_node, node));
                 let window = DesktopWindow {
                     id: node.id,
                     x_window_id: node.window,
-                    pos: (pos_x, pos_y),
-                    size: (size_x, (node.rect.3 + node.deco_rect.3)),
+                    pos,
+                    size,
                     is_focused: node.focused,
+                    is_urgent: node.urgent,
+                    marks: properties
+                        .get(&node.id)
+                        .map(|p| p.marks.clone())
+                        .unwrap_or_default(),
+                    class: window_class(node, properties),
+                    title: node.name.clone(),
+                    is_sticky: properties.get(&node.id).is_some_and(|p| p.sticky),
+                    is_fullscreen: properties.get(&node.id).is_some_and(|p| p.fullscreen),
+                    is_floating,
+                    pid: properties.get(&node.id).and_then(|p| p.pid),
+                    workspace: Some(workspace.name.clone()),
+                    output: Some(workspace.output.clone()),
+                    label: None,
+                    is_listed: false,
+                    external_id: None,
                 };
                 debug!("Found {:?}", window);
                 windows.push(window);
+            } else if include_containers && node.nodetype == NodeType::Con && !node.nodes.is_empty()
+            {
+                let (pos, size) = container_geometry(root_node, node);
+                let container = DesktopWindow {
+                    id: node.id,
+                    x_window_id: None,
+                    pos,
+                    size,
+                    is_focused: node.focused,
+                    is_urgent: node.urgent,
+                    marks: properties
+                        .get(&node.id)
+                        .map(|p| p.marks.clone())
+                        .unwrap_or_default(),
+                    class: None,
+                    title: node.name.clone(),
+                    is_sticky: properties.get(&node.id).is_some_and(|p| p.sticky),
+                    is_fullscreen: properties.get(&node.id).is_some_and(|p| p.fullscreen),
+                    is_floating,
+                    pid: properties.get(&node.id).and_then(|p| p.pid),
+                    workspace: Some(workspace.name.clone()),
+                    output: Some(workspace.output.clone()),
+                    label: None,
+                    is_listed: false,
+                    external_id: None,
+                };
+                debug!("Found container {:?}", container);
+                windows.push(container);
             }
         }
         nodes_to_explore = next_vec;
@@ -95,7 +638,9 @@ fn crawl_windows(root_node: &Node, workspace: &Workspace) -> Result<Vec<DesktopW
 }
 
 /// Return a list of all windows.
-pub fn get_windows() -> Result<Vec<DesktopWindow>> {
+///
+/// If `include_containers` is set, parent containers will be returned as well.
+pub fn get_windows(include_containers: bool) -> Result<Vec<DesktopWindow>> {
     // Establish a connection to i3 over a unix socket
     let mut connection = I3Connection::connect().context("Couldn't acquire i3 connection")?;
     let workspaces = connection
@@ -103,35 +648,863 @@ pub fn get_windows() -> Result<Vec<DesktopWindow>> {
         .context("Problem communicating with i3")?
         .workspaces;
     let visible_workspaces = workspaces.iter().filter(|w| w.visible);
-    let root_node = connection.get_tree()?;
-    let mut windows = vec![];
+    let (root_node, properties) = get_tree()?;
+    let mut windows: Vec<DesktopWindow> = vec![];
     for workspace in visible_workspaces {
-        windows.extend(crawl_windows(&root_node, workspace)?);
+        for window in crawl_windows(&root_node, workspace, &properties, include_containers)? {
+            // Sticky windows might show up on more than one workspace but we only want a single
+            // hint for them.
+            if windows.iter().any(|w| w.id == window.id) {
+                debug!("Skipping duplicate {:?}", window);
+                continue;
+            }
+            windows.push(window);
+        }
     }
     Ok(windows)
 }
 
-/// Focus a specific `window`.
-pub fn focus_window(window: &DesktopWindow) -> Result<()> {
+/// Return a `DesktopWindow` for every mark on any window.
+///
+/// Marked windows on workspaces that currently aren't visible have no place on the screen, so
+/// their marks will be listed in the middle of the focused workspace instead.
+pub fn get_marks() -> Result<Vec<DesktopWindow>> {
+    let mut connection = I3Connection::connect().context("Couldn't acquire i3 connection")?;
+    let workspaces = connection
+        .get_workspaces()
+        .context("Problem communicating with i3")?
+        .workspaces;
+    let (root_node, properties) = get_tree()?;
+    let focused_rect = workspaces
+        .iter()
+        .find(|w| w.focused)
+        .context("Couldn't find the focused workspace")?
+        .rect;
+
+    let mut marks = vec![];
+    for workspace in &workspaces {
+        for window in crawl_windows(&root_node, workspace, &properties, true)? {
+            for mark in &window.marks {
+                let mut desktop_mark = DesktopWindow {
+                    marks: vec![mark.clone()],
+                    label: Some(mark.clone()),
+                    is_listed: !workspace.visible,
+                    external_id: None,
+                    ..window.clone()
+                };
+                if !workspace.visible {
+                    desktop_mark.pos = (focused_rect.0, focused_rect.1);
+                    desktop_mark.size = (focused_rect.2, focused_rect.3);
+                }
+                debug!("Found mark {:?}", desktop_mark);
+                marks.push(desktop_mark);
+            }
+        }
+    }
+    Ok(marks)
+}
+
+/// Return the tabs of the tabbed or stacked container that the focused window lives in as
+/// `DesktopWindow`s.
+///
+/// Each tab is positioned on its title bar.
+pub fn get_tabs() -> Result<Vec<DesktopWindow>> {
+    let (root_node, properties) = get_tree()?;
+
+    let path = find_path_to_node_with_attr(&root_node, |x| x.focused)
+        .context("Couldn't find the focused window")?;
+    let container_index = path
+        .iter()
+        .rposition(|x| x.layout == NodeLayout::Tabbed || x.layout == NodeLayout::Stacked)
+        .context("The focused window isn't inside of a tabbed or stacked container")?;
+    let container = path[container_index];
+    // The tab that the focused window lives in (it might be nested in another container).
+    let focused_tab = path
+        .get(container_index + 1)
+        .context("Couldn't find the focused tab")?;
+    let workspace = path
+        .iter()
+        .rfind(|x| x.nodetype == NodeType::Workspace)
+        .and_then(|x| x.name.clone());
+    let output = path
+        .iter()
+        .rfind(|x| x.nodetype == NodeType::Output)
+        .and_then(|x| x.name.clone());
+    let is_floating = path.iter().any(|x| x.nodetype == NodeType::FloatingCon);
+
+    let mut tabs = vec![];
+    for node in &container.nodes {
+        let tab = DesktopWindow {
+            id: node.id,
+            x_window_id: node.window,
+            pos: (
+                container.rect.0 + node.deco_rect.0,
+                container.rect.1 + node.deco_rect.1,
+            ),
+            size: (node.deco_rect.2, node.deco_rect.3),
+            is_focused: node.id == focused_tab.id,
+            is_urgent: node.urgent,
+            marks: properties
+                .get(&node.id)
+                .map(|p| p.marks.clone())
+                .unwrap_or_default(),
+            class: window_class(node, &properties),
+            title: node.name.clone(),
+            is_sticky: properties.get(&node.id).is_some_and(|p| p.sticky),
+            is_fullscreen: properties.get(&node.id).is_some_and(|p| p.fullscreen),
+            is_floating,
+            pid: properties.get(&node.id).and_then(|p| p.pid),
+            workspace: workspace.clone(),
+            output: output.clone(),
+            label: None,
+            is_listed: false,
+            external_id: None,
+        };
+        debug!("Found tab {:?}", tab);
+        tabs.push(tab);
+    }
+    Ok(tabs)
+}
+
+/// Return a list of all workspaces as `DesktopWindow`s.
+///
+/// Workspaces that currently aren't visible have no place on the screen, so they'll be listed in
+/// the middle of the focused workspace instead.
+pub fn get_workspaces() -> Result<Vec<DesktopWindow>> {
+    let mut connection = I3Connection::connect().context("Couldn't acquire i3 connection")?;
+    let workspaces = connection
+        .get_workspaces()
+        .context("Problem communicating with i3")?
+        .workspaces;
+    let root_node = connection.get_tree()?;
+    let focused_rect = workspaces
+        .iter()
+        .find(|w| w.focused)
+        .context("Couldn't find the focused workspace")?
+        .rect;
+
+    let mut desktop_workspaces = vec![];
+    for workspace in &workspaces {
+        let workspace_node = find_first_node_with_attr(&root_node, |x| {
+            x.name == Some(workspace.name.clone()) && x.nodetype == NodeType::Workspace
+        })
+        .context("Couldn't find the Workspace node")?;
+        let rect = if workspace.visible {
+            workspace.rect
+        } else {
+            focused_rect
+        };
+        let desktop_workspace = DesktopWindow {
+            id: workspace_node.id,
+            x_window_id: None,
+            pos: (rect.0, rect.1),
+            size: (rect.2, rect.3),
+            is_focused: workspace.focused,
+            is_urgent: workspace.urgent,
+            marks: vec![],
+            class: None,
+            title: None,
+            is_sticky: false,
+            is_fullscreen: false,
+            is_floating: false,
+            pid: None,
+            workspace: Some(workspace.name.clone()),
+            output: Some(workspace.output.clone()),
+            label: (!workspace.visible).then(|| workspace.name.clone()),
+            is_listed: !workspace.visible,
+            external_id: None,
+        };
+        debug!("Found {:?}", desktop_workspace);
+        desktop_workspaces.push(desktop_workspace);
+    }
+    Ok(desktop_workspaces)
+}
+
+/// Workspaces named `names` that don't exist among `workspaces` yet, listed in the middle of the
+/// focused workspace like other invisible workspaces.
+pub fn empty_workspaces(workspaces: &[DesktopWindow], names: &[String]) -> Vec<DesktopWindow> {
+    let Some(focused) = workspaces.iter().find(|w| w.is_focused) else {
+        return vec![];
+    };
+    names
+        .iter()
+        .unique()
+        .filter(|name| {
+            !workspaces
+                .iter()
+                .any(|w| w.workspace.as_ref() == Some(name))
+        })
+        .enumerate()
+        // They don't have a container yet, so they get made-up ids which i3 never hands out.
+        .map(|(i, name)| DesktopWindow {
+            id: -(i as i64) - 1,
+            pos: focused.pos,
+            size: focused.size,
+            workspace: Some(name.clone()),
+            output: focused.output.clone(),
+            label: Some(name.clone()),
+            is_listed: true,
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// Split a line of an i3 config into its words, keeping double quoted ones together.
+fn config_words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            let mut word = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => word.extend(chars.next()),
+                    '"' => break,
+                    c => word.push(c),
+                }
+            }
+            words.push(word);
+        } else {
+            let word: String = iter::from_fn(|| chars.next_if(|c| !c.is_whitespace())).collect();
+            words.push(word);
+        }
+    }
+    words
+}
+
+/// Replace the config `variables` in `line`.
+fn replace_variables(line: &str, variables: &[(String, String)]) -> String {
+    variables
+        .iter()
+        .fold(line.to_string(), |line, (variable, value)| {
+            line.replace(variable, value)
+        })
+}
+
+/// The names of the workspaces that `config` assigns to outputs or has key bindings for.
+///
+/// Variables set in `config` are replaced in the names, commands chained after switching
+/// workspaces are left out.
+fn configured_workspace_names(config: &str) -> Vec<String> {
+    let mut variables: Vec<(String, String)> = vec![];
+    let mut names = vec![];
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('#') {
+            continue;
+        }
+        let words = config_words(line);
+        if words.len() > 2 && words[0] == "set" && words[1].starts_with('$') {
+            let value = replace_variables(&words[2..].join(" "), &variables);
+            variables.push((words[1].clone(), value));
+            // Longer variable names go first so that $ws10 isn't taken for $ws1 and a 0.
+            variables.sort_by_key(|(variable, _)| std::cmp::Reverse(variable.len()));
+            continue;
+        }
+        let words = config_words(&replace_variables(line, &variables));
+        let name = match words.first().map(String::as_str) {
+            Some("workspace") => {
+                let Some(end) = words.iter().position(|w| w == "output") else {
+                    continue;
+                };
+                words[1..end].join(" ")
+            }
+            Some("bindsym" | "bindcode") => {
+                let mut command = words[1..].iter().skip_while(|w| w.starts_with("--"));
+                // The key.
+                command.next();
+                if command.next().map(String::as_str) != Some("workspace") {
+                    continue;
+                }
+                let mut name = vec![];
+                for word in command.skip_while(|w| w.starts_with("--") || *w == "number") {
+                    // Another command follows.
+                    let last = word.trim_end_matches([',', ';']);
+                    if !last.is_empty() {
+                        name.push(last);
+                    }
+                    if last.len() < word.len() {
+                        break;
+                    }
+                }
+                let switches = [
+                    "next",
+                    "prev",
+                    "next_on_output",
+                    "prev_on_output",
+                    "back_and_forth",
+                ];
+                if name.len() == 1 && switches.contains(&name[0]) {
+                    continue;
+                }
+                name.join(" ")
+            }
+            _ => continue,
+        };
+        if !name.is_empty() {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// The names of the workspaces in the loaded config of i3, see `configured_workspace_names`.
+pub fn get_configured_workspace_names() -> Result<Vec<String>> {
+    let reply = send_raw_message(IPC_GET_CONFIG, "")?;
+    let mut config = reply["config"]
+        .as_str()
+        .context("i3 didn't tell its config")?
+        .to_string();
+    // i3 hands out files included by the config separately.
+    for included in reply["included_configs"].as_array().into_iter().flatten() {
+        if let Some(contents) = included["raw_contents"].as_str() {
+            config.push('\n');
+            config.push_str(contents);
+        }
+    }
+    Ok(configured_workspace_names(&config))
+}
+
+/// Return a list of all active outputs as `DesktopWindow`s.
+pub fn get_outputs() -> Result<Vec<DesktopWindow>> {
+    let mut connection = I3Connection::connect().context("Couldn't acquire i3 connection")?;
+    let outputs = connection
+        .get_outputs()
+        .context("Problem communicating with i3")?
+        .outputs;
+    let workspaces = connection
+        .get_workspaces()
+        .context("Problem communicating with i3")?
+        .workspaces;
+    let root_node = connection.get_tree()?;
+
+    let mut desktop_outputs = vec![];
+    for output in outputs.iter().filter(|o| o.active) {
+        let output_node = find_first_node_with_attr(&root_node, |x| {
+            x.name == Some(output.name.clone()) && x.nodetype == NodeType::Output
+        })
+        .context("Couldn't find the Output node")?;
+        let desktop_output = DesktopWindow {
+            id: output_node.id,
+            x_window_id: None,
+            pos: (output.rect.0, output.rect.1),
+            size: (output.rect.2, output.rect.3),
+            is_focused: workspaces
+                .iter()
+                .any(|w| w.focused && w.output == output.name),
+            is_urgent: workspaces
+                .iter()
+                .any(|w| w.urgent && w.output == output.name),
+            marks: vec![],
+            class: None,
+            title: None,
+            is_sticky: false,
+            is_fullscreen: false,
+            is_floating: false,
+            pid: None,
+            workspace: output.current_workspace.clone(),
+            output: Some(output.name.clone()),
+            label: Some(output.name.clone()),
+            is_listed: false,
+            external_id: None,
+        };
+        debug!("Found {:?}", desktop_output);
+        desktop_outputs.push(desktop_output);
+    }
+    Ok(desktop_outputs)
+}
+
+/// Quote `s` so that it can be used as a string argument in an i3 command.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Send `command_str` to i3.
+pub fn run_command(command_str: &str) -> Result<()> {
     let mut connection = I3Connection::connect().context("Couldn't acquire i3 connection")?;
-    let command_str = format!("[con_id=\"{}\"] focus", window.id);
     let command = connection
-        .run_command(&command_str)
+        .run_command(command_str)
         .context("Couldn't communicate with i3")?;
     info!("Sending to i3: {:?}", command);
     Ok(())
 }
 
-/// Focus a specific `window`.
-pub fn swap_windows(active_window: &DesktopWindow, window: &DesktopWindow) -> Result<()> {
-    let mut connection = I3Connection::connect().context("Couldn't acquire i3 connection")?;
-    let command_str = format!(
+/// The i3 command to switch to the workspace represented by `workspace`.
+pub fn focus_workspace_command(workspace: &DesktopWindow) -> Result<String> {
+    let name = workspace
+        .workspace
+        .as_deref()
+        .context("DesktopWindow has no workspace")?;
+    Ok(format!(
+        "workspace --no-auto-back-and-forth {}",
+        quote(name)
+    ))
+}
+
+/// The i3 command to focus the output represented by `output`.
+pub fn focus_output_command(output: &DesktopWindow) -> Result<String> {
+    let name = output
+        .output
+        .as_deref()
+        .context("DesktopWindow has no output")?;
+    Ok(format!("focus output {}", quote(name)))
+}
+
+/// The i3 command to move the focused workspace to the output represented by `output`.
+pub fn move_workspace_command(output: &DesktopWindow) -> Result<String> {
+    let name = output
+        .output
+        .as_deref()
+        .context("DesktopWindow has no output")?;
+    Ok(format!("move workspace to output {}", quote(name)))
+}
+
+/// The i3 command to focus a specific `window`.
+pub fn focus_window_command(window: &DesktopWindow) -> Result<String> {
+    Ok(format!("[con_id=\"{}\"] focus", window.id))
+}
+
+/// Whether `a` and `b` are the same window, even if its container changed in between.
+fn same_window(a: &DesktopWindow, b: &DesktopWindow) -> bool {
+    if a.id == b.id {
+        return true;
+    }
+    match (a.x_window_id, b.x_window_id) {
+        (Some(a), Some(b)) => a == b,
+        (None, None) => {
+            a.pid.is_some() && (&a.pid, &a.class, &a.title) == (&b.pid, &b.class, &b.title)
+        }
+        _ => false,
+    }
+}
+
+/// Whether the window manager has the focus on `window`.
+pub fn is_focused(window: &DesktopWindow) -> Result<bool> {
+    Ok(get_windows(false)?
+        .iter()
+        .any(|w| w.is_focused && same_window(w, window)))
+}
+
+/// Command to focus `window` by what it is rather than by its container, which might have been
+/// replaced since.
+///
+/// X windows are focused by their id, others by their app_id and title or by their pid.
+pub fn focus_criteria_command(window: &DesktopWindow) -> Option<String> {
+    let exactly = |s: &str| quote(&format!("^{}$", regex::escape(s)));
+    match (window.x_window_id, &window.class, &window.title, window.pid) {
+        (Some(x_window_id), ..) => Some(focus_x_window_command(x_window_id as u32)),
+        (None, Some(app_id), Some(title), _) => Some(format!(
+            "[app_id={} title={}] focus",
+            exactly(app_id),
+            exactly(title)
+        )),
+        (None, _, _, Some(pid)) => Some(format!("[pid={pid}] focus")),
+        _ => None,
+    }
+}
+
+/// Command to focus the X window `x_window_id`.
+pub fn focus_x_window_command(x_window_id: u32) -> String {
+    format!("[id={x_window_id}] focus")
+}
+
+/// The name of the binding mode i3 is in.
+pub fn get_binding_mode() -> Result<String> {
+    let reply = send_raw_message(IPC_GET_BINDING_STATE, "")?;
+    reply["name"]
+        .as_str()
+        .map(str::to_string)
+        .context("i3 didn't tell its binding mode")
+}
+
+/// The i3 command to switch to the binding mode `mode`.
+pub fn binding_mode_command(mode: &str) -> String {
+    format!("mode {}", quote(mode))
+}
+
+/// The i3 command to focus `window` and split it so that the next window opens next to it,
+/// `vertical`ly (below) or horizontally (to the right).
+///
+/// i3 always opens new windows after the focused one, so there's no splitting for left or above.
+pub fn split_command(window: &DesktopWindow, vertical: bool) -> String {
+    let orientation = if vertical { "vertical" } else { "horizontal" };
+    format!("[con_id=\"{}\"] focus, split {orientation}", window.id)
+}
+
+/// The i3 command to move the floating `window` to `pos` on the whole screen.
+pub fn move_position_command(window: &DesktopWindow, pos: (i32, i32)) -> String {
+    format!(
+        "[con_id=\"{}\"] move absolute position {} px {} px",
+        window.id, pos.0, pos.1
+    )
+}
+
+/// Mark that the windows put into a group are moved to, see `group_command`.
+const GROUP_MARK: &str = "_wmfocus_group";
+
+/// The i3 command to put `windows` into a new tabbed (or `stacked`) container in place of the first
+/// of them, which ends up focused.
+pub fn group_command(windows: &[DesktopWindow], stacked: bool) -> Result<String> {
+    let (first, others) = windows
+        .split_first()
+        .context("There are no windows to group")?;
+    let layout = if stacked { "stacking" } else { "tabbed" };
+    let mut commands = vec![format!(
+        "[con_id=\"{}\"] mark --add {GROUP_MARK}, split vertical, layout {layout}",
+        first.id
+    )];
+    commands.extend(others.iter().map(|window| {
+        format!(
+            "[con_id=\"{}\"] move container to mark {GROUP_MARK}",
+            window.id
+        )
+    }));
+    commands.push(format!(
+        "[con_id=\"{}\"] unmark {GROUP_MARK}, focus",
+        first.id
+    ));
+    Ok(commands.join("; "))
+}
+
+/// The i3 command to swap `active_window` with `window`.
+pub fn swap_windows_command(
+    active_window: &DesktopWindow,
+    window: &DesktopWindow,
+) -> Result<String> {
+    Ok(format!(
         "[con_id=\"{}\"] swap with container con_id {}",
         active_window.id, window.id
-    );
-    let command = connection
-        .run_command(&command_str)
-        .context("Couldn't communicate with i3")?;
-    info!("Sending to i3: {:?}", command);
-    Ok(())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `(id, pos, size)` of every window on the workspace of a captured tree.
+    fn fixture_geometry(fixture: &str) -> Vec<(i64, (i32, i32), (i32, i32))> {
+        let root_node = node_from_json(&serde_json::from_str(fixture).unwrap());
+        let workspace = Workspace {
+            num: 1,
+            name: "1".to_string(),
+            visible: true,
+            focused: true,
+            urgent: false,
+            rect: (0, 0, 1920, 1080),
+            output: "eDP-1".to_string(),
+        };
+        let mut windows = crawl_windows(&root_node, &workspace, &HashMap::new(), false).unwrap();
+        windows.sort_by_key(|w| w.id);
+        windows.iter().map(|w| (w.id, w.pos, w.size)).collect()
+    }
+
+    #[test]
+    fn test_window_geometry_gaps() {
+        // Gaps of 10 pixels with titles and 2 pixel borders, the right side is stacked.
+        assert_eq!(
+            fixture_geometry(include_str!("../tests/fixtures/i3/gaps.json")),
+            vec![
+                (11, (22, 42), (926, 1016)),
+                (13, (970, 20), (930, 1038)),
+                (14, (970, 42), (930, 1016)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_window_geometry_no_borders() {
+        // `default_border pixel 0` and `hide_edge_borders`, two tabs and a floating window.
+        assert_eq!(
+            fixture_geometry(include_str!("../tests/fixtures/i3/no-borders.json")),
+            vec![
+                (12, (0, 0), (960, 1080)),
+                (13, (960, 0), (960, 1080)),
+                (21, (560, 240), (800, 600)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_node_from_json() {
+        let tree = serde_json::json!({
+            "id": 1,
+            "type": "con",
+            "layout": "tabbed",
+            "border": "pixel",
+            "window": 4194307,
+            "window_properties": {"class": "Firefox", "instance": "Navigator", "machine": "box"},
+            "focus": [2],
+            "nodes": [{"id": 2, "type": "floating_con", "urgent": true}],
+        });
+        let node = node_from_json(&tree);
+        assert_eq!(node.id, 1);
+        assert_eq!(node.nodetype, NodeType::Con);
+        assert_eq!(node.layout, NodeLayout::Tabbed);
+        assert_eq!(node.border, NodeBorder::Pixel);
+        assert_eq!(node.window, Some(4194307));
+        assert_eq!(node.focus, vec![2]);
+        let properties = node.window_properties.unwrap();
+        assert_eq!(properties.len(), 2);
+        assert_eq!(properties[&WindowProperty::Instance], "Navigator");
+        // Sway leaves out what doesn't apply to native Wayland windows.
+        let child = &node.nodes[0];
+        assert_eq!(child.nodetype, NodeType::FloatingCon);
+        assert!(child.urgent);
+        assert_eq!(child.window, None);
+        assert_eq!(child.window_properties, None);
+        assert_eq!(child.rect, (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_collect_node_properties() {
+        let tree = serde_json::json!({
+            "id": 1,
+            "marks": [],
+            "nodes": [
+                {"id": 2, "marks": ["music"], "app_id": "foot", "fullscreen_mode": 1, "nodes": [], "floating_nodes": []},
+            ],
+            "floating_nodes": [
+                {"id": 3, "marks": ["a", "b"], "sticky": true, "nodes": [], "floating_nodes": []},
+            ],
+        });
+        let mut properties = HashMap::new();
+        collect_node_properties(&tree, &mut properties);
+
+        assert_eq!(properties.len(), 3);
+        assert!(properties[&1].marks.is_empty());
+        assert_eq!(properties[&2].app_id.as_deref(), Some("foot"));
+        assert_eq!(properties[&3].app_id, None);
+        assert!(!properties[&2].sticky);
+        assert!(properties[&3].sticky);
+        assert!(properties[&2].fullscreen);
+        assert!(!properties[&3].fullscreen);
+        assert_eq!(properties[&2].marks, vec!["music"]);
+        assert_eq!(properties[&3].marks, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_parse_desktop_outputs() {
+        let reply = serde_json::json!([
+            {
+                "name": "eDP-1",
+                "active": true,
+                "rect": {"x": 0, "y": 0, "width": 1920, "height": 1080},
+            },
+            {
+                "name": "DP-1",
+                "active": true,
+                "transform": "90",
+                "scale": 1.5,
+                "rect": {"x": 1920, "y": 0, "width": 1080, "height": 1920},
+            },
+            {
+                "name": "DP-2",
+                "active": false,
+                "rect": {"x": 0, "y": 0, "width": 0, "height": 0},
+            },
+        ]);
+        let outputs = parse_desktop_outputs(&reply);
+
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(outputs[0].name, "eDP-1");
+        assert_eq!(outputs[0].transform, Transform::Normal);
+        assert_eq!(outputs[1].rect, (1920, 0, 1080, 1920));
+        assert_eq!(outputs[1].transform, Transform::Rotate90);
+        assert_eq!(outputs[0].scale, 1.0);
+        assert_eq!(outputs[1].scale, 1.5);
+    }
+
+    #[test]
+    fn test_commands() {
+        let window = DesktopWindow {
+            id: 94,
+            workspace: Some("2: web".to_string()),
+            output: Some("DP-1".to_string()),
+            ..Default::default()
+        };
+        let active = DesktopWindow {
+            id: 17,
+            ..Default::default()
+        };
+        assert_eq!(
+            focus_window_command(&window).unwrap(),
+            "[con_id=\"94\"] focus"
+        );
+        assert_eq!(
+            swap_windows_command(&active, &window).unwrap(),
+            "[con_id=\"17\"] swap with container con_id 94"
+        );
+        assert_eq!(
+            focus_workspace_command(&window).unwrap(),
+            "workspace --no-auto-back-and-forth \"2: web\""
+        );
+        assert_eq!(
+            focus_output_command(&window).unwrap(),
+            "focus output \"DP-1\""
+        );
+        assert!(focus_output_command(&active).is_err());
+        assert_eq!(
+            move_workspace_command(&window).unwrap(),
+            "move workspace to output \"DP-1\""
+        );
+        assert_eq!(focus_x_window_command(0x1a00003), "[id=27262979] focus");
+        assert_eq!(binding_mode_command("wmfocus"), "mode \"wmfocus\"");
+        assert_eq!(
+            split_command(&window, true),
+            "[con_id=\"94\"] focus, split vertical"
+        );
+        assert_eq!(
+            move_position_command(&window, (-20, 300)),
+            "[con_id=\"94\"] move absolute position -20 px 300 px"
+        );
+        assert_eq!(
+            group_command(&[window.clone(), active.clone()], false).unwrap(),
+            "[con_id=\"94\"] mark --add _wmfocus_group, split vertical, layout tabbed; \
+             [con_id=\"17\"] move container to mark _wmfocus_group; \
+             [con_id=\"94\"] unmark _wmfocus_group, focus"
+        );
+        assert!(group_command(&[], true).is_err());
+    }
+
+    #[test]
+    fn test_configured_workspace_names() {
+        let config = r#"
+set $mod Mod4
+set $ws1 "1: web"
+set $ws10 10
+workspace $ws1 output DP-1
+# bindsym $mod+0 workspace commented
+bindsym $mod+1 workspace number $ws1
+bindsym $mod+0 workspace $ws10
+bindsym --release $mod+m workspace --no-auto-back-and-forth mail; exec thunderbird
+bindsym $mod+Tab workspace back_and_forth
+bindsym $mod+Shift+1 move container to workspace number $ws1
+"#;
+        assert_eq!(
+            configured_workspace_names(config),
+            vec!["1: web", "1: web", "10", "mail"]
+        );
+        assert_eq!(
+            config_words(r#"workspace "a \"b\"" output  HDMI-1"#),
+            vec!["workspace", "a \"b\"", "output", "HDMI-1"]
+        );
+    }
+
+    #[test]
+    fn test_empty_workspaces() {
+        let workspaces = [
+            DesktopWindow {
+                id: 3,
+                pos: (0, 24),
+                size: (1920, 1056),
+                is_focused: true,
+                workspace: Some("1: web".to_string()),
+                ..Default::default()
+            },
+            DesktopWindow {
+                id: 4,
+                workspace: Some("2".to_string()),
+                ..Default::default()
+            },
+        ];
+        let names = ["2", "mail", "1: web", "mail"].map(String::from);
+        let empty = empty_workspaces(&workspaces, &names);
+        assert_eq!(empty.len(), 1);
+        assert_eq!(empty[0].workspace.as_deref(), Some("mail"));
+        assert_eq!(empty[0].pos, (0, 24));
+        assert!(empty[0].id < 0 && empty[0].is_listed);
+        assert!(empty_workspaces(&[], &names).is_empty());
+    }
+
+    #[test]
+    fn test_focus_criteria() {
+        let native = DesktopWindow {
+            id: 5,
+            class: Some("org.gnome.Nautilus".to_string()),
+            title: Some("Files (2)".to_string()),
+            pid: Some(4242),
+            ..Default::default()
+        };
+        assert_eq!(
+            focus_criteria_command(&native).as_deref(),
+            Some(r#"[app_id="^org\\.gnome\\.Nautilus$" title="^Files \\(2\\)$"] focus"#)
+        );
+        let untitled = DesktopWindow {
+            title: None,
+            ..native.clone()
+        };
+        assert_eq!(
+            focus_criteria_command(&untitled).as_deref(),
+            Some("[pid=4242] focus")
+        );
+        let x_window = DesktopWindow {
+            x_window_id: Some(0x1a00003),
+            ..native.clone()
+        };
+        assert_eq!(
+            focus_criteria_command(&x_window).as_deref(),
+            Some("[id=27262979] focus")
+        );
+        assert_eq!(focus_criteria_command(&DesktopWindow::default()), None);
+
+        // The container of a window might have been replaced in the meantime.
+        let moved = DesktopWindow {
+            id: 6,
+            ..native.clone()
+        };
+        assert!(same_window(&native, &moved));
+        assert!(!same_window(&moved, &untitled));
+        assert!(same_window(
+            &x_window,
+            &DesktopWindow {
+                id: 7,
+                ..x_window.clone()
+            }
+        ));
+        assert!(!same_window(&x_window, &moved));
+    }
+
+    #[test]
+    fn test_window_events() {
+        let (i3, stream) = UnixStream::pair().unwrap();
+        let events = WindowEvents { stream };
+        write_message(&i3, IPC_EVENT_WINDOW, r#"{"change":"title"}"#).unwrap();
+        write_message(&i3, IPC_EVENT_WINDOW, r#"{"change":"new"}"#).unwrap();
+        write_message(&i3, 0x8000_0000, r#"{"change":"close"}"#).unwrap();
+        write_message(&i3, IPC_EVENT_WINDOW, r#"{"change":"close"}"#).unwrap();
+        assert!(!events.changed().unwrap());
+        assert!(events.changed().unwrap());
+        assert!(!events.changed().unwrap());
+        assert!(events.changed().unwrap());
+        drop(i3);
+        assert!(events.changed().is_err());
+    }
+
+    #[test]
+    fn test_drain_events() {
+        let (i3, stream) = UnixStream::pair().unwrap();
+        assert!(!drain_events(&stream).unwrap());
+        write_message(&i3, IPC_EVENT_WINDOW, r#"{"change":"title"}"#).unwrap();
+        write_message(&i3, 0x8000_0000, r#"{"change":"focus"}"#).unwrap();
+        assert!(drain_events(&stream).unwrap());
+        assert!(!drain_events(&stream).unwrap());
+        // Resizing through a key binding changes the windows, starting a program doesn't (yet).
+        let binding =
+            |command: &str| serde_json::json!({"binding": {"command": command}}).to_string();
+        write_message(&i3, IPC_EVENT_BINDING, &binding("exec wmfocus")).unwrap();
+        assert!(!drain_events(&stream).unwrap());
+        write_message(&i3, IPC_EVENT_BINDING, &binding("resize grow width 10 px")).unwrap();
+        assert!(drain_events(&stream).unwrap());
+        // i3 going away (eg for a restart) makes the windows unknowable.
+        drop(i3);
+        assert!(drain_events(&stream).is_err());
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("1: www"), r#""1: www""#);
+        assert_eq!(quote(r#"say "hi" \o/"#), r#""say \"hi\" \\o/""#);
+    }
 }